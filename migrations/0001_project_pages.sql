@@ -0,0 +1,22 @@
+-- Pages of a project. Every project owns at least one page; the board data of
+-- each page lives in its own Mongo document keyed by (project_id, page_id).
+CREATE TABLE IF NOT EXISTS project_pages (
+    id BIGSERIAL PRIMARY KEY,
+    project_id BIGINT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS project_pages_project_id_position_idx
+    ON project_pages (project_id, position);
+
+-- Give every existing project its first page. The board stored before pages
+-- existed is adopted by this page the first time it is loaded.
+INSERT INTO project_pages (project_id, name, position)
+SELECT projects.id, 'Page 1', 0
+FROM projects
+WHERE NOT EXISTS (
+    SELECT 1 FROM project_pages WHERE project_pages.project_id = projects.id
+);
@@ -0,0 +1,22 @@
+-- Text elements of every page, copied from the boards in MongoDB so that
+-- they can be searched with the project names. Rows are rewritten a few
+-- seconds after a page changes.
+CREATE TABLE IF NOT EXISTS board_texts (
+    project_id BIGINT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    page_id BIGINT NOT NULL REFERENCES project_pages(id) ON DELETE CASCADE,
+    element_id VARCHAR(255) NOT NULL,
+    text TEXT NOT NULL,
+    x REAL NOT NULL,
+    y REAL NOT NULL,
+    document TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', text)) STORED,
+    PRIMARY KEY (page_id, element_id)
+);
+
+CREATE INDEX IF NOT EXISTS board_texts_document_idx
+    ON board_texts USING GIN (document);
+
+CREATE INDEX IF NOT EXISTS board_texts_project_id_idx
+    ON board_texts (project_id);
+
+CREATE INDEX IF NOT EXISTS projects_name_document_idx
+    ON projects USING GIN (to_tsvector('simple', name));
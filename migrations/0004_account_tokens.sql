@@ -0,0 +1,15 @@
+-- Single-use tokens mailed to users to verify their email address or reset
+-- their password. Only a SHA-256 hash of each token is stored. Issuing a new
+-- token of a purpose invalidates the unused ones of the same user.
+CREATE TABLE IF NOT EXISTS account_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose VARCHAR(32) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS account_tokens_user_id_idx
+    ON account_tokens (user_id, purpose);
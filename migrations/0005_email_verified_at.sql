@@ -0,0 +1,7 @@
+-- When the email address of an account was verified. Accounts can't log in
+-- before it is set. `is_active` stays the switch for disabling an account.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ;
+
+-- Accounts active so far were created before verification was required
+UPDATE users SET email_verified_at = date_joined
+WHERE is_active AND email_verified_at IS NULL;
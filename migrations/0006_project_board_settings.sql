@@ -0,0 +1,3 @@
+-- Board settings shared by every page of a project. NULL until the owner
+-- changes them, the defaults apply until then.
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS board_settings JSONB;
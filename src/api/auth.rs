@@ -1,4 +1,5 @@
 use axum::{
+    body::Bytes,
     extract::{FromRequestParts, State},
     http::{request::Parts, StatusCode},
     response::{IntoResponse, Response},
@@ -18,6 +19,7 @@ use std::sync::LazyLock;
 
 use super::common::AppState;
 use crate::user;
+use crate::user::token::{random_hex, RefreshError, RefreshToken, RevocationList};
 
 
 
@@ -27,6 +29,16 @@ static KEYS: LazyLock<Keys> = LazyLock::new(|| {
     Keys::new(secret.as_bytes())
 });
 
+/// `AUTH_ACCESS_TOKEN_TTL`, seconds an access token is valid, default 15
+/// minutes. Clients get a new one from their refresh token.
+static ACCESS_TOKEN_TTL: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("AUTH_ACCESS_TOKEN_TTL").ok().and_then(|v| v.parse().ok()).unwrap_or(15 * 60)
+});
+
+/// Seconds a token is still accepted after it expired, to allow for clock
+/// skew. Revocations have to outlive a token by as much.
+const EXPIRY_LEEWAY: i64 = 60;
+
 struct Keys {
     encoding: EncodingKey,
     decoding: DecodingKey,
@@ -45,6 +57,13 @@ impl Keys {
 pub struct Claims {
     user_id: i64,
     exp: usize,
+    iat: usize,
+    /// Milliseconds since the Unix epoch, compared to "log out all
+    /// sessions". Tokens issued before it existed fall back to `iat`.
+    #[serde(default)]
+    iat_ms: Option<i64>,
+    /// Unique id of the token, used to revoke it on logout.
+    jti: String,
 }
 
 impl Display for Claims {
@@ -53,50 +72,92 @@ impl Display for Claims {
     }
 }
 
-impl<S> FromRequestParts<S> for Claims
-where
-    S: Send + Sync,
-{
+impl FromRequestParts<AppState> for Claims {
     type Rejection = AuthError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
         // Extract the token from the authorization header
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| AuthError::InvalidToken)?;
         
-        return validate_token(bearer.token());
+        return authenticate_token(bearer.token(), state).await;
     }
 }
 
 pub fn validate_token(token: &str) -> Result<Claims, AuthError>{
     // Decode the user data
-    let token_data = decode::<Claims>(token, &KEYS.decoding, &Validation::default())
+    let mut validation = Validation::default();
+    validation.leeway = EXPIRY_LEEWAY as u64;
+    let token_data = decode::<Claims>(token, &KEYS.decoding, &validation)
     .map_err(|_| AuthError::InvalidToken)?;
 
     Ok(token_data.claims)
 }
 
+/// Validates a token and makes sure it was not revoked.
+pub async fn authenticate_token(token: &str, state: &AppState) -> Result<Claims, AuthError> {
+    let claims = validate_token(token)?;
+    if claims.is_revoked(state).await {
+        return Err(AuthError::InvalidToken);
+    }
+    Ok(claims)
+}
+
 impl Claims {
     pub fn get_user_id(&self) -> i64 {
         return self.user_id;
     }
+
+    pub fn get_jti(&self) -> &str {
+        return &self.jti;
+    }
+
+    /// Claims of a fresh token of the user, without signing it.
+    #[cfg(test)]
+    pub fn for_user(user_id: i64) -> Self {
+        return Self::new(user_id);
+    }
+
+    fn new(user_id: i64) -> Self {
+        let now = chrono::Utc::now();
+        return Self {
+            user_id,
+            exp: (now.timestamp() + *ACCESS_TOKEN_TTL) as usize,
+            iat: now.timestamp() as usize,
+            iat_ms: Some(now.timestamp_millis()),
+            jti: random_hex(16),
+        };
+    }
+
+    /// Whether the token was revoked by a logout. Tokens are treated as
+    /// revoked when the revocation list can't be read.
+    pub async fn is_revoked(&self, state: &AppState) -> bool {
+        let revocations = RevocationList::new(state.redis_client.clone());
+        let issued_at_ms = self.iat_ms.unwrap_or(self.iat as i64 * 1000);
+        return revocations.is_revoked(&self.jti, self.user_id, issued_at_ms).await.unwrap_or(true);
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct AuthBody {
     access: String,
+    refresh: String,
     username: String,
     token_type: String,
+    /// Seconds until `access` expires.
+    expires_in: i64,
 }
 
 impl AuthBody {
-    fn new(access: String, username:String) -> Self {
+    fn new(access: String, refresh: String, username:String) -> Self {
         Self {
             access,
+            refresh,
             username,
             token_type: "Bearer".to_string(),
+            expires_in: *ACCESS_TOKEN_TTL,
         }
     }
 }
@@ -107,12 +168,27 @@ pub struct AuthPayload {
     password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RefreshPayload {
+    refresh: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutPayload {
+    /// Refresh token of the session, revoked with its family.
+    refresh: Option<String>,
+}
+
 #[derive(Debug)]
 pub enum AuthError {
     WrongCredentials,
     MissingCredentials,
     TokenCreation,
     InvalidToken,
+    InvalidRefreshToken,
+    RevocationFailed,
+    InactiveAccount,
+    EmailNotVerified,
 }
 
 
@@ -123,6 +199,10 @@ impl IntoResponse for AuthError {
             AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
             AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
             AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
+            AuthError::InvalidRefreshToken => (StatusCode::UNAUTHORIZED, "Invalid or expired refresh token"),
+            AuthError::RevocationFailed => (StatusCode::INTERNAL_SERVER_ERROR, "Token revocation error"),
+            AuthError::InactiveAccount => (StatusCode::FORBIDDEN, "Account is disabled"),
+            AuthError::EmailNotVerified => (StatusCode::FORBIDDEN, "Email address is not verified yet"),
         };
         let body = Json(json!({
             "error": error_message,
@@ -133,7 +213,15 @@ impl IntoResponse for AuthError {
 
 
 
-pub async fn authorize( 
+/// Signs an access token for the user that expires after `ACCESS_TOKEN_TTL`.
+fn create_access_token(user_id: i64) -> Result<String, AuthError> {
+    let claims = Claims::new(user_id);
+    return encode(&Header::default(), &claims, &KEYS.encoding)
+        .map_err(|_| AuthError::TokenCreation);
+}
+
+
+pub async fn authorize(
     State(state): State<AppState>,
     Json(payload): Json<AuthPayload>,
 ) -> Result<Json<AuthBody>, AuthError> {
@@ -147,17 +235,104 @@ pub async fn authorize(
         return Err(AuthError::WrongCredentials);
     }
 
-    let user = user.unwrap();
-    let claims = Claims {
-        user_id: user.get_id().unwrap(),
-        // Mandatory expiry time as UTC timestamp
-        exp: 2000000000, // May 2033
-    };
-    // Create the authorization token
-    let token = encode(&Header::default(), &claims, &KEYS.encoding)
+    let mut user = user.unwrap();
+    if !user.is_active() {
+        return Err(AuthError::InactiveAccount);
+    }
+    let verified_at = user.get_email_verified_at(&state.pg_pool).await
         .map_err(|_| AuthError::TokenCreation)?;
+    if verified_at.is_none() {
+        return Err(AuthError::EmailNotVerified);
+    }
+    if let Err(e) = user.record_login(&state.pg_pool).await {
+        println!("Failed to record login of user {}: {}", user.get_username(), e);
+    }
+    let user_id = user.get_id().unwrap();
+    let access = create_access_token(user_id)?;
+    let refresh = RefreshToken::issue(&state.pg_pool, user_id).await
+        .map_err(|_| AuthError::TokenCreation)?;
+
+    // Send the authorized tokens
+    Ok(Json(AuthBody::new(access, refresh, user.get_username().clone())))
+}
+
+
+/// Trades a refresh token for a new access token and a new refresh token.
+/// Each refresh token works once.
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshPayload>,
+) -> Result<Json<AuthBody>, AuthError> {
+    if payload.refresh.is_empty() {
+        return Err(AuthError::MissingCredentials);
+    }
 
-    // Send the authorized token
-    Ok(Json(AuthBody::new(token, user.get_username().clone())))
+    let (user_id, refresh) = RefreshToken::rotate(&state.pg_pool, &payload.refresh).await
+        .map_err(|e| match e {
+            RefreshError::Invalid | RefreshError::Reused => AuthError::InvalidRefreshToken,
+            RefreshError::Database(e) => {
+                println!("Failed to rotate refresh token: {}", e);
+                AuthError::TokenCreation
+            }
+        })?;
+
+    let user = user::User::get_by_id(&state.pg_pool, user_id).await
+        .map_err(|_| AuthError::TokenCreation)?;
+    let user = user.ok_or(AuthError::InvalidRefreshToken)?;
+    if !user.is_active() {
+        return Err(AuthError::InactiveAccount);
+    }
+    let access = create_access_token(user_id)?;
+
+    Ok(Json(AuthBody::new(access, refresh, user.get_username().clone())))
 }
 
+
+/// Revokes the access token of the request and, when given, the refresh
+/// token of the same session. WebSocket connections opened with the token
+/// are closed within a few seconds.
+pub async fn logout(
+    claims: Claims,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<StatusCode, AuthError> {
+    // The body is optional
+    let payload: Option<LogoutPayload> = if body.is_empty() {
+        None
+    } else {
+        Some(serde_json::from_slice(&body).map_err(|_| AuthError::MissingCredentials)?)
+    };
+
+    let ttl = claims.exp as i64 - chrono::Utc::now().timestamp() + EXPIRY_LEEWAY;
+    RevocationList::new(state.redis_client.clone()).revoke_token(&claims.jti, ttl).await
+        .map_err(|_| AuthError::RevocationFailed)?;
+
+    if let Some(refresh) = payload.and_then(|payload| payload.refresh) {
+        RefreshToken::revoke_family(&state.pg_pool, claims.user_id, &refresh).await
+            .map_err(|_| AuthError::RevocationFailed)?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Revokes every access and refresh token of the caller, on every device,
+/// and closes their WebSocket connections.
+pub async fn logout_all(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AuthError> {
+    revoke_sessions(&state, claims.user_id, None).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Revokes every access and refresh token issued to the user so far,
+/// except the access token with the `keep` jti.
+pub async fn revoke_sessions(state: &AppState, user_id: i64, keep: Option<&str>) -> Result<(), AuthError> {
+    let ttl = *ACCESS_TOKEN_TTL + EXPIRY_LEEWAY;
+    RevocationList::new(state.redis_client.clone()).revoke_user(user_id, ttl, keep).await
+        .map_err(|_| AuthError::RevocationFailed)?;
+    RefreshToken::revoke_user(&state.pg_pool, user_id).await
+        .map_err(|_| AuthError::RevocationFailed)?;
+    Ok(())
+}
@@ -6,8 +6,10 @@ use axum::{
 use std::{collections::HashMap, sync::Arc};
 use redis::Client as RedisClient;
 use mongodb::Client as MongoClient;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, RwLock};
 use axum::extract::ws::Message;
+use crate::whiteboard::live::LiveBoard;
+use crate::user::mailer::Mailer;
 
 // Represents a channel to send messages to a WebSocket client
 // Each client connection will have one such sender
@@ -17,12 +19,23 @@ pub type ClientTx = mpsc::UnboundedSender<Message>;
 // This allows broadcasting messages to all clients in a group
 pub type Groups = Arc<RwLock<HashMap<i64, Vec<ClientTx>>>>;
 
+// Boards of the pages that local clients are working on, keyed by
+// (project id, page id). Kept up to date by the Redis subscriber.
+pub type LiveBoards = Arc<RwLock<HashMap<(i64, i64), LiveBoard>>>;
+
+// Ids of projects whose collaborators changed or that were deleted, on any
+// node. Connections of those projects check their access again.
+pub type PermissionChanges = broadcast::Sender<i64>;
+
 #[derive(Clone)]
 pub struct AppState {
     pub pg_pool: Arc<PgPool>,
     pub redis_client: Arc<RedisClient>,
     pub mongo_client: Arc<MongoClient>,
     pub ws_groups: Groups,
+    pub live_boards: LiveBoards,
+    pub permission_changes: PermissionChanges,
+    pub mailer: Arc<dyn Mailer>,
 
 }
 
@@ -54,4 +67,43 @@ where
     E: std::error::Error,
 {
     (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
\ No newline at end of file
+}
+
+
+/// App state connected to the databases configured in `.env`, for tests
+/// that go through the views.
+#[cfg(test)]
+pub async fn get_test_state() -> AppState {
+    dotenv::dotenv().ok();
+    return crate::create_app_state().await.expect("Failed to connect to the databases");
+}
+
+/// Creates an active user with a unique username and returns its id.
+#[cfg(test)]
+pub async fn create_test_user(state: &AppState, password: &str) -> i64 {
+    let username = format!("test_{}", crate::user::token::random_hex(6));
+    let mut user = crate::user::User::create_new(
+        username.clone(),
+        password.to_string(),
+        "Test".to_string(),
+        "User".to_string(),
+        format!("{}@example.com", username),
+        false,
+        true,
+        false,
+    );
+    user.create_row(&state.pg_pool).await.unwrap();
+    return user.get_id().unwrap();
+}
+
+/// Creates a project of the owner with its first page and returns both ids.
+#[cfg(test)]
+pub async fn create_test_project(state: &AppState, owner_id: i64) -> (i64, i64) {
+    let mut project = crate::project::Project::create_new("Test project".to_string(), owner_id);
+    project.create_row(&state.pg_pool).await.unwrap();
+    let project_id = project.get_id().unwrap();
+
+    let mut page = crate::project::page::Page::create_new(project_id, "Page 1".to_string(), 0);
+    page.create_row(&state.pg_pool).await.unwrap();
+    return (project_id, page.get_id().unwrap());
+}
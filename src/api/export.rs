@@ -0,0 +1,642 @@
+use chrono::Utc;
+use crate::project::Project;
+use crate::project::page::Page;
+use crate::project::search::index_page;
+use crate::whiteboard::WhiteBoardData;
+use crate::whiteboard::archive::Archive;
+use crate::whiteboard::diff::diff_boards;
+use crate::whiteboard::import::{ import as import_drawing, ImportFormat, ImportSummary, MAX_NAME_LENGTH };
+use crate::whiteboard::settings::BoardSettings;
+use crate::whiteboard::storage::oplog::OpLog;
+use crate::whiteboard::timelapse::{ is_frame, BoardOp, Replay };
+use crate::whiteboard::validation::LIMITS;
+use crate::whiteboard::storage::WhiteBoardStorage;
+use crate::whiteboard::storage::mongo::MongoDBStorage;
+use crate::whiteboard::storage::thumbnail::{ ThumbnailStore, THUMBNAIL_DELAY };
+use crate::whiteboard::export::{
+    ExportArea,
+    svg::{ render_animated_svg, render_diff_svg, render_svg },
+    png::{ render_animated_png, render_png, render_thumbnail, TEXTS_NOT_DRAWN },
+    pdf::{ render_pdf, Orientation, PageSize, PdfMetadata, PdfOptions, PdfPage },
+};
+use serde::{ Deserialize, Serialize };
+use futures::TryStreamExt;
+use super::common::AppState;
+use super::auth::Claims;
+use super::page::{ get_page_storage, get_project_page, load_page_versions, storage_error, PageError };
+use super::project::{ permissions, ProjectOutput };
+use axum::{
+    body::Bytes,
+    extract::{State, Path, Query},
+    http::{ header, HeaderValue },
+    response::{IntoResponse, Response},
+    Json,
+};
+
+
+/// Header with what an export had to leave out.
+const EXPORT_WARNING_HEADER: &str = "x-export-warning";
+
+fn png_response(png: Vec<u8>, has_texts: bool) -> Response {
+    let mut response = ([(header::CONTENT_TYPE, "image/png")], png).into_response();
+    if has_texts {
+        response.headers_mut().insert(EXPORT_WARNING_HEADER, HeaderValue::from_static(TEXTS_NOT_DRAWN));
+    }
+    return response;
+}
+
+
+/// Which page to export and which part of it. Without a full viewport the
+/// export is cropped to the drawn content, grown by `padding`.
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    page_id: Option<i64>,
+    x: Option<f32>,
+    y: Option<f32>,
+    width: Option<f32>,
+    height: Option<f32>,
+    padding: Option<f32>,
+    /// Pixels per board unit, PNG only.
+    scale: Option<f32>,
+    /// Overrides the page background; `transparent` leaves it out.
+    background: Option<String>,
+}
+
+/// Which pages go into a PDF and how they are laid out. Without `page_id`
+/// every page of the project is exported, in order.
+#[derive(Debug, Deserialize)]
+pub struct PdfExportQuery {
+    page_id: Option<i64>,
+    /// One PDF page per frame instead of per board page.
+    #[serde(default)]
+    frames: bool,
+    #[serde(default)]
+    page_size: PageSize,
+    #[serde(default)]
+    orientation: Orientation,
+    /// Millimeters, default 10.
+    margin: Option<f32>,
+    /// Default true.
+    fit: Option<bool>,
+    padding: Option<f32>,
+    background: Option<String>,
+}
+
+/// Which page to replay into a timelapse and how. Without `frame` the PNG
+/// export is animated.
+#[derive(Debug, Deserialize)]
+pub struct TimelapseQuery {
+    page_id: Option<i64>,
+    /// Number of frames, default 60.
+    frames: Option<usize>,
+    /// Milliseconds each frame is shown, default 100.
+    frame_delay: Option<u16>,
+    padding: Option<f32>,
+    /// Pixels per board unit, PNG only.
+    scale: Option<f32>,
+    background: Option<String>,
+    /// PNG only: returns this frame alone as a still image, counting from 0.
+    frame: Option<usize>,
+}
+
+/// Two versions of a page to draw over each other, see `DiffQuery`.
+#[derive(Debug, Deserialize)]
+pub struct DiffExportQuery {
+    from: i64,
+    to: Option<i64>,
+    padding: Option<f32>,
+    background: Option<String>,
+}
+
+/// Largest file accepted by the import endpoints, in bytes.
+pub const MAX_ARCHIVE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Upper bound on the pages of one PDF export.
+const MAX_PDF_PAGES: usize = 200;
+
+/// Upper bound on the frames of one timelapse.
+const MAX_TIMELAPSE_FRAMES: usize = 120;
+
+impl ExportQuery {
+    fn get_viewport(&self) -> Option<ExportArea> {
+        match (self.x, self.y, self.width, self.height) {
+            (Some(x), Some(y), Some(width), Some(height)) => Some(ExportArea::new(x, y, width, height)),
+            _ => None,
+        }
+    }
+}
+
+
+/// Resolves the requested page, defaulting to the first page of the project.
+async fn get_export_page_id(project_id: i64, page_id: Option<i64>, state: &AppState) -> Result<i64, PageError> {
+    if let Some(page_id) = page_id {
+        get_project_page(project_id, page_id, state).await?;
+        return Ok(page_id);
+    }
+
+    let page = Page::get_first_page(&state.pg_pool, project_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+    return page.and_then(|p| p.get_id()).ok_or(PageError::NotFound);
+}
+
+/// The requested background, none for `transparent`, or else `board`.
+fn choose_background(requested: Option<&str>, board: &str) -> Option<String> {
+    match requested {
+        Some("transparent") => None,
+        Some(background) => Some(background.to_string()),
+        None => Some(board.to_string()),
+    }
+}
+
+async fn get_export_background(project_id: i64, requested: Option<&str>, state: &AppState) -> Result<Option<String>, PageError> {
+    let settings = Project::get_board_settings(&state.pg_pool, project_id).await?;
+    return Ok(choose_background(requested, settings.get_background()));
+}
+
+/// Loads the board to export with the area and background to render.
+async fn load_export(
+    project_id: i64,
+    query: &ExportQuery,
+    state: &AppState,
+) -> Result<(WhiteBoardData, ExportArea, Option<String>), PageError> {
+
+    let page_id = get_export_page_id(project_id, query.page_id, state).await?;
+
+    let padding = query.padding.unwrap_or(0.0).abs();
+    let viewport = query.get_viewport();
+    if let Some(area) = viewport.as_ref() {
+        let values = [area.x, area.y, area.width, area.height];
+        if !values.iter().all(|v| v.is_finite()) || area.width == 0.0 || area.height == 0.0 {
+            return Err(PageError::InvalidCoordinates);
+        }
+    }
+    if !padding.is_finite() {
+        return Err(PageError::InvalidCoordinates);
+    }
+
+    let mut storage = get_page_storage(project_id, page_id, state);
+    let background = get_export_background(project_id, query.background.as_deref(), state).await?;
+    let board = storage.get_whiteboard().await.map_err(storage_error)?.clone();
+    let area = viewport.unwrap_or_else(|| ExportArea::from_content(&board, padding));
+
+    return Ok((board, area, background));
+}
+
+
+pub async fn project_export_svg_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    let (board, area, background) = load_export(project_id, &query, &state).await?;
+
+    let svg = render_svg(&board, &area, background.as_deref());
+
+    return Ok(
+        ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+    );
+}
+
+
+pub async fn project_export_png_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    let (board, area, background) = load_export(project_id, &query, &state).await?;
+    let scale = query.scale.unwrap_or(1.0);
+    let has_texts = !board.get_texts().is_empty();
+
+    // Rasterizing a large board takes a while; keep it off the async workers
+    let png = tokio::task::spawn_blocking(move || render_png(&board, &area, scale, background.as_deref())).await
+        .map_err(|_| PageError::InternalServerError)?
+        .map_err(PageError::InvalidExport)?;
+
+    return Ok(png_response(png, has_texts));
+}
+
+
+pub async fn project_export_pdf_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Query(query): Query<PdfExportQuery>,
+) -> Result<Response, PageError> {
+
+    let project = permissions::is_collaborator(project_id, &state, &claims).await?;
+
+    let margin = query.margin.unwrap_or(10.0);
+    let padding = query.padding.unwrap_or(0.0).abs();
+    if !margin.is_finite() || margin < 0.0 || !padding.is_finite() {
+        return Err(PageError::InvalidCoordinates);
+    }
+
+    let page_ids = match query.page_id {
+        Some(page_id) => vec![get_project_page(project_id, page_id, &state).await?.get_id().unwrap()],
+        None => Page::get_project_pages(&state.pg_pool, project_id).await
+            .map_err(|_| PageError::InternalServerError)?
+            .iter().filter_map(|p| p.get_id()).collect(),
+    };
+
+    // Each board page, with the areas that become PDF pages
+    let background = get_export_background(project_id, query.background.as_deref(), &state).await?;
+    let mut boards = Vec::new();
+    for page_id in page_ids {
+        let mut storage = get_page_storage(project_id, page_id, &state);
+        let board = storage.get_whiteboard().await.map_err(storage_error)?.clone();
+        let mut areas: Vec<ExportArea> = Vec::new();
+        if query.frames {
+            areas = board.get_frames().iter()
+                .map(ExportArea::from_frame)
+                .filter(|area| area.width > 0.0 && area.height > 0.0)
+                .collect();
+        }
+        if areas.is_empty() {
+            areas.push(ExportArea::from_content(&board, padding));
+        }
+        boards.push((board, areas, background.clone()));
+    }
+    if boards.iter().map(|(_, areas, _)| areas.len()).sum::<usize>() > MAX_PDF_PAGES {
+        return Err(PageError::InvalidExport(format!("a PDF can have at most {} pages", MAX_PDF_PAGES)));
+    }
+
+    let options = PdfOptions {
+        page_size: query.page_size,
+        orientation: query.orientation,
+        margin,
+        fit_to_page: query.fit.unwrap_or(true),
+    };
+    let metadata = PdfMetadata { title: project.get_name().clone(), created_at: Utc::now() };
+
+    let pdf = tokio::task::spawn_blocking(move || {
+        let pages: Vec<PdfPage> = boards.iter()
+            .flat_map(|(board, areas, background)| areas.iter().map(move |area| PdfPage {
+                board,
+                area: *area,
+                background: background.clone(),
+            }))
+            .collect();
+        render_pdf(&pages, &options, &metadata)
+    }).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    return Ok(
+        ([(header::CONTENT_TYPE, "application/pdf")], pdf).into_response()
+    );
+}
+
+
+pub async fn project_export_archive_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> Result<Json<Archive>, PageError> {
+
+    let project = permissions::is_collaborator(project_id, &state, &claims).await?;
+    let pages = Page::get_project_pages(&state.pg_pool, project_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    let settings = Project::get_board_settings(&state.pg_pool, project_id).await?;
+    let mut archived = Vec::new();
+    for page in pages.iter() {
+        let mut storage = get_page_storage(project_id, page.get_id().unwrap(), &state);
+        let data = storage.get_whiteboard().await.map_err(storage_error)?.clone();
+        archived.push((page.get_name().clone(), page.get_position(), data));
+    }
+
+    return Ok(Json(Archive::new(project.get_name(), &settings, archived)));
+}
+
+
+/// Replays the op log of a page and keeps the board at evenly spaced points
+/// of its history, with the area holding all of them.
+async fn load_timelapse(
+    project_id: i64,
+    query: &TimelapseQuery,
+    state: &AppState,
+) -> Result<(Vec<(WhiteBoardData, Option<String>)>, ExportArea), PageError> {
+
+    let page_id = get_export_page_id(project_id, query.page_id, state).await?;
+    let frame_count = query.frames.unwrap_or(60);
+    if frame_count == 0 || frame_count > MAX_TIMELAPSE_FRAMES {
+        return Err(PageError::InvalidExport(format!("frames must be between 1 and {}", MAX_TIMELAPSE_FRAMES)));
+    }
+    let padding = query.padding.unwrap_or(0.0).abs();
+    if !padding.is_finite() {
+        return Err(PageError::InvalidCoordinates);
+    }
+
+    let op_log = OpLog::new(state.mongo_client.database("whiteboard_db").collection("whiteboard_ops"));
+    let total = op_log.count(project_id, page_id).await
+        .map_err(|_| PageError::InternalServerError)? as usize;
+    let mut history = op_log.history(project_id, page_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    // Settings are not logged, every frame has today's background
+    let background = get_export_background(project_id, query.background.as_deref(), state).await?;
+    let mut replay = Replay::new();
+    let mut frames = Vec::new();
+    let mut index = 0;
+    // Operations logged after counting are left for a later timelapse
+    while index < total {
+        let entry = match history.try_next().await.map_err(|_| PageError::InternalServerError)? {
+            Some(entry) => entry,
+            None => break,
+        };
+        if let Some(op) = BoardOp::from_entry(&entry) {
+            replay.apply(op);
+        }
+        if is_frame(index, total, frame_count) {
+            frames.push((replay.get_board().clone(), background.clone()));
+        }
+        index += 1;
+    }
+    if frames.is_empty() {
+        return Err(PageError::InvalidExport("no history is recorded for this page".to_string()));
+    }
+
+    let boards: Vec<&WhiteBoardData> = frames.iter().map(|(board, _)| board).collect();
+    let area = ExportArea::from_boards(&boards, padding);
+    return Ok((frames, area));
+}
+
+
+pub async fn project_timelapse_svg_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Query(query): Query<TimelapseQuery>,
+) -> Result<Response, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    let (frames, area) = load_timelapse(project_id, &query, &state).await?;
+    let frame_delay = query.frame_delay.unwrap_or(100);
+
+    let frames: Vec<(&WhiteBoardData, Option<&str>)> = frames.iter()
+        .map(|(board, background)| (board, background.as_deref()))
+        .collect();
+    let svg = render_animated_svg(&frames, &area, frame_delay as f32 / 1000.0);
+
+    return Ok(
+        ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+    );
+}
+
+
+pub async fn project_timelapse_png_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Query(query): Query<TimelapseQuery>,
+) -> Result<Response, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    let (mut frames, area) = load_timelapse(project_id, &query, &state).await?;
+    let frame_delay = query.frame_delay.unwrap_or(100);
+    let scale = query.scale.unwrap_or(1.0);
+
+    if let Some(frame) = query.frame {
+        if frame >= frames.len() {
+            return Err(PageError::InvalidExport(format!("the timelapse has {} frames", frames.len())));
+        }
+        frames = vec![frames.swap_remove(frame)];
+    }
+    let still = query.frame.is_some();
+    let has_texts = frames.iter().any(|(board, _)| !board.get_texts().is_empty());
+
+    let png = tokio::task::spawn_blocking(move || {
+        if still {
+            let (board, background) = &frames[0];
+            return render_png(board, &area, scale, background.as_deref());
+        }
+        let frames: Vec<(&WhiteBoardData, Option<&str>)> = frames.iter()
+            .map(|(board, background)| (board, background.as_deref()))
+            .collect();
+        return render_animated_png(&frames, &area, scale, frame_delay);
+    }).await
+        .map_err(|_| PageError::InternalServerError)?
+        .map_err(PageError::InvalidExport)?;
+
+    return Ok(png_response(png, has_texts));
+}
+
+
+/// Highlights what changed on a page between two versions, over the newer
+/// one.
+pub async fn page_diff_svg_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+    Query(query): Query<DiffExportQuery>,
+) -> Result<Response, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+    let padding = query.padding.unwrap_or(0.0).abs();
+    if !padding.is_finite() {
+        return Err(PageError::InvalidCoordinates);
+    }
+
+    let (before, after) = load_page_versions(project_id, page_id, query.from, query.to, &state).await?;
+    let diff = diff_boards(&before, &after);
+
+    let background = get_export_background(project_id, query.background.as_deref(), &state).await?;
+    let area = ExportArea::from_boards(&[&before, &after], padding);
+    let svg = render_diff_svg(&before, &after, &diff, &area, background.as_deref());
+
+    return Ok(
+        ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+    );
+}
+
+
+/// Creates a project owned by the caller with the given settings and pages
+/// and stores their boards.
+async fn create_imported_project(
+    state: &AppState,
+    claims: &Claims,
+    name: String,
+    settings: BoardSettings,
+    pages: Vec<(String, i32, WhiteBoardData)>,
+) -> Result<i64, PageError> {
+    let mut project = Project::create_new(name, claims.get_user_id());
+    project.create_row(&state.pg_pool).await
+        .map_err(|_| PageError::InternalServerError)?;
+    let project_id = project.get_id().unwrap();
+    Project::update_board_settings::<PageError, _>(&state.pg_pool, project_id, |stored| {
+        *stored = settings;
+        return Ok(());
+    }).await?;
+
+    let collection = state.mongo_client.database("whiteboard_db").collection("whiteboards");
+    for (name, position, data) in pages {
+        let mut page = Page::create_new(project_id, name, position);
+        page.create_row(&state.pg_pool).await
+            .map_err(|_| PageError::InternalServerError)?;
+
+        if let Err(e) = index_page(&state.pg_pool, project_id, page.get_id().unwrap(), &data).await {
+            println!("Failed to index texts of page {}: {}", page.get_id().unwrap(), e);
+        }
+        let mut storage = MongoDBStorage::new(project_id, page.get_id().unwrap(), collection.clone(), None);
+        let saved = async {
+            storage.set_whiteboard(data).await?;
+            return storage.save().await;
+        };
+        if let Err(e) = saved.await {
+            println!("Failed to save imported page {}: {}", page.get_id().unwrap(), e);
+            return Err(PageError::InternalServerError);
+        }
+    }
+    schedule_thumbnail(state, project_id);
+    return Ok(project_id);
+}
+
+
+/// Creates a new project owned by the caller from an archive. Nothing is
+/// created unless the whole archive is valid.
+pub async fn project_import_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<Option<ProjectOutput>>, PageError> {
+
+    let imported = Archive::import(&body, &LIMITS)
+        .map_err(|e| PageError::InvalidImport(e.to_string()))?;
+
+    let pages = imported.pages.into_iter()
+        .map(|page| (page.name, page.position, page.data))
+        .collect();
+    let project_id = create_imported_project(&state, &claims, imported.name, imported.settings, pages).await?;
+
+    let output = ProjectOutput::get_project_detail(&state.pg_pool, project_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+    return Ok(Json(output));
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct DrawingImportQuery {
+    /// Name of the new project. Defaults to the document name, when the file
+    /// has one.
+    name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DrawingImportOutput {
+    project: Option<ProjectOutput>,
+    summary: ImportSummary,
+}
+
+/// Creates a project from an Excalidraw or tldraw file and reports which
+/// shapes were converted, approximated or dropped.
+pub async fn project_import_drawing_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(format): Path<ImportFormat>,
+    Query(query): Query<DrawingImportQuery>,
+    body: Bytes,
+) -> Result<Json<DrawingImportOutput>, PageError> {
+
+    let drawing = import_drawing(format, &body, &LIMITS)
+        .map_err(|e| PageError::InvalidImport(e.to_string()))?;
+
+    let name = query.name.or(drawing.name)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "Imported drawing".to_string());
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(PageError::InvalidImport(format!("name must be at most {} characters", MAX_NAME_LENGTH)));
+    }
+
+    let pages = drawing.pages.into_iter()
+        .enumerate()
+        .map(|(position, (name, data))| (name, position as i32, data))
+        .collect();
+    let project_id = create_imported_project(&state, &claims, name, BoardSettings::default(), pages).await?;
+
+    let project = ProjectOutput::get_project_detail(&state.pg_pool, project_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+    return Ok(Json(DrawingImportOutput { project, summary: drawing.summary }));
+}
+
+
+/// Renders the thumbnail of a project from its first page and caches it.
+async fn regenerate_thumbnail(state: &AppState, project_id: i64) -> Result<Vec<u8>, PageError> {
+    let page_id = get_export_page_id(project_id, None, state).await?;
+    let background = Project::get_board_settings(&state.pg_pool, project_id).await?.get_background().to_string();
+    let board = get_page_storage(project_id, page_id, state).get_whiteboard().await.map_err(storage_error)?.clone();
+
+    let png = tokio::task::spawn_blocking(move || render_thumbnail(&board, &background)).await
+        .map_err(|_| PageError::InternalServerError)?
+        .map_err(PageError::InvalidExport)?;
+
+    ThumbnailStore::new(project_id, state.redis_client.clone()).set(&png).await
+        .map_err(|_| PageError::InternalServerError)?;
+    return Ok(png);
+}
+
+/// Regenerates the project thumbnail shortly after a change. While one
+/// regeneration is pending, further changes only wait for it.
+pub fn schedule_thumbnail(state: &AppState, project_id: i64) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let store = ThumbnailStore::new(project_id, state.redis_client.clone());
+        match store.claim_regeneration().await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                println!("Failed to schedule thumbnail of project {}: {}", project_id, e);
+                return;
+            }
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(*THUMBNAIL_DELAY)).await;
+        let _ = store.start_regeneration().await;
+        if let Err(e) = regenerate_thumbnail(&state, project_id).await {
+            println!("Failed to render thumbnail of project {}: {:?}", project_id, e);
+        }
+    });
+}
+
+
+pub async fn project_thumbnail_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> Result<Response, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+
+    let cached = ThumbnailStore::new(project_id, state.redis_client.clone()).get().await
+        .map_err(|_| PageError::InternalServerError)?;
+    let png = match cached {
+        Some(png) => png,
+        None => regenerate_thumbnail(&state, project_id).await?,
+    };
+
+    return Ok(
+        ([(header::CONTENT_TYPE, "image/png")], png).into_response()
+    );
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_response_warns_about_left_out_texts() {
+        let response = png_response(vec![1, 2, 3], true);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(response.headers()[EXPORT_WARNING_HEADER], TEXTS_NOT_DRAWN);
+
+        let response = png_response(vec![1, 2, 3], false);
+        assert!(response.headers().get(EXPORT_WARNING_HEADER).is_none());
+    }
+}
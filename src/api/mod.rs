@@ -2,4 +2,7 @@ pub mod auth;
 pub mod common;
 pub mod user;
 pub mod project;
+pub mod page;
 pub mod whiteboard;
+pub mod export;
+pub mod search;
@@ -0,0 +1,676 @@
+use chrono::{ DateTime, Utc };
+use crate::project::page::Page;
+use crate::whiteboard::storage::WhiteBoardStorage;
+use serde::{Serialize, Deserialize};
+use serde_json::{ json, Value };
+use super::common::AppState;
+use super::auth::Claims;
+use super::project::permissions::{self, ProjPermError};
+use axum::{
+    body::Body,
+    extract::{State, Path, Query},
+    http::{ header, StatusCode },
+    response::{IntoResponse, Response},
+    Json,
+};
+use crate::whiteboard::{
+    Frame,
+    WhiteBoardData,
+    storage::redis::RedisStorage as WhiteBoardRedisStorage,
+    storage::stats::IngestStats,
+    storage::oplog::{ OpLog, OpLogEntry },
+    diff::{ diff_boards, BoardDiff },
+    timelapse::replay_until,
+    presence::{ Presence, PresenceStore },
+    live::LiveBoard,
+};
+use super::export::schedule_thumbnail;
+use rstar::AABB;
+use futures::TryStreamExt;
+use std::convert::Infallible;
+use std::time::Duration;
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageCreationInput {
+    name: String,
+    position: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageUpdateInput {
+    name: Option<String>,
+    position: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ViewportQuery {
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HitTestQuery {
+    x: f32,
+    y: f32,
+    radius: Option<f32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HitTestOutput {
+    ids: Vec<String>,
+}
+
+/// Playback of a page's history. `speed` 1 keeps the recorded pace, 0 sends
+/// everything at once.
+#[derive(Debug, Deserialize)]
+pub struct ReplayQuery {
+    speed: Option<f32>,
+    /// Longest pause between two events in milliseconds, default 3000.
+    max_gap: Option<u64>,
+}
+
+/// Two versions of a page, as times in milliseconds since the Unix epoch.
+/// Without `to` the page is compared after its latest operation.
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    from: i64,
+    to: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffOutput {
+    from: i64,
+    to: Option<i64>,
+    #[serde(flatten)]
+    diff: BoardDiff,
+}
+
+/// One line of a replay stream.
+#[derive(Debug, Serialize)]
+struct ReplayEvent {
+    /// Milliseconds since the Unix epoch.
+    created_at: i64,
+    user_id: i64,
+    /// The operation as its WebSocket message.
+    event: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageStatsOutput {
+    page_id: i64,
+    #[serde(flatten)]
+    stats: IngestStats,
+    bytes_saved: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageOutput {
+    id: i64,
+    project_id: i64,
+    name: String,
+    position: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<&Page> for PageOutput {
+    fn from(value: &Page) -> Self {
+        return Self {
+            id: value.get_id().unwrap(),
+            project_id: value.get_project_id(),
+            name: value.get_name().clone(),
+            position: value.get_position(),
+            created_at: value.get_created_at(),
+            updated_at: value.get_updated_at(),
+        };
+    }
+}
+
+
+#[derive(Debug)]
+pub enum PageError {
+    Permission(ProjPermError),
+    NotFound,
+    LastPage,
+    InvalidCoordinates,
+    InvalidSettings(String),
+    InvalidExport(String),
+    InvalidImport(String),
+    InvalidReplay(String),
+    InvalidDiff(String),
+    InternalServerError,
+}
+
+impl From<ProjPermError> for PageError {
+    fn from(value: ProjPermError) -> Self {
+        Self::Permission(value)
+    }
+}
+
+impl From<sqlx::Error> for PageError {
+    fn from(value: sqlx::Error) -> Self {
+        println!("Database error: {}", value);
+        Self::InternalServerError
+    }
+}
+
+impl IntoResponse for PageError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            PageError::Permission(err) => return err.into_response(),
+            PageError::NotFound => (StatusCode::NOT_FOUND, "page not found"),
+            PageError::LastPage => (StatusCode::BAD_REQUEST, "a project must keep at least one page"),
+            PageError::InvalidSettings(message) | PageError::InvalidExport(message) | PageError::InvalidImport(message)
+            | PageError::InvalidReplay(message) | PageError::InvalidDiff(message) =>
+                return (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response(),
+            PageError::InvalidCoordinates => (StatusCode::BAD_REQUEST, "coordinates must be finite numbers"),
+            PageError::InternalServerError =>
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong, we're fix it as soon as possible :)",
+                ),
+        };
+        let body = Json(json!({
+            "error": error_message,
+        }));
+        (status, body).into_response()
+    }
+}
+
+// A board that can't be read is never served, in place of an empty one
+pub fn storage_error(e: String) -> PageError {
+    println!("Failed to load whiteboard: {}", e);
+    return PageError::InternalServerError;
+}
+
+
+/// Loads a page and makes sure it belongs to the given project.
+pub async fn get_project_page(project_id: i64, page_id: i64, state: &AppState) -> Result<Page, PageError> {
+    let page = Page::get_by_id(&state.pg_pool, page_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    match page {
+        Some(page) if page.get_project_id() == project_id => Ok(page),
+        _ => Err(PageError::NotFound),
+    }
+}
+
+pub fn get_page_storage(project_id: i64, page_id: i64, state: &AppState) -> WhiteBoardRedisStorage {
+    let database = state.mongo_client.database("whiteboard_db");
+    let collection = database.collection("whiteboards");
+
+    WhiteBoardRedisStorage::new(
+        project_id, page_id, state.redis_client.clone(), collection
+    ).with_pg_pool(state.pg_pool.clone())
+}
+
+
+pub async fn page_list_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> Result<Json<Vec<PageOutput>>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+
+    let pages = Page::get_project_pages(&state.pg_pool, project_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    return Ok(
+        Json(
+            pages.iter().map(PageOutput::from).collect()
+        )
+    );
+}
+
+
+pub async fn page_creation_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Json(payload): Json<PageCreationInput>,
+) -> Result<Json<PageOutput>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+
+    let position = match payload.position {
+        Some(position) => position,
+        None => {
+            let pages = Page::get_project_pages(&state.pg_pool, project_id).await
+                .map_err(|_| PageError::InternalServerError)?;
+            pages.iter().map(|p| p.get_position() + 1).max().unwrap_or(0)
+        }
+    };
+
+    let mut page = Page::create_new(project_id, payload.name, position);
+    page.create_row(&state.pg_pool).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    return Ok(Json(PageOutput::from(&page)));
+}
+
+
+pub async fn page_detail_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+) -> Result<Json<PageOutput>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    let page = get_project_page(project_id, page_id, &state).await?;
+
+    return Ok(Json(PageOutput::from(&page)));
+}
+
+
+pub async fn page_update_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+    Json(payload): Json<PageUpdateInput>,
+) -> Result<Json<PageOutput>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    let mut page = get_project_page(project_id, page_id, &state).await?;
+
+    if let Some(name) = payload.name {
+        page.set_name(name);
+    }
+    if let Some(position) = payload.position {
+        page.set_position(position);
+    }
+    page.update(&state.pg_pool).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    return Ok(Json(PageOutput::from(&page)));
+}
+
+
+pub async fn page_delete_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    let page = get_project_page(project_id, page_id, &state).await?;
+
+    let pages = Page::get_project_pages(&state.pg_pool, project_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+    if pages.len() <= 1 {
+        return Err(PageError::LastPage);
+    }
+
+    get_page_storage(project_id, page_id, &state).delete().await;
+    state.live_boards.write().await.remove(&(project_id, page_id));
+    let op_log = OpLog::new(state.mongo_client.database("whiteboard_db").collection("whiteboard_ops"));
+    op_log.delete_page(project_id, page_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+    page.delete(&state.pg_pool).await
+        .map_err(|_| PageError::InternalServerError)?;
+    schedule_thumbnail(&state, project_id);
+
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+
+pub async fn page_drawing_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+) -> Result<Json<WhiteBoardData>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let mut storage = get_page_storage(project_id, page_id, &state);
+
+    return Ok(
+        Json(
+          storage.get_whiteboard().await.map_err(storage_error)?.clone()
+        )
+    );
+}
+
+
+pub async fn page_frame_list_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+) -> Result<Json<Vec<Frame>>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let mut storage = get_page_storage(project_id, page_id, &state);
+
+    return Ok(
+        Json(
+          storage.get_whiteboard().await.map_err(storage_error)?.get_frames().clone()
+        )
+    );
+}
+
+
+pub async fn page_stats_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+) -> Result<Json<PageStatsOutput>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let stats = IngestStats::load(&state.redis_client, project_id, page_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    return Ok(
+        Json(
+            PageStatsOutput {
+                page_id,
+                bytes_saved: stats.get_bytes_saved(),
+                stats,
+            }
+        )
+    );
+}
+
+
+pub async fn page_presence_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+) -> Result<Json<Vec<Presence>>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let users = PresenceStore::new(project_id, page_id, state.redis_client.clone()).list().await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    return Ok(Json(users));
+}
+
+
+pub async fn page_viewport_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+    Query(viewport): Query<ViewportQuery>,
+) -> Result<Json<WhiteBoardData>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let bounds = [viewport.min_x, viewport.min_y, viewport.max_x, viewport.max_y];
+    if !bounds.iter().all(|v| v.is_finite()) {
+        return Err(PageError::InvalidCoordinates);
+    }
+    let rect = AABB::from_corners([viewport.min_x, viewport.min_y], [viewport.max_x, viewport.max_y]);
+
+    // Pages with connected clients are already indexed in memory
+    if let Some(board) = state.live_boards.read().await.get(&(project_id, page_id)) {
+        return Ok(Json(board.viewport(&rect)));
+    }
+
+    let mut storage = get_page_storage(project_id, page_id, &state);
+    let board = LiveBoard::new(storage.get_whiteboard().await.map_err(storage_error)?.clone());
+
+    return Ok(Json(board.viewport(&rect)));
+}
+
+
+pub async fn page_hit_test_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+    Query(query): Query<HitTestQuery>,
+) -> Result<Json<HitTestOutput>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let radius = query.radius.unwrap_or(0.0).abs();
+    if !query.x.is_finite() || !query.y.is_finite() || !radius.is_finite() {
+        return Err(PageError::InvalidCoordinates);
+    }
+
+    if let Some(board) = state.live_boards.read().await.get(&(project_id, page_id)) {
+        return Ok(Json(HitTestOutput { ids: board.hit_test((query.x, query.y), radius) }));
+    }
+
+    let mut storage = get_page_storage(project_id, page_id, &state);
+    let board = LiveBoard::new(storage.get_whiteboard().await.map_err(storage_error)?.clone());
+
+    return Ok(Json(HitTestOutput { ids: board.hit_test((query.x, query.y), radius) }));
+}
+
+
+/// Streams the operations of a page as newline-delimited JSON, spaced out
+/// the way they were recorded.
+pub async fn page_replay_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+    Query(query): Query<ReplayQuery>,
+) -> Result<Response, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let speed = query.speed.unwrap_or(1.0);
+    if !speed.is_finite() || speed < 0.0 {
+        return Err(PageError::InvalidReplay("speed must be a positive number or 0".to_string()));
+    }
+    let max_gap = Duration::from_millis(query.max_gap.unwrap_or(3000));
+
+    let op_log = OpLog::new(state.mongo_client.database("whiteboard_db").collection("whiteboard_ops"));
+    let history = op_log.history(project_id, page_id).await
+        .map_err(|_| PageError::InternalServerError)?;
+
+    let stream = futures::stream::unfold((history, None::<i64>), move |(mut history, previous)| async move {
+        // A failing cursor ends the stream, the status has already been sent
+        let entry = history.try_next().await.ok()??;
+        let created_at = entry.get_created_at();
+        if let (Some(previous), true) = (previous, speed > 0.0) {
+            let gap = Duration::from_secs_f64((created_at - previous).max(0) as f64 / 1000.0 / speed as f64);
+            tokio::time::sleep(gap.min(max_gap)).await;
+        }
+
+        let mut event = entry.get_op().clone();
+        if let Some(fields) = event.as_object_mut() {
+            fields.insert("type".to_string(), json!(entry.get_kind()));
+            fields.insert("page_id".to_string(), json!(page_id));
+        }
+        let line = ReplayEvent { created_at, user_id: entry.get_user_id(), event };
+        let line = serde_json::to_string(&line).unwrap() + "\n";
+        return Some((Ok::<_, Infallible>(line), (history, Some(created_at))));
+    });
+
+    return Ok(
+        ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(stream)).into_response()
+    );
+}
+
+
+/// The boards of a page as they were at `from` and at `to`, or as it is
+/// now without `to`, rebuilt from its op log. See `replay_until`.
+pub async fn load_page_versions(
+    project_id: i64,
+    page_id: i64,
+    from: i64,
+    to: Option<i64>,
+    state: &AppState,
+) -> Result<(WhiteBoardData, WhiteBoardData), PageError> {
+    if from > to.unwrap_or_else(|| Utc::now().timestamp_millis()) {
+        return Err(PageError::InvalidDiff("`from` must not be later than `to`".to_string()));
+    }
+
+    let op_log = OpLog::new(state.mongo_client.database("whiteboard_db").collection("whiteboard_ops"));
+    let entries: Vec<OpLogEntry> = op_log.history_until(project_id, page_id, to).await
+        .map_err(|_| PageError::InternalServerError)?
+        .try_collect().await
+        .map_err(|_| PageError::InternalServerError)?;
+    return Ok((replay_until(&entries, Some(from)), replay_until(&entries, to)));
+}
+
+
+pub async fn page_diff_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((project_id, page_id)): Path<(i64, i64)>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<DiffOutput>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+    get_project_page(project_id, page_id, &state).await?;
+
+    let (before, after) = load_page_versions(project_id, page_id, query.from, query.to, &state).await?;
+
+    return Ok(
+        Json(
+            DiffOutput {
+                from: query.from,
+                to: query.to,
+                diff: diff_boards(&before, &after),
+            }
+        )
+    );
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::common::{ create_test_project, create_test_user, get_test_state };
+    use mongodb::bson::{ doc, to_document, Document };
+    use crate::whiteboard::Group;
+
+    fn page_input(name: &str) -> Json<PageCreationInput> {
+        return Json(PageCreationInput { name: name.to_string(), position: None });
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_create_rename_and_delete_pages() {
+        let state = get_test_state().await;
+        let owner_id = create_test_user(&state, "pass123").await;
+        let (project_id, first_page_id) = create_test_project(&state, owner_id).await;
+
+        let Json(page) = page_creation_view(
+            Claims::for_user(owner_id), State(state.clone()), Path(project_id), page_input("Page 2")
+        ).await.unwrap();
+        assert_eq!(page.name, "Page 2");
+        assert_eq!(page.position, 1);
+
+        let rename = Json(PageUpdateInput { name: Some("Sketches".to_string()), position: None });
+        let Json(renamed) = page_update_view(
+            Claims::for_user(owner_id), State(state.clone()), Path((project_id, page.id)), rename
+        ).await.unwrap();
+        assert_eq!(renamed.name, "Sketches");
+        assert_eq!(renamed.position, 1);
+
+        let status = page_delete_view(
+            Claims::for_user(owner_id), State(state.clone()), Path((project_id, page.id))
+        ).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let Json(pages) = page_list_view(Claims::for_user(owner_id), State(state.clone()), Path(project_id))
+            .await.unwrap();
+        assert_eq!(pages.iter().map(|p| p.id).collect::<Vec<i64>>(), vec![first_page_id]);
+
+        let last = page_delete_view(
+            Claims::for_user(owner_id), State(state.clone()), Path((project_id, first_page_id))
+        ).await;
+        assert!(matches!(last, Err(PageError::LastPage)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_pages_are_checked_against_their_project() {
+        let state = get_test_state().await;
+        let owner_id = create_test_user(&state, "pass123").await;
+        let stranger_id = create_test_user(&state, "pass123").await;
+        let (project_id, page_id) = create_test_project(&state, owner_id).await;
+        let (other_project_id, other_page_id) = create_test_project(&state, owner_id).await;
+
+        let denied = page_creation_view(
+            Claims::for_user(stranger_id), State(state.clone()), Path(project_id), page_input("Mine")
+        ).await;
+        assert!(matches!(denied, Err(PageError::Permission(ProjPermError::NotColaborator))));
+
+        let denied = page_delete_view(
+            Claims::for_user(stranger_id), State(state.clone()), Path((project_id, page_id))
+        ).await;
+        assert!(matches!(denied, Err(PageError::Permission(ProjPermError::NotColaborator))));
+
+        // A page of another project is not found, even for its owner
+        let wrong_project = page_detail_view(
+            Claims::for_user(owner_id), State(state.clone()), Path((project_id, other_page_id))
+        ).await;
+        assert!(matches!(wrong_project, Err(PageError::NotFound)));
+
+        let rename = Json(PageUpdateInput { name: Some("Moved".to_string()), position: None });
+        let wrong_project = page_update_view(
+            Claims::for_user(owner_id), State(state.clone()), Path((other_project_id, page_id)), rename
+        ).await;
+        assert!(matches!(wrong_project, Err(PageError::NotFound)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_legacy_board_goes_to_the_first_page() {
+        let state = get_test_state().await;
+        let owner_id = create_test_user(&state, "pass123").await;
+        let (project_id, first_page_id) = create_test_project(&state, owner_id).await;
+        let Json(second_page) = page_creation_view(
+            Claims::for_user(owner_id), State(state.clone()), Path(project_id), page_input("Page 2")
+        ).await.unwrap();
+
+        let mut legacy: Value = serde_json::from_str(include_str!("../whiteboard/storage/fixtures/v1.json")).unwrap();
+        legacy["project_id"] = json!(project_id);
+        let legacy: Document = to_document(&legacy).unwrap();
+        let collection = state.mongo_client.database("whiteboard_db").collection::<Document>("whiteboards");
+        collection.insert_one(legacy).await.unwrap();
+
+        // Opening the second page first must not take the board over
+        let mut storage = get_page_storage(project_id, second_page.id, &state);
+        let board = serde_json::to_value(storage.get_whiteboard().await.unwrap()).unwrap();
+        assert_eq!(board["lines"], json!([]));
+
+        let mut storage = get_page_storage(project_id, first_page_id, &state);
+        let board = serde_json::to_value(storage.get_whiteboard().await.unwrap()).unwrap();
+        assert_eq!(board["lines"].as_array().unwrap().len(), 2);
+
+        let adopted = collection.find_one(doc! { "project_id": project_id, "page_id": first_page_id }).await.unwrap();
+        assert!(adopted.is_some());
+    }
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_concurrent_updates_are_not_lost() {
+        let state = get_test_state().await;
+        let owner_id = create_test_user(&state, "pass123").await;
+        let (project_id, page_id) = create_test_project(&state, owner_id).await;
+
+        let updates = (0..8).map(|i| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let group = Group::new(format!("g{}", i), vec![format!("l{}", i)]);
+                get_page_storage(project_id, page_id, &state).update(|board| {
+                    board.add_group(group.clone());
+                    return true;
+                }).await
+            })
+        });
+        for update in futures::future::join_all(updates).await {
+            assert_eq!(update.unwrap(), Ok(true));
+        }
+
+        let mut storage = get_page_storage(project_id, page_id, &state);
+        let board = serde_json::to_value(storage.get_whiteboard().await.unwrap()).unwrap();
+        assert_eq!(board["groups"].as_array().unwrap().len(), 8);
+    }
+}
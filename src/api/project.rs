@@ -1,19 +1,28 @@
 use chrono::{ DateTime, Utc };
 use crate::project::Project;
+use crate::project::page::Page;
 use crate::whiteboard::storage::WhiteBoardStorage;
+use crate::whiteboard::storage::oplog::OpLog;
+use crate::whiteboard::storage::thumbnail::ThumbnailStore;
 use serde::{Serialize, Deserialize};
 use super::common::AppState;
 use super::auth::{Claims, AuthError};
+use super::page::{ get_page_storage, storage_error, PageError };
+use super::export::schedule_thumbnail;
+use super::whiteboard::publish_settings_update;
 use axum::{
     extract::{State, Path},
     http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
 use sqlx::{ FromRow, PgPool };
 use std::collections::HashMap;
 use crate::whiteboard::{
     WhiteBoardData,
-    storage::redis::RedisStorage as WhiteBoardRedisStorage
+    settings::{ BoardSettings, BoardSettingsUpdate },
+    storage::redis::RedisStorage as WhiteBoardRedisStorage,
+    validation::{ validate_settings, LIMITS },
 };
 
 
@@ -37,6 +46,8 @@ pub struct ProjectOutput {
     collaborators: Vec<ProjectUserOutput>,
     name: String,
     owner: ProjectUserOutput,
+    /// PNG preview of the first page, regenerated in the background.
+    thumbnail_url: String,
     created_at: Option<DateTime<Utc>>,
     updated_at: Option<DateTime<Utc>>,
 }
@@ -62,6 +73,7 @@ impl ProjectOutput {
                     created_at: r.own_create,
                     updated_at: r.own_update,
                 },
+                thumbnail_url: format!("/api/projects/{}/thumbnail.png", r.proj_id.unwrap()),
                 created_at: r.proj_create,
                 updated_at: r.proj_update,
             });
@@ -108,7 +120,7 @@ impl ProjectOutput {
 
     }
 
-    async fn get_project_detail(pool: &PgPool, project_id: i64) -> Result<Option<Self>, sqlx::Error>{
+    pub(super) async fn get_project_detail(pool: &PgPool, project_id: i64) -> Result<Option<Self>, sqlx::Error>{
         let proj_data = sqlx::query_as_unchecked!(
             ProjectDetailDbRow,
             r#"
@@ -165,6 +177,7 @@ pub mod permissions {
     use axum::response::IntoResponse;
 
     use super::*;
+    #[derive(Debug)]
     pub enum ProjPermError {
         NotFound,
         NotOwner,
@@ -231,6 +244,9 @@ pub async fn project_creation_view(
 
 
     let project_id = proj.get_id().unwrap();
+    let mut first_page = Page::create_new(project_id, "Page 1".to_string(), 0);
+    first_page.create_row(&state.pg_pool).await.unwrap();
+
     let output_data =   ProjectOutput::get_project_detail(
         &state.pg_pool, project_id
     ).await.unwrap();
@@ -274,7 +290,7 @@ pub async fn add_collaborator_view(
     println!("{:?}", payload);
 
     let proj: Project = permissions::is_owner(project_id, &state, &claims).await?;
-    proj.update_collaborators(&state.pg_pool, payload.collaborator_ids).await.unwrap();
+    proj.update_collaborators(&state.pg_pool, &state.redis_client, payload.collaborator_ids).await.unwrap();
 
 
     let output_data =   ProjectOutput::get_project_detail(
@@ -289,11 +305,44 @@ pub async fn add_collaborator_view(
 }
 
 
+/// Deletes the project with its pages, their history and the thumbnail.
+/// Open connections to it are closed.
+pub async fn project_delete_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> Result<StatusCode, Response> {
+
+    let proj: Project = permissions::is_owner(project_id, &state, &claims).await
+        .map_err(|e| e.into_response())?;
+    let pages = Page::get_project_pages(&state.pg_pool, project_id).await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    proj.delete(&state.pg_pool).await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    Project::publish_permission_change(&state.redis_client, project_id).await;
+
+    // The project is gone for clients now, leftovers of a failed cleanup are
+    // never read again
+    let op_log = OpLog::new(state.mongo_client.database("whiteboard_db").collection("whiteboard_ops"));
+    for page in pages {
+        let page_id = page.get_id().unwrap();
+        get_page_storage(project_id, page_id, &state).delete().await;
+        if let Err(e) = op_log.delete_page(project_id, page_id).await {
+            println!("Failed to delete the op log of page {}: {}", page_id, e);
+        }
+    }
+    state.live_boards.write().await.retain(|(board_project, _), _| *board_project != project_id);
+    let _ = ThumbnailStore::new(project_id, state.redis_client.clone()).delete().await;
+
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+
 pub async fn get_whiteboard_data_view(
     claims: Claims,
     State(state): State<AppState>,
     Path(project_id): Path<i64>,
-) -> Result<Json<WhiteBoardData>, permissions::ProjPermError> {
+) -> Result<Json<WhiteBoardData>, PageError> {
 
     println!("{}", claims);
 
@@ -304,18 +353,62 @@ pub async fn get_whiteboard_data_view(
 
 
     proj.await?;
-    
+
+    // Clients that do not know about pages get the first page of the project.
+    let page = Page::get_first_page(&state.pg_pool, project_id).await.unwrap();
+    if page.is_none() {
+        return Err(permissions::ProjPermError::NotFound.into());
+    }
+    let page_id = page.unwrap().get_id().unwrap();
+
     let mut storage = WhiteBoardRedisStorage::new(
-        project_id, state.redis_client, collection
-    );
+        project_id, page_id, state.redis_client, collection
+    ).with_pg_pool(state.pg_pool);
 
     return Ok(
         Json(
-          storage.get_whiteboard().await.clone()
+          storage.get_whiteboard().await.map_err(storage_error)?.clone()
         )
     );
 }
 
+
+/// Board settings shared by every page of the project.
+pub async fn project_settings_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> Result<Json<BoardSettings>, PageError> {
+
+    permissions::is_collaborator(project_id, &state, &claims).await?;
+
+    return Ok(Json(Project::get_board_settings(&state.pg_pool, project_id).await?));
+}
+
+
+pub async fn project_settings_update_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Json(payload): Json<BoardSettingsUpdate>,
+) -> Result<Json<BoardSettings>, PageError> {
+
+    permissions::is_owner(project_id, &state, &claims).await?;
+
+    let settings = Project::update_board_settings(&state.pg_pool, project_id, |settings| {
+        payload.apply_to(settings);
+        return validate_settings(settings, &LIMITS)
+            .map_err(|e| PageError::InvalidSettings(format!("invalid settings: {}", e)));
+    }).await?;
+
+    schedule_thumbnail(&state, project_id);
+    if let Err(e) = publish_settings_update(&state, project_id, settings.clone()).await {
+        println!("Failed to publish settings update: {}", e);
+    }
+
+    return Ok(Json(settings));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,6 +417,7 @@ mod tests {
     use std::env;
     use sqlx::PgPool;
     use sqlx::postgres::PgPoolOptions;
+    use crate::api::common::{ create_test_project, create_test_user, get_test_state };
 
     use std::sync::Once;
     static INIT: Once = Once::new();
@@ -354,6 +448,38 @@ mod tests {
         println!("{:?}", data);
     }
 
+    #[tokio::test]
+    #[ignore = "needs Postgres and Redis"]
+    async fn test_board_settings_are_kept_per_project() {
+        let state = get_test_state().await;
+        let owner_id = create_test_user(&state, "pass123").await;
+        let (project_id, _) = create_test_project(&state, owner_id).await;
+        let (other_project_id, _) = create_test_project(&state, owner_id).await;
+
+        let Json(settings) = project_settings_view(Claims::for_user(owner_id), State(state.clone()), Path(project_id))
+            .await.unwrap();
+        assert_eq!(settings, BoardSettings::default());
+
+        let update = serde_json::from_str(r##"{"background":"#000000"}"##).unwrap();
+        let Json(settings) = project_settings_update_view(
+            Claims::for_user(owner_id), State(state.clone()), Path(project_id), Json(update)
+        ).await.unwrap();
+        assert_eq!(settings.get_background(), "#000000");
+
+        let invalid = serde_json::from_str(r#"{"background":"not a color"}"#).unwrap();
+        let rejected = project_settings_update_view(
+            Claims::for_user(owner_id), State(state.clone()), Path(project_id), Json(invalid)
+        ).await;
+        assert!(matches!(rejected, Err(PageError::InvalidSettings(_))));
+
+        let Json(settings) = project_settings_view(Claims::for_user(owner_id), State(state.clone()), Path(project_id))
+            .await.unwrap();
+        assert_eq!(settings.get_background(), "#000000");
+        let Json(other) = project_settings_view(Claims::for_user(owner_id), State(state.clone()), Path(other_project_id))
+            .await.unwrap();
+        assert_eq!(other, BoardSettings::default());
+    }
+
     
     }
 
@@ -0,0 +1,170 @@
+use crate::project::search::{ index_page, search_projects, search_texts, ProjectHit, TextHit };
+use crate::whiteboard::storage::WhiteBoardStorage;
+use serde::{ Deserialize, Serialize };
+use serde_json::json;
+use super::common::AppState;
+use super::auth::Claims;
+use super::page::get_page_storage;
+use axum::{
+    extract::{ State, Query },
+    http::StatusCode,
+    response::{ IntoResponse, Response },
+    Json,
+};
+use std::sync::LazyLock;
+
+
+/// `WHITEBOARD_SEARCH_INDEX_DELAY`, seconds to wait after a change before the
+/// texts of a page are indexed again.
+static SEARCH_INDEX_DELAY: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("WHITEBOARD_SEARCH_INDEX_DELAY").ok().and_then(|v| v.parse().ok()).unwrap_or(5)
+});
+
+/// Longest accepted query, in characters.
+const MAX_QUERY_LENGTH: usize = 200;
+const MAX_LIMIT: i64 = 100;
+
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    /// Results of each kind, default 20.
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchOutput {
+    projects: Vec<ProjectHit>,
+    texts: Vec<TextHit>,
+}
+
+
+#[derive(Debug)]
+pub enum SearchError {
+    InvalidQuery(String),
+    InternalServerError,
+}
+
+impl IntoResponse for SearchError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            SearchError::InvalidQuery(message) => (StatusCode::BAD_REQUEST, message),
+            SearchError::InternalServerError =>
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong, we're fix it as soon as possible :)".to_string(),
+                ),
+        };
+        let body = Json(json!({
+            "error": error_message,
+        }));
+        (status, body).into_response()
+    }
+}
+
+
+/// Indexes the texts of a page shortly after a change. While one indexing is
+/// pending for the page, further changes only wait for it.
+pub fn schedule_search_index(state: &AppState, project_id: i64, page_id: i64) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let pending_key = format!("whiteboard_search_pending:{}:{}", project_id, page_id);
+        match claim_pending(&state, &pending_key).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                println!("Failed to schedule search indexing of page {}: {}", page_id, e);
+                return;
+            }
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(*SEARCH_INDEX_DELAY)).await;
+        // Cleared before reading the board, so that changes made while
+        // indexing schedule another run
+        if let Ok(mut con) = state.redis_client.get_multiplexed_async_connection().await {
+            let _: redis::RedisResult<()> = redis::cmd("DEL").arg(&pending_key).query_async(&mut con).await;
+        }
+
+        let board = match get_page_storage(project_id, page_id, &state).get_whiteboard().await {
+            Ok(board) => board.clone(),
+            Err(e) => {
+                println!("Failed to load page {} for indexing: {}", page_id, e);
+                return;
+            }
+        };
+        if let Err(e) = index_page(&state.pg_pool, project_id, page_id, &board).await {
+            println!("Failed to index texts of page {}: {}", page_id, e);
+        }
+    });
+}
+
+/// Sets the pending flag of an indexing unless it is already set. The flag
+/// expires on its own in case the node that claimed it goes away.
+async fn claim_pending(state: &AppState, pending_key: &str) -> redis::RedisResult<bool> {
+    let mut con = state.redis_client.get_multiplexed_async_connection().await?;
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(pending_key)
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(*SEARCH_INDEX_DELAY * 2 + 30)
+        .query_async(&mut con)
+        .await?;
+    return Ok(claimed.is_some());
+}
+
+
+/// The trimmed search text and the number of results of each kind.
+fn validate_query(query: &SearchQuery) -> Result<(&str, i64), SearchError> {
+    let text = query.q.trim();
+    if text.is_empty() || text.chars().count() > MAX_QUERY_LENGTH {
+        return Err(SearchError::InvalidQuery(format!("q must have between 1 and {} characters", MAX_QUERY_LENGTH)));
+    }
+    let limit = query.limit.unwrap_or(20);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(SearchError::InvalidQuery(format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    return Ok((text, limit));
+}
+
+
+/// Searches the names of the caller's projects and the texts on their
+/// boards.
+pub async fn search_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchOutput>, SearchError> {
+
+    let (text, limit) = validate_query(&query)?;
+
+    let projects = search_projects(&state.pg_pool, claims.get_user_id(), text, limit).await
+        .map_err(|_| SearchError::InternalServerError)?;
+    let texts = search_texts(&state.pg_pool, claims.get_user_id(), text, limit).await
+        .map_err(|_| SearchError::InternalServerError)?;
+
+    return Ok(Json(SearchOutput { projects, texts }));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(q: &str, limit: Option<i64>) -> SearchQuery {
+        return SearchQuery { q: q.to_string(), limit };
+    }
+
+    #[test]
+    fn test_validate_query() {
+        let search = query("  roadmap  ", None);
+        assert!(matches!(validate_query(&search), Ok(("roadmap", 20))));
+        let search = query("roadmap", Some(MAX_LIMIT));
+        assert!(matches!(validate_query(&search), Ok(("roadmap", MAX_LIMIT))));
+
+        assert!(matches!(validate_query(&query("   ", None)), Err(SearchError::InvalidQuery(_))));
+        assert!(matches!(validate_query(&query(&"a".repeat(MAX_QUERY_LENGTH + 1), None)), Err(SearchError::InvalidQuery(_))));
+        assert!(matches!(validate_query(&query("roadmap", Some(0))), Err(SearchError::InvalidQuery(_))));
+        assert!(matches!(validate_query(&query("roadmap", Some(MAX_LIMIT + 1))), Err(SearchError::InvalidQuery(_))));
+    }
+}
@@ -2,13 +2,23 @@ use axum::response::Response;
 use chrono::Utc;
 use http::StatusCode;
 use crate::user::User;
+use crate::user::mailer::Email;
+use crate::user::policy::PASSWORD_POLICY;
+use crate::user::token::{ AccountToken, AccountTokenPurpose, RefreshToken };
 use serde::{ Deserialize, Serialize };
 use super::common::AppState;
-use super::auth::Claims;
+use super::auth::{ revoke_sessions, Claims };
 use axum::{ extract::State, response::IntoResponse };
 use axum::Json;
 use sqlx::{ FromRow, PgPool };
 use serde_json::json;
+use std::sync::LazyLock;
+
+
+/// `APP_URL`, address of the web app, used in the links of account emails.
+static APP_URL: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
+});
 
 #[derive(Deserialize, Debug)]
 pub struct UserRegisterData {
@@ -18,6 +28,47 @@ pub struct UserRegisterData {
     confirm_password: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct AccountTokenInput {
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AccountEmailInput {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordResetInput {
+    token: String,
+    password: String,
+    confirm_password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordChangeInput {
+    current_password: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PasswordChangeOutput {
+    /// Replaces the refresh token of the session, which was revoked with
+    /// the others.
+    refresh: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccountSecurityOutput {
+    username: String,
+    email: String,
+    date_joined: chrono::DateTime<Utc>,
+    last_login: Option<chrono::DateTime<Utc>>,
+    /// Logins whose refresh token still works.
+    active_sessions: i64,
+}
+
 #[derive(Serialize, Debug, FromRow)]
 pub struct UserListOutputView {
     id: i64,
@@ -61,19 +112,21 @@ impl UserListOutputView{
 pub enum RegisterError {
     UsernameExists,
     PasswordConfirmation,
+    WeakPassword(String),
     InternalServerError,
 }
 
 impl IntoResponse for RegisterError {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
-            RegisterError::UsernameExists => (StatusCode::BAD_REQUEST, "Username exists"),
+            RegisterError::UsernameExists => (StatusCode::BAD_REQUEST, "Username exists".to_string()),
             RegisterError::PasswordConfirmation =>
-                (StatusCode::BAD_REQUEST, "Passwords does not match"),
+                (StatusCode::BAD_REQUEST, "Passwords does not match".to_string()),
+            RegisterError::WeakPassword(message) => (StatusCode::BAD_REQUEST, message),
             RegisterError::InternalServerError =>
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "Something went wrong, we're fix it as soon as possible :)",
+                    "Something went wrong, we're fix it as soon as possible :)".to_string(),
                 ),
         };
         let body = Json(json!({
@@ -83,6 +136,77 @@ impl IntoResponse for RegisterError {
     }
 }
 
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidToken,
+    PasswordConfirmation,
+    WrongPassword,
+    WeakPassword(String),
+    InternalServerError,
+}
+
+impl IntoResponse for AccountError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            AccountError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid or expired token".to_string()),
+            AccountError::PasswordConfirmation =>
+                (StatusCode::BAD_REQUEST, "Passwords does not match".to_string()),
+            AccountError::WrongPassword => (StatusCode::BAD_REQUEST, "Current password is wrong".to_string()),
+            AccountError::WeakPassword(message) => (StatusCode::BAD_REQUEST, message),
+            AccountError::InternalServerError =>
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong, we're fix it as soon as possible :)".to_string(),
+                ),
+        };
+        let body = Json(json!({
+            "error": error_message,
+        }));
+        (status, body).into_response()
+    }
+}
+
+
+/// Mails the user a link with a new token for `purpose`, in the background
+/// so that responses take the same time whether or not an email is sent.
+fn send_account_email(state: &AppState, user: &User, purpose: AccountTokenPurpose) {
+    let state = state.clone();
+    let user_id = user.get_id().unwrap();
+    let username = user.get_username().clone();
+    let to = user.get_email().clone();
+    tokio::spawn(async move {
+        let token = match AccountToken::issue(&state.pg_pool, user_id, purpose).await {
+            Ok(token) => token,
+            Err(e) => {
+                println!("Failed to issue account token for user {}: {}", user_id, e);
+                return;
+            }
+        };
+        let email = match purpose {
+            AccountTokenPurpose::VerifyEmail => Email {
+                to,
+                subject: "Verify your email address".to_string(),
+                body: format!(
+                    "Hi {},\n\nOpen this link to activate your account:\n{}/verify-email?token={}\n",
+                    username, *APP_URL, token
+                ),
+            },
+            AccountTokenPurpose::ResetPassword => Email {
+                to,
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Hi {},\n\nOpen this link to choose a new password:\n{}/reset-password?token={}\n\nIf you did not ask for it, ignore this email.\n",
+                    username, *APP_URL, token
+                ),
+            },
+        };
+        if let Err(e) = state.mailer.send(email).await {
+            println!("Failed to send account email to user {}: {}", user_id, e);
+        }
+    });
+}
+
+
 pub async fn user_list_view(
     claims: Claims,
     State(state): State<AppState>
@@ -99,14 +223,11 @@ pub async fn user_register_view(
     if payload.password != payload.confirm_password {
         return Err(RegisterError::PasswordConfirmation);
     }
+    PASSWORD_POLICY.check(&payload.password)
+        .map_err(|e| RegisterError::WeakPassword(e.to_string()))?;
 
     let valid_username = match User::get_by_username(&state.pg_pool, payload.username.clone()).await {
-        Ok(user) => {
-            match user {
-                Some(_) => false,
-                None => true,
-            }
-        }
+        Ok(user) => user.is_none(),
         Err(_) => {
             return Err(RegisterError::InternalServerError);
         }
@@ -127,8 +248,279 @@ pub async fn user_register_view(
         false
     );
 
+    // Logging in needs the link of the verification email
     user.create_row(&state.pg_pool).await.unwrap();
+    send_account_email(&state, &user, AccountTokenPurpose::VerifyEmail);
     let output = UserListOutputView::from(&user);
     
     return Ok(Json(output));
 }
+
+
+/// Verifies the email address of the account the token was mailed for.
+pub async fn verify_email_view(
+    State(state): State<AppState>,
+    Json(payload): Json<AccountTokenInput>
+) -> Result<StatusCode, AccountError> {
+    let user_id = AccountToken::redeem(&state.pg_pool, &payload.token, AccountTokenPurpose::VerifyEmail).await
+        .map_err(|_| AccountError::InternalServerError)?
+        .ok_or(AccountError::InvalidToken)?;
+
+    let user = User::get_by_id(&state.pg_pool, user_id).await
+        .map_err(|_| AccountError::InternalServerError)?
+        .ok_or(AccountError::InvalidToken)?;
+    user.mark_email_verified(&state.pg_pool).await
+        .map_err(|_| AccountError::InternalServerError)?;
+
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+
+/// Mails a new verification link to the unverified accounts of the
+/// address. The response does not tell whether there are any.
+pub async fn resend_verification_view(
+    State(state): State<AppState>,
+    Json(payload): Json<AccountEmailInput>
+) -> Result<StatusCode, AccountError> {
+    let users = User::get_by_email(&state.pg_pool, payload.email.trim()).await
+        .map_err(|_| AccountError::InternalServerError)?;
+    for user in users.iter() {
+        let verified_at = user.get_email_verified_at(&state.pg_pool).await
+            .map_err(|_| AccountError::InternalServerError)?;
+        if verified_at.is_none() {
+            send_account_email(&state, user, AccountTokenPurpose::VerifyEmail);
+        }
+    }
+    return Ok(StatusCode::ACCEPTED);
+}
+
+
+/// Mails a password reset link to every account of the address. The
+/// response does not tell whether there are any.
+pub async fn password_reset_request_view(
+    State(state): State<AppState>,
+    Json(payload): Json<AccountEmailInput>
+) -> Result<StatusCode, AccountError> {
+    let users = User::get_by_email(&state.pg_pool, payload.email.trim()).await
+        .map_err(|_| AccountError::InternalServerError)?;
+    for user in users.iter() {
+        send_account_email(&state, user, AccountTokenPurpose::ResetPassword);
+    }
+    return Ok(StatusCode::ACCEPTED);
+}
+
+
+/// Sets the password of the account the reset token was mailed for and logs
+/// it out everywhere.
+pub async fn password_reset_confirm_view(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetInput>
+) -> Result<StatusCode, AccountError> {
+    if payload.password != payload.confirm_password {
+        return Err(AccountError::PasswordConfirmation);
+    }
+    PASSWORD_POLICY.check(&payload.password)
+        .map_err(|e| AccountError::WeakPassword(e.to_string()))?;
+
+    let user_id = AccountToken::redeem(&state.pg_pool, &payload.token, AccountTokenPurpose::ResetPassword).await
+        .map_err(|_| AccountError::InternalServerError)?
+        .ok_or(AccountError::InvalidToken)?;
+
+    let mut user = User::get_by_id(&state.pg_pool, user_id).await
+        .map_err(|_| AccountError::InternalServerError)?
+        .ok_or(AccountError::InvalidToken)?;
+    user.set_password(payload.password);
+    user.update(&state.pg_pool).await
+        .map_err(|_| AccountError::InternalServerError)?;
+    revoke_sessions(&state, user_id, None).await
+        .map_err(|_| AccountError::InternalServerError)?;
+
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+
+/// Changes the caller's password. Every other session of the account is
+/// logged out; the access token of the request keeps working and the
+/// session gets a new refresh token.
+pub async fn password_change_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordChangeInput>
+) -> Result<Json<PasswordChangeOutput>, AccountError> {
+    let mut user = User::get_by_id(&state.pg_pool, claims.get_user_id()).await
+        .map_err(|_| AccountError::InternalServerError)?
+        .ok_or(AccountError::InvalidToken)?;
+
+    if !user.check_password(payload.current_password) {
+        return Err(AccountError::WrongPassword);
+    }
+    if payload.new_password != payload.confirm_password {
+        return Err(AccountError::PasswordConfirmation);
+    }
+    PASSWORD_POLICY.check(&payload.new_password)
+        .map_err(|e| AccountError::WeakPassword(e.to_string()))?;
+
+    user.set_password(payload.new_password);
+    user.update(&state.pg_pool).await
+        .map_err(|_| AccountError::InternalServerError)?;
+    revoke_sessions(&state, claims.get_user_id(), Some(claims.get_jti())).await
+        .map_err(|_| AccountError::InternalServerError)?;
+    let refresh = RefreshToken::issue(&state.pg_pool, claims.get_user_id()).await
+        .map_err(|_| AccountError::InternalServerError)?;
+
+    return Ok(Json(PasswordChangeOutput { refresh }));
+}
+
+
+pub async fn account_security_view(
+    claims: Claims,
+    State(state): State<AppState>
+) -> Result<Json<AccountSecurityOutput>, AccountError> {
+    let user = User::get_by_id(&state.pg_pool, claims.get_user_id()).await
+        .map_err(|_| AccountError::InternalServerError)?
+        .ok_or(AccountError::InvalidToken)?;
+    let active_sessions = RefreshToken::count_active(&state.pg_pool, claims.get_user_id()).await
+        .map_err(|_| AccountError::InternalServerError)?;
+
+    return Ok(Json(AccountSecurityOutput {
+        username: user.get_username().clone(),
+        email: user.get_email().clone(),
+        date_joined: user.get_date_joined(),
+        last_login: user.get_last_login(),
+        active_sessions,
+    }));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::common::{ create_test_user, get_test_state };
+
+    const PASSWORD: &str = "tidy-lantern-42";
+
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_account_tokens_expire_and_work_once() {
+        let state = get_test_state().await;
+        let user_id = create_test_user(&state, PASSWORD).await;
+
+        let token = AccountToken::issue(&state.pg_pool, user_id, AccountTokenPurpose::VerifyEmail).await.unwrap();
+        let verified = User::get_by_id(&state.pg_pool, user_id).await.unwrap().unwrap()
+            .get_email_verified_at(&state.pg_pool).await.unwrap();
+        assert!(verified.is_none());
+
+        let input = AccountTokenInput { token: token.clone() };
+        assert_eq!(verify_email_view(State(state.clone()), Json(input)).await.unwrap(), StatusCode::NO_CONTENT);
+        let user = User::get_by_id(&state.pg_pool, user_id).await.unwrap().unwrap();
+        assert!(user.get_email_verified_at(&state.pg_pool).await.unwrap().is_some());
+        assert!(user.is_active());
+
+        let input = AccountTokenInput { token };
+        assert!(matches!(verify_email_view(State(state.clone()), Json(input)).await, Err(AccountError::InvalidToken)));
+
+        // Tokens are bound to their purpose and stop working once expired
+        let token = AccountToken::issue(&state.pg_pool, user_id, AccountTokenPurpose::ResetPassword).await.unwrap();
+        assert_eq!(AccountToken::redeem(&state.pg_pool, &token, AccountTokenPurpose::VerifyEmail).await.unwrap(), None);
+        sqlx::query("UPDATE account_tokens SET expires_at = $1 WHERE user_id = $2")
+            .bind(Utc::now() - chrono::Duration::seconds(1))
+            .bind(user_id)
+            .execute(&*state.pg_pool)
+            .await
+            .unwrap();
+        assert_eq!(AccountToken::redeem(&state.pg_pool, &token, AccountTokenPurpose::ResetPassword).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_password_reset_revokes_sessions() {
+        let state = get_test_state().await;
+        let user_id = create_test_user(&state, PASSWORD).await;
+        let claims = Claims::for_user(user_id);
+        RefreshToken::issue(&state.pg_pool, user_id).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        let token = AccountToken::issue(&state.pg_pool, user_id, AccountTokenPurpose::ResetPassword).await.unwrap();
+        let input = PasswordResetInput {
+            token: token.clone(),
+            password: "quiet-harbor-77".to_string(),
+            confirm_password: "quiet-harbor-77".to_string(),
+        };
+        assert_eq!(password_reset_confirm_view(State(state.clone()), Json(input)).await.unwrap(), StatusCode::NO_CONTENT);
+
+        let user = User::get_by_id(&state.pg_pool, user_id).await.unwrap().unwrap();
+        assert!(user.check_password("quiet-harbor-77".to_string()));
+        assert!(claims.is_revoked(&state).await);
+        assert_eq!(RefreshToken::count_active(&state.pg_pool, user_id).await.unwrap(), 0);
+
+        let input = PasswordResetInput {
+            token,
+            password: "other-harbor-77".to_string(),
+            confirm_password: "other-harbor-77".to_string(),
+        };
+        assert!(matches!(password_reset_confirm_view(State(state.clone()), Json(input)).await, Err(AccountError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_password_change_keeps_the_callers_token() {
+        let state = get_test_state().await;
+        let user_id = create_test_user(&state, PASSWORD).await;
+        let caller = Claims::for_user(user_id);
+        let other = Claims::for_user(user_id);
+        RefreshToken::issue(&state.pg_pool, user_id).await.unwrap();
+        RefreshToken::issue(&state.pg_pool, user_id).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        let input = PasswordChangeInput {
+            current_password: "wrong-password-1".to_string(),
+            new_password: "quiet-harbor-77".to_string(),
+            confirm_password: "quiet-harbor-77".to_string(),
+        };
+        let changed = password_change_view(Claims::for_user(user_id), State(state.clone()), Json(input)).await;
+        assert!(matches!(changed, Err(AccountError::WrongPassword)));
+        assert!(!other.is_revoked(&state).await);
+
+        let input = PasswordChangeInput {
+            current_password: PASSWORD.to_string(),
+            new_password: "quiet-harbor-77".to_string(),
+            confirm_password: "quiet-harbor-77".to_string(),
+        };
+        let caller_jti = caller.get_jti().to_string();
+        let output = password_change_view(caller, State(state.clone()), Json(input)).await.unwrap();
+
+        let user = User::get_by_id(&state.pg_pool, user_id).await.unwrap().unwrap();
+        assert!(user.check_password("quiet-harbor-77".to_string()));
+        assert!(other.is_revoked(&state).await);
+        let revocations = crate::user::token::RevocationList::new(state.redis_client.clone());
+        assert!(!revocations.is_revoked(&caller_jti, user_id, 0).await.unwrap());
+
+        // Only the refresh token handed back still works
+        assert_eq!(RefreshToken::count_active(&state.pg_pool, user_id).await.unwrap(), 1);
+        assert!(RefreshToken::rotate(&state.pg_pool, &output.refresh).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres, Redis and MongoDB"]
+    async fn test_account_security_counts_active_sessions() {
+        let state = get_test_state().await;
+        let user_id = create_test_user(&state, PASSWORD).await;
+        let first = RefreshToken::issue(&state.pg_pool, user_id).await.unwrap();
+        RefreshToken::issue(&state.pg_pool, user_id).await.unwrap();
+        let mut user = User::get_by_id(&state.pg_pool, user_id).await.unwrap().unwrap();
+        user.record_login(&state.pg_pool).await.unwrap();
+
+        let output = account_security_view(Claims::for_user(user_id), State(state.clone())).await.unwrap();
+        assert_eq!(&output.username, user.get_username());
+        assert_eq!(output.last_login, user.get_last_login());
+        assert_eq!(output.active_sessions, 2);
+
+        // A rotated token counts once, a revoked family not at all
+        RefreshToken::rotate(&state.pg_pool, &first).await.unwrap();
+        let output = account_security_view(Claims::for_user(user_id), State(state.clone())).await.unwrap();
+        assert_eq!(output.active_sessions, 2);
+        RefreshToken::revoke_family(&state.pg_pool, user_id, &first).await.unwrap();
+        let output = account_security_view(Claims::for_user(user_id), State(state.clone())).await.unwrap();
+        assert_eq!(output.active_sessions, 1);
+    }
+}
@@ -1,96 +1,67 @@
-use super::common::{ WsEventReceive, WsEventSend };
-use crate::api::auth::validate_token;
+use super::common::WsEventSend;
+use crate::api::auth::Claims;
 use crate::api::common::AppState;
 use crate::api::project::permissions::is_collaborator;
 
 use rand::{distributions::Alphanumeric, Rng};
-use std::{collections::HashMap, sync::Arc};
-use redis::{Commands, Script, Client, Connection};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::{Arc, LazyLock};
+use redis::AsyncCommands;
 
-pub struct RedisActions {
-    conn: Connection,
-}
+/// `WHITEBOARD_WS_SESSION_TTL`, seconds a WebSocket session survives without
+/// being refreshed. Open connections refresh theirs every few seconds.
+pub static WS_SESSION_TTL: LazyLock<u32> = LazyLock::new(|| {
+    std::env::var("WHITEBOARD_WS_SESSION_TTL").ok().and_then(|v| v.parse().ok()).unwrap_or(30)
+});
 
-impl RedisActions {
-    pub fn new(client: &Client) -> redis::RedisResult<Self> {
-        let conn = client.get_connection()?;
-
-        Ok(Self { conn })
-    }
-    pub fn set_multiple_keys_atomic(
-        &mut self,
-        key_value_pairs: HashMap<String, String>,
-        ttl: Option<u32>,
-    ) -> redis::RedisResult<()> {
-        let lua_script = Script::new(r#"
-            for i = 1, #KEYS do
-                redis.call('SET', KEYS[i], ARGV[i])
-                if tonumber(ARGV[#ARGV]) then
-                    redis.call('EXPIRE', KEYS[i], tonumber(ARGV[#ARGV]))
-                end
-            end
-            return true
-        "#);
-    
-        // Prepare keys and args
-        let mut keys = Vec::new();
-        let mut args = Vec::new();
-    
-        for (key, value) in &key_value_pairs {
-            keys.push(key);
-            args.push(value.clone());  // clone to store the value
-        }
-    
-        // TTL as the last argument
-        if let Some(ttl_val) = ttl {
-            args.push(ttl_val.to_string());  // store the owned String
-        } else {
-            args.push("nil".to_string());  // ensure owned String
-        }
-    
-        // Create an invocation and apply keys/args
-        let mut invocation = lua_script.prepare_invoke();
-        for key in keys {
-            invocation.key(&key);
-        }
-        for arg in args {
-            invocation.arg(&arg);
-        }
-    
-        invocation.invoke(&mut self.conn)?;
-        Ok(())
-    }
-    
-    pub fn remove_key(&mut self, key: &str) -> redis::RedisResult<bool> {
-        let deleted: i32 = self.conn.del(key)?;
-        Ok(deleted > 0)
-    }
+/// `WHITEBOARD_WS_TICKET_TTL`, seconds a ticket can be used to connect.
+pub static WS_TICKET_TTL: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("WHITEBOARD_WS_TICKET_TTL").ok().and_then(|v| v.parse().ok()).unwrap_or(10)
+});
 
+/// Random id of this server process, binding sessions to the node that
+/// holds their socket.
+static NODE_ID: LazyLock<String> = LazyLock::new(|| generate_random_string(16));
 
-    pub fn key_exists(&mut self, key: &str) -> redis::RedisResult<bool> {
-        let exists: i32 = self.conn.exists(key)?;
-        Ok(exists > 0)
-    }
+/// Alphanumeric characters of a session id or ticket, about 190 bits of
+/// randomness.
+const SESSION_ID_LENGTH: usize = 32;
 
-    pub fn get_key_or_raise(&mut self, key: &str) -> redis::RedisResult<String> {
-        if !self.key_exists(key)? {
-            Err(redis::RedisError::from((redis::ErrorKind::TypeError, "Key not found")))
-        } else {
-            self.conn.get(key)
-        }
+/// What a session id stands for. A session is only valid for the project
+/// and node it was created on.
+#[derive(Serialize, Deserialize, Debug)]
+struct WsSession {
+    user_id: i64,
+    project_id: i64,
+    node_id: String,
+}
+
+impl WsSession {
+    fn is_valid_for(&self, project_id: i64) -> bool {
+        return self.project_id == project_id && self.node_id == *NODE_ID;
     }
 }
 
+/// The WebSocket sessions of a project. Redis is only connected to when a
+/// session is read or written.
 pub struct WSAuthenticatedUsers {
     room_name: String,
-    redis_actions: RedisActions,
+    project_id: i64,
+    redis_cli: Arc<redis::Client>,
 }
 
 impl WSAuthenticatedUsers {
-    pub fn new(room_name: &str, redis_cli: Arc<redis::Client>) -> Self {
+    pub fn new(project_id: i64, redis_cli: Arc<redis::Client>) -> Self {
         Self {
-            room_name: room_name.to_string(),
-            redis_actions:RedisActions::new(&redis_cli).unwrap(),
+            room_name: format!("whiteboard_{}", project_id),
+            project_id,
+            redis_cli,
         }
     }
 
@@ -98,36 +69,47 @@ impl WSAuthenticatedUsers {
         format!("ws_auth_{}__", self.room_name)
     }
 
-    fn get_token_key(&self, token: &str) -> String {
-        format!("{}token_{}", self.get_base_key(), token)
-    }
-
-    fn get_user_key(&self, user_id: &str) -> String {
-        format!("{}user_{}", self.get_base_key(), user_id)
+    fn get_session_key(&self, session_id: &str) -> String {
+        format!("{}session_{}", self.get_base_key(), session_id)
     }
 
-    pub fn is_authenticated(&mut self, token: &str) -> Option<i32> {
-        let key = self.get_token_key(token);
-        match self.redis_actions.get_key_or_raise(&key) {
-            Ok(val) => val.parse::<i32>().ok(),
-            Err(_) => None,
-        }
+    /// The user of a session, if it exists and belongs to this project and
+    /// node.
+    pub async fn is_authenticated(&self, session_id: &str) -> redis::RedisResult<Option<i64>> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let value: Option<String> = con.get(self.get_session_key(session_id)).await?;
+        let session = match value.and_then(|value| serde_json::from_str::<WsSession>(&value).ok()) {
+            Some(session) if session.is_valid_for(self.project_id) => session,
+            _ => return Ok(None),
+        };
+        return Ok(Some(session.user_id));
     }
 
-    pub fn add_auth_user(&mut self, token: &str, user_id: &str) {
-        let token_key = self.get_token_key(token);
-        let user_key = self.get_user_key(user_id);
+    /// Creates a session for a new connection of the user and returns its id.
+    pub async fn add_auth_user(&self, user_id: i64) -> redis::RedisResult<String> {
+        let session_id = generate_random_string(SESSION_ID_LENGTH);
+        let session = WsSession { user_id, project_id: self.project_id, node_id: NODE_ID.clone() };
 
-        if let Ok(old_token) = self.redis_actions.get_key_or_raise(&user_key) {
-            let old_token_key = self.get_token_key(&old_token);
-            let _ = self.redis_actions.remove_key(&old_token_key);
-        }
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let _: () = con.set_ex(
+            self.get_session_key(&session_id),
+            serde_json::to_string(&session).unwrap(),
+            *WS_SESSION_TTL as u64,
+        ).await?;
+        Ok(session_id)
+    }
 
-        let mut key_value_pairs = HashMap::new();
-        key_value_pairs.insert(token_key, user_id.to_string());
-        key_value_pairs.insert(user_key, token.to_string());
+    /// Pushes back the expiry of a live session.
+    pub async fn refresh_session(&self, session_id: &str) -> redis::RedisResult<bool> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let updated: i32 = con.expire(self.get_session_key(session_id), *WS_SESSION_TTL as i64).await?;
+        Ok(updated > 0)
+    }
 
-        let _ = self.redis_actions.set_multiple_keys_atomic(key_value_pairs, Some(3600));
+    pub async fn remove_session(&self, session_id: &str) -> redis::RedisResult<()> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let _: i32 = con.del(self.get_session_key(session_id)).await?;
+        Ok(())
     }
 }
 
@@ -140,34 +122,192 @@ fn generate_random_string(n: usize) -> String {
         .collect()
 }
 
-pub async fn authorize(project_id: i64, state: &AppState, event: &WsEventReceive, ws_auth_users: &mut WSAuthenticatedUsers) -> WsEventSend {
-    match event {
-        WsEventReceive::AUTH { token } => {
-            let validation_result = validate_token(&token.as_str());
-            match validation_result {
-                Ok(claims) => {
-                    let perm = is_collaborator(project_id, state, &claims).await;
-                    match perm {
-                        Ok(_) => {
-                            let ws_token = claims.get_user_id().to_string();
-                            ws_auth_users.add_auth_user(ws_token.as_str(), claims.get_user_id().to_string().as_str());
-                            return WsEventSend::AuthSuccess {
-                                message: "Authenticated successfully".to_string(),
-                                user_token: ws_token,
-                            };
-                        },
-                        Err(_) => {
-                            return WsEventSend::Error { message: "No access to this project".to_string() };
-                        }
-                    }
-                }
-                Err(_) => {
-                    return WsEventSend::Error { message: "Invalid token".to_string() };
-                }
-            }
-        }
-        _ => {
-            return WsEventSend::Error { message: "Invalid event type for authorizing".to_string() };
-        }
+/// A single-use ticket that authorizes one WebSocket upgrade. It stands
+/// for the access token it was issued with.
+pub struct WsTicket {
+    redis_cli: Arc<redis::Client>,
+}
+
+impl WsTicket {
+    pub fn new(redis_cli: Arc<redis::Client>) -> Self {
+        Self { redis_cli }
+    }
+
+    fn get_key(ticket: &str) -> String {
+        format!("ws_ticket:{}", ticket)
+    }
+
+    /// Stores a ticket for the claims and project and returns it.
+    pub async fn issue(&self, claims: &Claims, project_id: i64) -> redis::RedisResult<String> {
+        let ticket = generate_random_string(SESSION_ID_LENGTH);
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let _: () = con.set_ex(Self::get_key(&ticket), Self::encode(claims, project_id), *WS_TICKET_TTL).await?;
+        Ok(ticket)
+    }
+
+    /// Takes the ticket out of Redis, so that it can't be used twice, and
+    /// returns the project it was issued for with the claims.
+    pub async fn redeem(&self, ticket: &str) -> redis::RedisResult<Option<(i64, Claims)>> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let value: Option<String> = redis::cmd("GETDEL").arg(Self::get_key(ticket)).query_async(&mut con).await?;
+        Ok(value.and_then(|value| Self::decode(&value)))
+    }
+
+    fn encode(claims: &Claims, project_id: i64) -> String {
+        return json!({ "project_id": project_id, "claims": claims }).to_string();
+    }
+
+    fn decode(value: &str) -> Option<(i64, Claims)> {
+        let mut value = serde_json::from_str::<Value>(value).ok()?;
+        let project_id = value.get("project_id")?.as_i64()?;
+        let claims = serde_json::from_value(value.get_mut("claims")?.take()).ok()?;
+        return Some((project_id, claims));
+    }
+}
+
+/// The claims of a redeemed ticket, if it was issued for the project.
+fn check_ticket(redeemed: Option<(i64, Claims)>, project_id: i64) -> Result<Claims, WsAuthError> {
+    return match redeemed {
+        Some((ticket_project_id, claims)) if ticket_project_id == project_id => Ok(claims),
+        Some(_) => Err(WsAuthError::NoAccess),
+        None => Err(WsAuthError::InvalidTicket),
+    };
+}
+
+
+#[derive(Debug)]
+pub enum WsAuthError {
+    MissingTicket,
+    InvalidTicket,
+    NoAccess,
+    InternalServerError,
+}
+
+impl IntoResponse for WsAuthError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            WsAuthError::MissingTicket => (StatusCode::UNAUTHORIZED, "A ticket is required to connect"),
+            WsAuthError::InvalidTicket => (StatusCode::UNAUTHORIZED, "Invalid or expired ticket"),
+            WsAuthError::NoAccess => (StatusCode::FORBIDDEN, "No access to this project"),
+            WsAuthError::InternalServerError =>
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong, we're fix it as soon as possible :)",
+                ),
+        };
+        let body = Json(json!({
+            "error": error_message,
+        }));
+        (status, body).into_response()
+    }
+}
+
+
+/// Authenticates an upgrade request from its ticket and creates the session
+/// of the connection. The claims of the token the ticket was issued with
+/// are returned, to check later that it is not revoked.
+pub async fn authorize(project_id: i64, state: &AppState, ticket: &str, ws_auth_users: &WSAuthenticatedUsers) -> Result<(WsEventSend, Claims), WsAuthError> {
+    let redeemed = WsTicket::new(state.redis_client.clone()).redeem(ticket).await
+        .map_err(|_| WsAuthError::InternalServerError)?;
+    let claims = check_ticket(redeemed, project_id)?;
+    // The token may have been revoked, or the access removed, since the
+    // ticket was issued
+    if claims.is_revoked(state).await {
+        return Err(WsAuthError::InvalidTicket);
+    }
+    is_collaborator(project_id, state, &claims).await
+        .map_err(|_| WsAuthError::NoAccess)?;
+
+    let ws_token = ws_auth_users.add_auth_user(claims.get_user_id()).await
+        .map_err(|_| WsAuthError::InternalServerError)?;
+    let event = WsEventSend::AuthSuccess {
+        message: "Authenticated successfully".to_string(),
+        user_token: ws_token,
+    };
+    return Ok((event, claims));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_redis_client() -> Arc<redis::Client> {
+        dotenv::dotenv().ok();
+        let redis_url = std::env::var("REDIS_CONNECTION_STRING").expect("REDIS_CONNECTION_STRING must be set");
+        return Arc::new(redis::Client::open(redis_url).expect("Invalid Redis connection string"));
+    }
+
+    #[test]
+    fn test_tickets_round_trip_and_are_bound_to_their_project() {
+        let claims = Claims::for_user(7);
+        let (project_id, decoded) = WsTicket::decode(&WsTicket::encode(&claims, 3)).unwrap();
+        assert_eq!(project_id, 3);
+        assert_eq!((decoded.get_user_id(), decoded.get_jti()), (7, claims.get_jti()));
+
+        assert!(WsTicket::decode("not json").is_none());
+        assert!(WsTicket::decode(r#"{"project_id": 3}"#).is_none());
+
+        let redeemed = WsTicket::decode(&WsTicket::encode(&claims, 3));
+        assert!(matches!(check_ticket(redeemed, 3), Ok(claims) if claims.get_user_id() == 7));
+        let redeemed = WsTicket::decode(&WsTicket::encode(&claims, 3));
+        assert!(matches!(check_ticket(redeemed, 4), Err(WsAuthError::NoAccess)));
+        assert!(matches!(check_ticket(None, 3), Err(WsAuthError::InvalidTicket)));
+    }
+
+    #[test]
+    fn test_sessions_are_bound_to_their_project_and_node() {
+        let session = WsSession { user_id: 7, project_id: 3, node_id: NODE_ID.clone() };
+        let session: WsSession = serde_json::from_str(&serde_json::to_string(&session).unwrap()).unwrap();
+        assert_eq!(session.user_id, 7);
+        assert!(session.is_valid_for(3));
+        assert!(!session.is_valid_for(4));
+
+        let other_node = WsSession { user_id: 7, project_id: 3, node_id: generate_random_string(16) };
+        assert!(!other_node.is_valid_for(3));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis"]
+    async fn test_tickets_work_once() {
+        let tickets = WsTicket::new(get_test_redis_client());
+        let claims = Claims::for_user(7);
+        let ticket = tickets.issue(&claims, 3).await.unwrap();
+        assert_ne!(ticket, tickets.issue(&claims, 3).await.unwrap());
+
+        let (project_id, redeemed) = tickets.redeem(&ticket).await.unwrap().unwrap();
+        assert_eq!((project_id, redeemed.get_jti()), (3, claims.get_jti()));
+        assert!(tickets.redeem(&ticket).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis"]
+    async fn test_sessions_are_unique_and_bound_to_their_project() {
+        let redis_cli = get_test_redis_client();
+        let project_id = -(rand::thread_rng().gen_range(1..i64::MAX));
+        let ws_auth_users = WSAuthenticatedUsers::new(project_id, redis_cli.clone());
+
+        let first = ws_auth_users.add_auth_user(7).await.unwrap();
+        let second = ws_auth_users.add_auth_user(7).await.unwrap();
+        assert_eq!(first.len(), SESSION_ID_LENGTH);
+        assert_ne!(first, second);
+        assert_eq!(ws_auth_users.is_authenticated(&first).await.unwrap(), Some(7));
+        assert_eq!(ws_auth_users.is_authenticated(&second).await.unwrap(), Some(7));
+        assert!(ws_auth_users.refresh_session(&first).await.unwrap());
+
+        let other_project = WSAuthenticatedUsers::new(project_id - 1, redis_cli);
+        assert_eq!(other_project.is_authenticated(&first).await.unwrap(), None);
+
+        ws_auth_users.remove_session(&first).await.unwrap();
+        assert_eq!(ws_auth_users.is_authenticated(&first).await.unwrap(), None);
+        assert_eq!(ws_auth_users.is_authenticated(&second).await.unwrap(), Some(7));
+        ws_auth_users.remove_session(&second).await.unwrap();
+    }
+
+    #[test]
+    fn test_sessions_do_not_connect_until_used() {
+        // Nothing listens there, only reading or writing a session fails
+        let redis_cli = Arc::new(redis::Client::open("redis://127.0.0.1:1/").unwrap());
+        let _ = WSAuthenticatedUsers::new(3, redis_cli);
     }
 }
@@ -1,28 +1,84 @@
 use serde::{ Serialize, Deserialize };
-use crate::whiteboard::{ CursorPosition, WhiteBoardData };
+use crate::whiteboard::{ new_element_id, Group, WhiteBoardData };
+use crate::whiteboard::presence::{ CursorPosition, Presence, Viewport };
+use crate::whiteboard::transform::{ Transform, TransformOp };
+use crate::whiteboard::eraser::{ EraseMode, EraseOps };
+use crate::whiteboard::Point;
+use crate::whiteboard::settings::{ BoardSettings, BoardSettingsUpdate };
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum WsEventReceive {
-    #[serde(rename = "auth")] AUTH {
-        token: String,
-    },
     #[serde(rename = "drawing_update")] DrawingUpdate {
+        page_id: i64,
         data: WhiteBoardData,
-        user: String,
+        #[serde(default)]
+        user: Option<String>,
     },
     #[serde(rename = "cursor_update")] CursorUpdate {
+        page_id: i64,
         data: CursorPosition,
-        user: String,
+        #[serde(default)]
+        user: Option<String>,
+    },
+    /// The sender is taken from the connection, never from the message.
+    #[serde(rename = "presence_update")] PresenceUpdate {
+        page_id: i64,
+        selection: Option<Vec<String>>,
+        viewport: Option<Viewport>,
+    },
+    #[serde(rename = "presence_sync")] PresenceSync {
+        page_id: i64,
+    },
+    #[serde(rename = "transform")] Transform {
+        page_id: i64,
+        ids: Vec<String>,
+        transform: TransformOp,
+        #[serde(default)]
+        user: Option<String>,
+    },
+    #[serde(rename = "erase")] Erase {
+        page_id: i64,
+        path: Vec<Point>,
+        radius: f32,
+        mode: EraseMode,
+        #[serde(default)]
+        user: Option<String>,
+    },
+    /// Settings apply to every page of the project.
+    #[serde(rename = "settings_update")] SettingsUpdate {
+        settings: BoardSettingsUpdate,
+        #[serde(default)]
+        user: Option<String>,
+    },
+    #[serde(rename = "group")] Group {
+        page_id: i64,
+        group_id: Option<String>,
+        ids: Vec<String>,
+        #[serde(default)]
+        user: Option<String>,
+    },
+    #[serde(rename = "ungroup")] Ungroup {
+        page_id: i64,
+        group_id: String,
+        #[serde(default)]
+        user: Option<String>,
     },
 }
 
 impl WsEventReceive {
-    pub fn get_name(&self) -> &str {
+    /// The page an event applies to, if it is a drawing event.
+    pub fn get_page_id(&self) -> Option<i64> {
         match self {
-            Self::AUTH { token } => "AUTH",
-            Self::DrawingUpdate { data, user } => "DrawingUpdate",
-            Self::CursorUpdate { data, user } => "CursorUpdate",
+            Self::DrawingUpdate { page_id, .. } => Some(*page_id),
+            Self::CursorUpdate { page_id, .. } => Some(*page_id),
+            Self::PresenceUpdate { page_id, .. } => Some(*page_id),
+            Self::PresenceSync { page_id } => Some(*page_id),
+            Self::Transform { page_id, .. } => Some(*page_id),
+            Self::Erase { page_id, .. } => Some(*page_id),
+            Self::SettingsUpdate { .. } => None,
+            Self::Group { page_id, .. } => Some(*page_id),
+            Self::Ungroup { page_id, .. } => Some(*page_id),
         }
     }
 }
@@ -35,11 +91,46 @@ pub enum WsEventSend {
         user_token: String,
     },
     #[serde(rename = "drawing_update")] DrawingUpdate {
+        page_id: i64,
         data: WhiteBoardData,
     },
     #[serde(rename = "cursor_update")] CursorUpdate {
+        page_id: i64,
         data: CursorPosition,
     },
+    #[serde(rename = "presence_update")] PresenceUpdate {
+        page_id: i64,
+        user_id: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selection: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        viewport: Option<Viewport>,
+    },
+    #[serde(rename = "presence")] Presence {
+        page_id: i64,
+        users: Vec<Presence>,
+    },
+    #[serde(rename = "transform")] Transform {
+        page_id: i64,
+        ids: Vec<String>,
+        matrix: Transform,
+    },
+    #[serde(rename = "erase")] Erase {
+        page_id: i64,
+        #[serde(flatten)]
+        ops: EraseOps,
+    },
+    #[serde(rename = "settings_update")] SettingsUpdate {
+        settings: BoardSettings,
+    },
+    #[serde(rename = "group")] Group {
+        page_id: i64,
+        group: Group,
+    },
+    #[serde(rename = "ungroup")] Ungroup {
+        page_id: i64,
+        group_id: String,
+    },
     #[serde(rename = "error")] Error {
         message: String,
     },
@@ -48,10 +139,48 @@ pub enum WsEventSend {
 impl WsEventSend {
     pub fn get_name(&self) -> &str {
         match self {
-            Self::AuthSuccess { message, user_token } => "[ :) ]AuthSuccess",
-            Self::DrawingUpdate { data } => "[ x ]DrawingUpdate",
-            Self::CursorUpdate { data } => "[ . ]CursorUpdate",
-            Self::Error { message } => "[ :-(  ]Error",
+            Self::AuthSuccess { .. } => "[ :) ]AuthSuccess",
+            Self::DrawingUpdate { .. } => "[ x ]DrawingUpdate",
+            Self::CursorUpdate { .. } => "[ . ]CursorUpdate",
+            Self::PresenceUpdate { .. } => "[ o ]PresenceUpdate",
+            Self::Presence { .. } => "[ oo ]Presence",
+            Self::Transform { .. } => "[ <> ]Transform",
+            Self::Erase { .. } => "[ ~ ]Erase",
+            Self::SettingsUpdate { .. } => "[ # ]SettingsUpdate",
+            Self::Group { .. } => "[ () ]Group",
+            Self::Ungroup { .. } => "[ )( ]Ungroup",
+            Self::Error { .. } => "[ :-(  ]Error",
+        }
+    }
+
+    /// The `type` the event is sent with.
+    pub fn get_type(&self) -> &'static str {
+        match self {
+            Self::AuthSuccess { .. } => "auth_success",
+            Self::DrawingUpdate { .. } => "drawing_update",
+            Self::CursorUpdate { .. } => "cursor_update",
+            Self::PresenceUpdate { .. } => "presence_update",
+            Self::Presence { .. } => "presence",
+            Self::Transform { .. } => "transform",
+            Self::Erase { .. } => "erase",
+            Self::SettingsUpdate { .. } => "settings_update",
+            Self::Group { .. } => "group",
+            Self::Ungroup { .. } => "ungroup",
+            Self::Error { .. } => "error",
+        }
+    }
+
+    pub fn get_page_id(&self) -> Option<i64> {
+        match self {
+            Self::DrawingUpdate { page_id, .. }
+            | Self::CursorUpdate { page_id, .. }
+            | Self::PresenceUpdate { page_id, .. }
+            | Self::Presence { page_id, .. }
+            | Self::Transform { page_id, .. }
+            | Self::Erase { page_id, .. }
+            | Self::Group { page_id, .. }
+            | Self::Ungroup { page_id, .. } => Some(*page_id),
+            Self::AuthSuccess { .. } | Self::SettingsUpdate { .. } | Self::Error { .. } => None,
         }
     }
 }
@@ -59,10 +188,21 @@ impl WsEventSend {
 impl From<&WsEventReceive> for WsEventSend {
     fn from(value: &WsEventReceive) -> Self {
         match value {
-            WsEventReceive::DrawingUpdate { data, user } =>
-                Self::DrawingUpdate { data: data.clone() },
-            WsEventReceive::CursorUpdate { data, user } =>
-                Self::CursorUpdate { data: data.clone() },
+            WsEventReceive::DrawingUpdate { page_id, data, .. } => {
+                let mut data = data.clone();
+                data.ensure_element_ids();
+                Self::DrawingUpdate { page_id: *page_id, data }
+            }
+            WsEventReceive::CursorUpdate { page_id, data, .. } =>
+                Self::CursorUpdate { page_id: *page_id, data: data.clone() },
+            WsEventReceive::Transform { page_id, ids, transform, .. } =>
+                Self::Transform { page_id: *page_id, ids: ids.clone(), matrix: transform.to_matrix() },
+            WsEventReceive::Group { page_id, group_id, ids, .. } => {
+                let group_id = group_id.clone().unwrap_or_else(new_element_id);
+                Self::Group { page_id: *page_id, group: Group::new(group_id, ids.clone()) }
+            }
+            WsEventReceive::Ungroup { page_id, group_id, .. } =>
+                Self::Ungroup { page_id: *page_id, group_id: group_id.clone() },
             _ => Self::Error { message: "Invalid event at this state!".to_string() },
         }
     }
@@ -3,31 +3,100 @@ mod auth;
 mod common;
 // --- Imports and Type Definitions ---
 
-use axum::{ extract::{ Path, State, WebSocketUpgrade }, response::IntoResponse, body::Bytes };
-use axum::extract::ws::{ Message, WebSocket, Utf8Bytes };
+use axum::{ extract::{ Path, Query, State, WebSocketUpgrade }, response::{ IntoResponse, Response }, body::Bytes, Json };
+use axum::extract::ws::{ CloseFrame, Message, WebSocket, Utf8Bytes };
 use common::{ compress_data, decompress_data, WsEventReceive, WsEventSend };
+use serde::{ Deserialize, Serialize };
 use futures::{ stream::SplitSink, SinkExt, StreamExt };
-use tokio::sync::mpsc;
+use tokio::sync::{ broadcast::error::RecvError, mpsc, Mutex };
+use std::collections::HashSet;
+use std::sync::Arc;
 use redis::AsyncCommands;
-use tokio::time::{ timeout, Duration, sleep };
+use tokio::time::{ Duration, interval };
 
-use crate::{ api::common::{ AppState, ClientTx }, whiteboard::storage::{redis::RedisStorage, WhiteBoardStorage} };
+use crate::{ api::common::{ AppState, ClientTx }, project::page::Page, whiteboard::storage::{redis::RedisStorage, WhiteBoardStorage} };
+use crate::whiteboard::validation::{
+    validate_board, validate_cursor, validate_erase, validate_eraser, validate_group, validate_settings, validate_transform,
+    validate_transformed, validate_viewport, ValidationError, LIMITS,
+};
+use crate::whiteboard::settings::{ BoardSettings, BoardSettingsUpdate };
+use crate::project::Project;
+use crate::whiteboard::eraser::{ EraseMode, EraseUndo };
+use crate::whiteboard::diff::{ delta_boards, BoardDelta };
+use crate::whiteboard::storage::oplog::{ OpLog, OpLogEntry };
+use crate::whiteboard::Point;
+use crate::whiteboard::transform::Transform;
+use crate::whiteboard::presence::PresenceStore;
+use crate::whiteboard::live::LiveBoard;
+use crate::api::page::{ get_page_storage, PageError };
+use crate::api::export::schedule_thumbnail;
+use crate::api::search::schedule_search_index;
+use crate::api::auth::Claims;
+use crate::api::project::permissions::{ is_collaborator, ProjPermError };
+use crate::whiteboard::simplify::PIPELINE;
+use crate::whiteboard::storage::stats::IngestStats;
 
 // --- WebSocket Handler ---
 
-// Called when a new client connects to a WebSocket group
+#[derive(Debug, Deserialize)]
+pub struct WsConnectQuery {
+    ticket: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WsTicketOutput {
+    ticket: String,
+    /// Seconds the ticket can be used.
+    expires_in: u64,
+}
+
+// Close codes, in the range left to applications, sent when the server
+// ends a connection
+const CLOSE_SESSION_EXPIRED: u16 = 4401;
+const CLOSE_TOKEN_REVOKED: u16 = 4402;
+const CLOSE_ACCESS_REMOVED: u16 = 4403;
+const CLOSE_PROJECT_DELETED: u16 = 4404;
+
+fn close_frame(code: u16, reason: &'static str) -> CloseFrame {
+    return CloseFrame { code, reason: Utf8Bytes::from_static(reason) };
+}
+
+// Issues a ticket for one WebSocket connection to the project. Browsers
+// can't send the access token with the upgrade request, they send this
+// ticket in the URL instead.
+pub async fn ws_ticket_view(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> Result<Json<WsTicketOutput>, Response> {
+    is_collaborator(project_id, &state, &claims).await
+        .map_err(|e| e.into_response())?;
+    let ticket = auth::WsTicket::new(state.redis_client.clone()).issue(&claims, project_id).await
+        .map_err(|_| auth::WsAuthError::InternalServerError.into_response())?;
+
+    return Ok(Json(WsTicketOutput { ticket, expires_in: *auth::WS_TICKET_TTL }));
+}
+
+// Called when a new client connects to a WebSocket group. The upgrade is
+// refused unless it carries a valid ticket.
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     Path(project_id): Path<i64>,
+    Query(query): Query<WsConnectQuery>,
     State(state): State<AppState>
-) -> impl IntoResponse {
-    // Upgrade HTTP to WebSocket and handle connection
-    let ws_auth_users = auth::WSAuthenticatedUsers::new(
-        format!("whiteboard_{}", project_id).as_str(),
-        state.redis_client.clone()
-    );
+) -> Response {
+    let ticket = match query.ticket {
+        Some(ticket) => ticket,
+        None => return auth::WsAuthError::MissingTicket.into_response(),
+    };
+    let ws_auth_users = auth::WSAuthenticatedUsers::new(project_id, state.redis_client.clone());
+    let (auth_success, claims) = match auth::authorize(project_id, &state, &ticket, &ws_auth_users).await {
+        Ok(authorized) => authorized,
+        Err(e) => return e.into_response(),
+    };
 
-    ws.on_upgrade(move |socket| handle_connection(socket, project_id, state, ws_auth_users))
+    // Upgrade HTTP to WebSocket and handle connection
+    ws.on_upgrade(move |socket| handle_connection(socket, project_id, state, ws_auth_users, auth_success, claims))
 }
 
 async fn send_event_to_ws(
@@ -38,50 +107,26 @@ async fn send_event_to_ws(
     return sender_ws.send(Message::Text(Utf8Bytes::from(msg_txt)));
 }
 
-// Manages a single WebSocket connection
+// Manages a single WebSocket connection, authorized by `ws_handler`
 async fn handle_connection(
     stream: WebSocket,
     project_id: i64,
     state: AppState,
-    mut ws_auth_users: auth::WSAuthenticatedUsers
+    ws_auth_users: auth::WSAuthenticatedUsers,
+    auth_success: WsEventSend,
+    user_claims: Claims,
 ) {
     let (mut sender_ws, mut receiver_ws) = stream.split();
     let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
-    let mut user_ws_token: Option<String> = None;
-
-    // --- Authenticate within 5 seconds ---
-    let token_event_str = match timeout(Duration::from_secs(5), receiver_ws.next()).await {
-        Ok(Some(Ok(Message::Text(auth_msg)))) => auth_msg.to_string(),
-        _ => {
-            let _ = sender_ws.send(Message::Close(None)).await;
-            return;
-        }
+    let user_ws_token = match &auth_success {
+        WsEventSend::AuthSuccess { user_token, .. } => user_token.clone(),
+        _ => return,
     };
 
-    // Replace with actual token validation logic
-    let token = serde_json::from_str::<WsEventReceive>(&token_event_str);
-    match token {
-        Ok(event) => {
-            let auth_result = auth::authorize(project_id, &state, &event, &mut ws_auth_users).await;
-            match &auth_result {
-                WsEventSend::AuthSuccess { message, user_token } => {
-                    user_ws_token = Some(user_token.clone());
-                    println!("[New connection] New user joind to group {}", project_id);
-                    if send_event_to_ws(&mut sender_ws, auth_result).await.await.is_err() {
-                        return;
-                    }
-                }
-                _ => {
-                    let _ = send_event_to_ws(&mut sender_ws, auth_result).await.await;
-                    let _ = sender_ws.send(Message::Close(None)).await;
-                    return;
-                }
-            }
-        }
-        Err(_) => {
-            let _ = sender_ws.send(Message::Close(None)).await;
-            return;
-        }
+    println!("[New connection] New user joind to group {}", project_id);
+    if send_event_to_ws(&mut sender_ws, auth_success).await.await.is_err() {
+        let _ = ws_auth_users.remove_session(&user_ws_token).await;
+        return;
     }
 
     // Register this connection in the group
@@ -102,14 +147,22 @@ async fn handle_connection(
     // Clone group and Redis client for the receiving task
     let group_clone = project_id.clone();
     let redis_client = state.redis_client.clone();
+    let sender_tx = tx.clone();
+    let user_id: i64 = user_claims.get_user_id();
+    let session_id = user_ws_token.clone();
+    let cleanup_state = state.clone();
+    let access_state = state.clone();
+    let mut permission_changes = state.permission_changes.subscribe();
+    // Pages this connection sent events for, the only ones it has presence on
+    let joined_pages: Arc<Mutex<HashSet<i64>>> = Arc::new(Mutex::new(HashSet::new()));
+    let recv_pages = joined_pages.clone();
 
     // Task: receive messages from the WebSocket and publish to Redis
     let recv_task = tokio::spawn(async move {
-        let mut redis_storage = RedisStorage::new(
-            project_id,
-            state.redis_client,
-            state.mongo_client.database("whiteboard_db").collection("whiteboards")
-        );
+        let mongo_collection = state.mongo_client.database("whiteboard_db").collection("whiteboards");
+        let op_log = OpLog::new(state.mongo_client.database("whiteboard_db").collection("whiteboard_ops"));
+        // Pages already checked to belong to this project
+        let mut known_pages: HashSet<i64> = HashSet::new();
 
         while let Some(Ok(Message::Binary(comressed_message))) = receiver_ws.next().await {
             let mut conn = match redis_client.get_multiplexed_async_connection().await {
@@ -120,70 +173,435 @@ async fn handle_connection(
             };
 
             let text = decompress_data(comressed_message.into_iter().collect()).unwrap();
+            // The message as the client sent it, before it is normalized
+            let bytes_received = text.len();
+
+            let received = serde_json::from_str::<WsEventReceive>(text.as_str());
+            let page_id = received.as_ref().ok().and_then(|e| e.get_page_id());
+
+            if let Some(page_id) = page_id {
+                if !known_pages.contains(&page_id) {
+                    match Page::get_by_id(&state.pg_pool, page_id).await {
+                        Ok(Some(page)) if page.get_project_id() == project_id => {
+                            if let Err(e) = load_live_board(&state, project_id, page_id).await {
+                                send_event_to_client(&sender_tx, &WsEventSend::Error { message: format!("page {} can't be loaded: {}.", page_id, e) });
+                                continue;
+                            }
+                            known_pages.insert(page_id);
+                            recv_pages.lock().await.insert(page_id);
+                        }
+                        _ => {
+                            send_event_to_client(&sender_tx, &WsEventSend::Error { message: format!("page {} not found in this project.", page_id) });
+                            continue;
+                        }
+                    }
+                }
+            }
 
-            let event = match serde_json::from_str::<WsEventReceive>(text.as_str()) {
+            if let Ok(WsEventReceive::PresenceSync { page_id }) = &received {
+                let users = PresenceStore::new(project_id, *page_id, redis_client.clone()).list().await.unwrap_or_default();
+                send_event_to_client(&sender_tx, &WsEventSend::Presence { page_id: *page_id, users });
+                continue;
+            }
+
+            let mut erase_undo = None;
+            let mut drawing_delta = None;
+            let mut event = match received {
+                Ok(WsEventReceive::Erase { page_id, path, radius, mode, .. }) => {
+                    match erase_event(&state, project_id, page_id, &path, radius, mode).await {
+                        Ok(Some((event, undo))) => {
+                            erase_undo = Some(undo);
+                            event
+                        }
+                        // The eraser touched nothing
+                        Ok(None) => continue,
+                        Err(message) => {
+                            send_event_to_client(&sender_tx, &WsEventSend::Error { message });
+                            continue;
+                        }
+                    }
+                }
+                Ok(WsEventReceive::SettingsUpdate { settings, .. }) => {
+                    match settings_event(&state, project_id, user_id, settings).await {
+                        Ok(event) => event,
+                        Err(message) => {
+                            send_event_to_client(&sender_tx, &WsEventSend::Error { message });
+                            continue;
+                        }
+                    }
+                }
+                Ok(WsEventReceive::PresenceUpdate { page_id, selection, viewport }) =>
+                    WsEventSend::PresenceUpdate { page_id, user_id, selection, viewport },
                 Ok(e) => WsEventSend::from(&e),
                 Err(_) => WsEventSend::Error { message: "invalid message.".to_string() },
             };
 
-            println!("Message {} published", event.get_name());
-            
+            if let Err(e) = validate_event(&mut event) {
+                send_event_to_client(&sender_tx, &WsEventSend::Error { message: format!("invalid drawing data: {}.", e) });
+                continue;
+            }
+            if let WsEventSend::Transform { page_id, ids, matrix } = &event {
+                if let Err(e) = check_transform(&state, project_id, *page_id, ids, matrix).await {
+                    send_event_to_client(&sender_tx, &WsEventSend::Error { message: format!("invalid transform: {}.", e) });
+                    continue;
+                }
+            }
+
+            if let Some(page_id) = page_id {
+                let presence_store = PresenceStore::new(project_id, page_id, redis_client.clone());
+                update_presence(&event, &presence_store, user_id).await;
+            }
+
+            if let WsEventSend::DrawingUpdate { page_id, data } = &mut event {
+                PIPELINE.apply(data);
+                let stats = IngestStats::for_update(bytes_received, data, &PIPELINE);
+                let _ = stats.record(&redis_client, project_id, *page_id).await;
+            }
+
             match &event{
                 WsEventSend::Error { message } => println!("{}", message),
                 _ => {}
             }
             
-            let updator_future = match &event {
-                WsEventSend::DrawingUpdate { data } => Some(redis_storage.set_whiteboard(data.clone())),
-                _ => None
+            let mut redis_storage = page_id.map(|page_id| RedisStorage::new(
+                project_id,
+                page_id,
+                state.redis_client.clone(),
+                mongo_collection.clone()
+            ).with_pg_pool(state.pg_pool.clone()));
+
+            // Stored before it is broadcast, others never see a change that was lost
+            let updated = match redis_storage.as_mut() {
+                Some(storage) => match update_storage(&event, storage, &mut drawing_delta).await {
+                    Ok(updated) => updated,
+                    Err(e) => {
+                        send_event_to_client(&sender_tx, &WsEventSend::Error { message: format!("the page could not be saved: {}.", e) });
+                        continue;
+                    }
+                },
+                None => false,
             };
 
             let _ = conn.publish::<_, _, ()>(
                 format!("group:{}", group_clone),
                 compress_data(serde_json::to_string(&event).unwrap())
             ).await;
+            println!("Message {} published", event.get_name());
 
-            if let Some(updator) = updator_future{
-                updator.await;
+            if updated {
+                schedule_thumbnail(&state, project_id);
+                // Only these can add, edit or move texts
+                if let WsEventSend::DrawingUpdate { page_id, .. } | WsEventSend::Transform { page_id, .. } = &event {
+                    schedule_search_index(&state, project_id, *page_id);
+                }
+                record_op(&op_log, project_id, user_id, &event, erase_undo.as_ref(), drawing_delta.as_ref()).await;
             }
         }
     });
 
-    // Task: check if user is still authenticate and can send data for this project
+    // Task: check if user is still authenticate and can send data for this
+    // project. Ends with the reason the connection is closed for.
     let still_has_access = tokio::spawn(async move {
+        // Changes of other projects must not push the next check back
+        let mut checks = interval(Duration::from_secs(5));
         loop {
-            sleep(Duration::from_secs(5)).await;
-            if let Some(user_token) = user_ws_token.as_ref() {
-                if ws_auth_users.is_authenticated(user_token.as_str()).is_some() {
-
-                } else {
+            tokio::select! {
+                _ = checks.tick() => {}
+                changed = permission_changes.recv() => {
+                    match changed {
+                        Ok(changed_project) if changed_project != project_id => continue,
+                        // Missed changes may have been for this project
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => continue,
+                    }
+                    match is_collaborator(project_id, &access_state, &user_claims).await {
+                        Ok(_) => continue,
+                        Err(ProjPermError::NotFound) => {
+                            println!("project was deleted.");
+                            return close_frame(CLOSE_PROJECT_DELETED, "project was deleted");
+                        }
+                        Err(_) => {
+                            println!("user was removed from the project.");
+                            return close_frame(CLOSE_ACCESS_REMOVED, "access to the project was removed");
+                        }
+                    }
+                }
+            }
+            match ws_auth_users.is_authenticated(user_ws_token.as_str()).await {
+                Ok(Some(_)) => {
+                    let _ = ws_auth_users.refresh_session(user_ws_token.as_str()).await;
+                }
+                Ok(None) => {
                     println!("user is not authenticated.");
-                    break;
+                    return close_frame(CLOSE_SESSION_EXPIRED, "session expired");
                 }
-            } else {
-                break;
+                // Checked again on the next tick, well before the session expires
+                Err(e) => println!("Failed to check the WebSocket session: {}", e),
+            }
+            // A logout revokes the token the connection was opened with
+            if user_claims.is_revoked(&access_state).await {
+                println!("user token was revoked.");
+                return close_frame(CLOSE_TOKEN_REVOKED, "access token was revoked");
             }
         }
     });
 
     // Wait for either task to finish (disconnect or error)
-    let msg =
+    let (msg, close) =
         tokio::select! {
-        _ = send_task => "user sender disconnected",
-        _ = recv_task => "user receiver disconnected",
-        _ = still_has_access => "user lose it's access to the project",
+        _ = send_task => ("user sender disconnected", None),
+        _ = recv_task => ("user receiver disconnected", None),
+        close = still_has_access => ("user lose it's access to the project", close.ok()),
     };
 
-    let _ = tx.send(Message::Close(None));
+    let _ = tx.send(Message::Close(close));
     println!("End WS connection: {}", msg);
+
+    let ws_auth_users = auth::WSAuthenticatedUsers::new(project_id, cleanup_state.redis_client.clone());
+    if let Err(e) = ws_auth_users.remove_session(&session_id).await {
+        println!("Failed to remove the WebSocket session: {}", e);
+    }
     
+    // Other users should not see a cursor of someone who left
+    for page_id in joined_pages.lock().await.iter() {
+        let presence_store = PresenceStore::new(project_id, *page_id, cleanup_state.redis_client.clone());
+        let _ = presence_store.remove(user_id).await;
+    }
+
     // Remove this client from the group after disconnect
-    let mut group_map = state.ws_groups.write().await;
+    let mut group_map = cleanup_state.ws_groups.write().await;
     if let Some(members) = group_map.get_mut(&project_id) {
         members.retain(|member| !member.same_channel(&tx));
         if members.is_empty() {
             group_map.remove(&project_id);
+            cleanup_state.live_boards.write().await.retain(|(board_project, _), _| *board_project != project_id);
+        }
+    }
+}
+
+// Sends an event to a single client, compressed like group broadcasts
+fn send_event_to_client(tx: &ClientTx, event: &WsEventSend) {
+    let payload = compress_data(serde_json::to_string(event).unwrap());
+    let _ = tx.send(Message::Binary(Bytes::from(payload)));
+}
+
+// Checks and normalizes incoming drawing data before it is stored or broadcast
+fn validate_event(event: &mut WsEventSend) -> Result<(), ValidationError> {
+    match event {
+        WsEventSend::DrawingUpdate { data, .. } => validate_board(data, &LIMITS),
+        WsEventSend::CursorUpdate { data, .. } => validate_cursor(data, &LIMITS),
+        WsEventSend::PresenceUpdate { viewport: Some(viewport), .. } => validate_viewport(viewport, &LIMITS),
+        WsEventSend::Transform { ids, matrix, .. } => validate_transform(ids, matrix, &LIMITS),
+        WsEventSend::Group { group, .. } => validate_group(group, &LIMITS),
+        WsEventSend::Erase { ops, .. } => validate_erase(ops, &LIMITS),
+        _ => Ok(()),
+    }
+}
+
+// Keeps the sender's cursor, selection and viewport for users joining later
+async fn update_presence(event: &WsEventSend, store: &PresenceStore, user_id: i64) {
+    let result = match event {
+        WsEventSend::CursorUpdate { data, .. } =>
+            store.update(user_id, |presence| presence.set_cursor(data.clone())).await,
+        WsEventSend::PresenceUpdate { selection, viewport, .. } =>
+            store.update(user_id, |presence| {
+                if let Some(selection) = selection {
+                    presence.set_selection(selection.clone());
+                }
+                if let Some(viewport) = viewport {
+                    presence.set_viewport(viewport.clone());
+                }
+            }).await,
+        _ => return,
+    };
+    if let Err(e) = result {
+        println!("Failed to update presence: {}", e);
+    }
+}
+
+// Keeps the page's board in memory while this node has clients on it
+async fn load_live_board(state: &AppState, project_id: i64, page_id: i64) -> Result<(), String> {
+    if state.live_boards.read().await.contains_key(&(project_id, page_id)) {
+        return Ok(());
+    }
+    let data = get_page_storage(project_id, page_id, state).get_whiteboard().await?.clone();
+    state.live_boards.write().await
+        .entry((project_id, page_id))
+        .or_insert_with(|| LiveBoard::new(data));
+    return Ok(());
+}
+
+// Works out which strokes an eraser removes or splits, using the page's
+// in-memory board and its spatial index
+async fn erase_event(
+    state: &AppState,
+    project_id: i64,
+    page_id: i64,
+    path: &[Point],
+    radius: f32,
+    mode: EraseMode,
+) -> Result<Option<(WsEventSend, EraseUndo)>, String> {
+    validate_eraser(path, radius, &LIMITS).map_err(|e| format!("invalid eraser: {}.", e))?;
+    load_live_board(state, project_id, page_id).await
+        .map_err(|e| format!("page {} can't be loaded: {}.", page_id, e))?;
+
+    let live_boards = state.live_boards.read().await;
+    let (ops, undo) = match live_boards.get(&(project_id, page_id)) {
+        Some(board) => board.erase(path, radius, mode),
+        None => return Ok(None),
+    };
+    if ops.is_empty() {
+        return Ok(None);
+    }
+    return Ok(Some((WsEventSend::Erase { page_id, ops }, undo)));
+}
+
+// Refuses a transform that would move points of the page's in-memory board
+// out of range, before it is broadcast
+async fn check_transform(
+    state: &AppState,
+    project_id: i64,
+    page_id: i64,
+    ids: &[String],
+    matrix: &Transform,
+) -> Result<(), ValidationError> {
+    return match state.live_boards.read().await.get(&(project_id, page_id)) {
+        Some(board) => board.validate_transform(ids, matrix, &LIMITS),
+        None => Ok(()),
+    };
+}
+
+// Applies a settings change of the project owner to the project's current
+// settings and stores them
+async fn settings_event(
+    state: &AppState,
+    project_id: i64,
+    user_id: i64,
+    update: BoardSettingsUpdate,
+) -> Result<WsEventSend, String> {
+    match Project::get_by_id(&state.pg_pool, project_id).await {
+        Ok(Some(project)) if project.get_owner_id() == user_id => {}
+        _ => return Err("only the project owner can change board settings.".to_string()),
+    }
+
+    let settings = Project::update_board_settings(&state.pg_pool, project_id, |settings| {
+        update.apply_to(settings);
+        return validate_settings(settings, &LIMITS)
+            .map_err(|e| PageError::InvalidSettings(format!("invalid settings: {}.", e)));
+    }).await.map_err(|e| match e {
+        PageError::InvalidSettings(message) => message,
+        _ => "the settings could not be saved.".to_string(),
+    })?;
+    schedule_thumbnail(state, project_id);
+
+    return Ok(WsEventSend::SettingsUpdate { settings });
+}
+
+// Tells the clients of a project about settings changed outside the WebSocket
+pub async fn publish_settings_update(state: &AppState, project_id: i64, settings: BoardSettings) -> redis::RedisResult<()> {
+    let event = WsEventSend::SettingsUpdate { settings };
+    let mut conn = state.redis_client.get_multiplexed_async_connection().await?;
+    conn.publish(format!("group:{}", project_id), compress_data(serde_json::to_string(&event).unwrap())).await
+}
+
+// Applies a broadcast event to the in-memory board of its page, if loaded
+async fn apply_to_live_board(state: &AppState, project_id: i64, payload: Vec<u8>) {
+    let event = match decompress_data(payload).ok().and_then(|text| serde_json::from_str::<WsEventSend>(&text).ok()) {
+        Some(event) => event,
+        None => return,
+    };
+    let page_id = match &event {
+        WsEventSend::DrawingUpdate { page_id, .. }
+        | WsEventSend::Transform { page_id, .. }
+        | WsEventSend::Erase { page_id, .. }
+        | WsEventSend::Group { page_id, .. }
+        | WsEventSend::Ungroup { page_id, .. } => *page_id,
+        _ => return,
+    };
+
+    let mut live_boards = state.live_boards.write().await;
+    let board = match live_boards.get_mut(&(project_id, page_id)) {
+        Some(board) => board,
+        None => return,
+    };
+    match event {
+        WsEventSend::DrawingUpdate { data, .. } => board.set_data(data),
+        WsEventSend::Transform { ids, matrix, .. } => {
+            board.apply_transform(&ids, &matrix);
+        }
+        WsEventSend::Erase { ops, .. } => {
+            board.apply_erase(&ops);
         }
+        WsEventSend::Group { group, .. } => board.add_group(group),
+        WsEventSend::Ungroup { group_id, .. } => {
+            board.remove_group(&group_id);
+        }
+        _ => {}
+    }
+}
+
+// Applies a drawing event to the stored board of its page, each as one
+// atomic update so that concurrent events of other users are not lost.
+// Returns whether the stored board changed. A board that can't be read is
+// left alone. For drawing updates, `delta` is set to what changed on the
+// board they replaced.
+async fn update_storage(event: &WsEventSend, storage: &mut RedisStorage, delta: &mut Option<BoardDelta>) -> Result<bool, String> {
+    return match event {
+        WsEventSend::DrawingUpdate { data, .. } => storage.update(|board| {
+            let mut after = data.clone();
+            after.unpack_points();
+            *delta = Some(delta_boards(board, &after));
+            *board = data.clone();
+            return true;
+        }).await,
+        WsEventSend::Transform { ids, matrix, .. } => storage.update(|board| {
+            // The stored board may differ from the one checked before
+            if validate_transformed(board, ids, matrix, &LIMITS).is_err() {
+                return false;
+            }
+            return board.apply_transform(ids, matrix) > 0;
+        }).await,
+        WsEventSend::Erase { ops, .. } => storage.update(|board| board.apply_erase(ops) > 0).await,
+        WsEventSend::Group { group, .. } => storage.update(|board| {
+            board.add_group(group.clone());
+            return true;
+        }).await,
+        WsEventSend::Ungroup { group_id, .. } => storage.update(|board| board.remove_group(group_id)).await,
+        _ => Ok(false),
+    };
+}
+
+// Appends an operation that changed a page to its op log, for undo and
+// replay. The message is stored without its `type` and `page_id`, which the
+// entry records itself. A drawing update with its `delta` is stored as a
+// `drawing_delta`, not with the whole board it sent.
+async fn record_op(
+    op_log: &OpLog,
+    project_id: i64,
+    user_id: i64,
+    event: &WsEventSend,
+    undo: Option<&EraseUndo>,
+    delta: Option<&BoardDelta>,
+) {
+    let page_id = match event.get_page_id() {
+        Some(page_id) => page_id,
+        None => return,
+    };
+    let entry = match (event, delta) {
+        (WsEventSend::DrawingUpdate { .. }, Some(delta)) => OpLogEntry::new(
+            project_id, page_id, user_id, "drawing_delta", &serde_json::json!({ "delta": delta }), undo
+        ),
+        _ => {
+            let mut op = serde_json::to_value(event).unwrap();
+            if let Some(fields) = op.as_object_mut() {
+                fields.remove("type");
+                fields.remove("page_id");
+            }
+            OpLogEntry::new(project_id, page_id, user_id, event.get_type(), &op, undo)
+        }
+    };
+    if let Err(e) = op_log.record(&entry).await {
+        println!("Failed to record {}: {}", event.get_type(), e);
     }
 }
 
@@ -199,8 +617,9 @@ pub async fn redis_subscriber(state: AppState) {
         }
     };
 
-    // Subscribe to all group channels
+    // Subscribe to all group channels and permission changes
     let _: () = pubsub.psubscribe("group:*").await.unwrap();
+    let _: () = pubsub.psubscribe("permissions:*").await.unwrap();
 
     // Continuously listen for new messages
     let mut stream = pubsub.on_message();
@@ -208,16 +627,27 @@ pub async fn redis_subscriber(state: AppState) {
         let channel = msg.get_channel_name();
         let payload: Vec<u8> = msg.get_payload().unwrap_or_default();
 
+        if let Some(project) = channel.strip_prefix("permissions:") {
+            if let Ok(project_id) = project.parse::<i64>() {
+                // Nobody listens when no client is connected
+                let _ = state.permission_changes.send(project_id);
+            }
+            continue;
+        }
+
         // Extract group name from channel
         if let Some(group) = channel.strip_prefix("group:") {
             let project_id: i64 = group.parse().expect("Invalid number");
             // Send message to all local clients in the group
-            let group_map = state.ws_groups.read().await;
-            if let Some(members) = group_map.get(&project_id) {
-                for member in members {
-                    let _ = member.send(Message::Binary(Bytes::from(payload.clone())));
+            {
+                let group_map = state.ws_groups.read().await;
+                if let Some(members) = group_map.get(&project_id) {
+                    for member in members {
+                        let _ = member.send(Message::Binary(Bytes::from(payload.clone())));
+                    }
                 }
             }
+            apply_to_live_board(&state, project_id, payload).await;
         }
     }
 }
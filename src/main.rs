@@ -7,10 +7,11 @@ extern crate dotenv;
 
 
 use axum::{
-    routing::{post, get},
+    extract::DefaultBodyLimit,
+    routing::{post, get, delete},
     Router,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 use std::{collections::HashMap, net::SocketAddr};
 use http::Method;
 use sqlx::{postgres::PgPoolOptions, PgPool};
@@ -26,6 +27,7 @@ use std::sync::Arc;
 use redis::Client as RedisClient;
 use api::common::AppState;
 use api::whiteboard::{redis_subscriber, ws_handler};
+use whiteboard::storage::{ mongo::MongoDBStorage, oplog::OpLog, WhiteBoardStorage };
 
 
 #[instrument(skip(pg_pool), name = "postgres_health_check")]
@@ -80,17 +82,67 @@ async fn create_app_state() -> Result<AppState, Box<dyn Error>> {
         error!("Mongo health check failed: {}", e);
         return Err(Box::new(e));
     }
+    // Page versions are read back from the op log by time
+    let op_log = OpLog::new(mongo_client.database("whiteboard_db").collection("whiteboard_ops"));
+    if let Err(e) = op_log.create_indexes().await {
+        error!("Creating the op log indexes failed: {}", e);
+        return Err(Box::new(e));
+    }
     let mongo_client = Arc::new(mongo_client);
     info!("Mongo connection established.");
 
+    let mailer = user::mailer::mailer_from_env()
+        .map_err(|e| format!("Mail configuration is invalid: {}", e))?;
+    // Fails at startup rather than on the first password set
+    std::sync::LazyLock::force(&user::policy::PASSWORD_POLICY);
+
     Ok(AppState {
         pg_pool: Arc::new(pg_pool),
         redis_client,
         mongo_client,
         ws_groups: Arc::new(RwLock::new(HashMap::new())),
+        live_boards: Arc::new(RwLock::new(HashMap::new())),
+        permission_changes: broadcast::channel(64).0,
+        mailer,
     })
 }
 
+/// `whiteboard migrate-boards` rewrites every stored board to the current
+/// schema version instead of waiting for boards to be migrated on read.
+async fn migrate_boards(app_state: &AppState) -> Result<(), Box<dyn Error>> {
+    let collection = app_state.mongo_client.database("whiteboard_db").collection("whiteboards");
+    let report = whiteboard::storage::migration::migrate_collection(&collection, &app_state.pg_pool).await?;
+    println!(
+        "{} outdated board(s) found, {} migrated, {} failed.",
+        report.scanned, report.migrated, report.failed
+    );
+    Ok(())
+}
+
+/// `whiteboard index-search` indexes the texts of every page, for boards saved
+/// before they were searchable.
+async fn index_search(app_state: &AppState) -> Result<(), Box<dyn Error>> {
+    let collection = app_state.mongo_client.database("whiteboard_db").collection("whiteboards");
+    let pages = project::page::Page::get_all(&app_state.pg_pool).await?;
+    let mut failed = 0;
+    for page in pages.iter() {
+        let (project_id, page_id) = (page.get_project_id(), page.get_id().unwrap());
+        let mut storage = MongoDBStorage::new(project_id, page_id, collection.clone(), None)
+            .with_pg_pool(app_state.pg_pool.clone());
+        let indexed = match storage.get_whiteboard().await {
+            Ok(board) => project::search::index_page(&app_state.pg_pool, project_id, page_id, board).await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = indexed {
+            println!("Failed to index texts of page {}: {}", page_id, e);
+            failed += 1;
+        }
+    }
+    println!("{} page(s) indexed, {} failed.", pages.len() - failed, failed);
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
@@ -102,28 +154,84 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .init();
 
     let app_state = create_app_state().await?;
+
+    if env::args().nth(1).as_deref() == Some("migrate-boards") {
+        return migrate_boards(&app_state).await;
+    }
+    if env::args().nth(1).as_deref() == Some("index-search") {
+        return index_search(&app_state).await;
+    }
     
 
 
     let cors_layer = CorsLayer::new()
-    .allow_methods([Method::GET, Method::POST])
+    .allow_methods([Method::GET, Method::POST, Method::DELETE])
     .allow_headers(Any)
-    .allow_origin(Any);
+    .allow_origin(Any)
+    .expose_headers([http::HeaderName::from_static("x-export-warning")]);
 
         // Start Redis WS subscription listener
         tokio::spawn(redis_subscriber(app_state.clone()));
 
     let app = Router::new()
         .route("/api/auth/login/", post(api::auth::authorize))
+        .route("/api/auth/refresh/", post(api::auth::refresh))
+        .route("/api/auth/logout/", post(api::auth::logout))
+        .route("/api/auth/logout/all/", post(api::auth::logout_all))
         .route("/api/projects/users/", get(api::user::user_list_view))
         .route("/api/users/", post(api::user::user_register_view))
+        .route("/api/users/verify-email/", post(api::user::verify_email_view))
+        .route("/api/users/verify-email/resend/", post(api::user::resend_verification_view))
+        .route("/api/users/password-reset/", post(api::user::password_reset_request_view))
+        .route("/api/users/password-reset/confirm/", post(api::user::password_reset_confirm_view))
+        .route("/api/users/me/password/", post(api::user::password_change_view))
+        .route("/api/users/me/security/", get(api::user::account_security_view))
         .route("/api/projects/",
              post(api::project::project_creation_view)
             .get(api::project::owned_project_list_view)
             )
-
+        .route("/api/projects/{project_id}/", delete(api::project::project_delete_view))
         .route("/api/projects/{project_id}/update_collaborators/", post(api::project::add_collaborator_view))
         .route("/api/projects/{project_id}/drawing/", get(api::project::get_whiteboard_data_view))
+        .route("/api/projects/{project_id}/settings/",
+             get(api::project::project_settings_view)
+            .post(api::project::project_settings_update_view)
+            )
+        .route("/api/projects/{project_id}/export.svg", get(api::export::project_export_svg_view))
+        .route("/api/projects/{project_id}/export.png", get(api::export::project_export_png_view))
+        .route("/api/projects/{project_id}/export.pdf", get(api::export::project_export_pdf_view))
+        .route("/api/projects/{project_id}/export.json", get(api::export::project_export_archive_view))
+        .route("/api/projects/import/",
+            post(api::export::project_import_view)
+                .layer(DefaultBodyLimit::max(api::export::MAX_ARCHIVE_BYTES))
+        )
+        .route("/api/projects/import/{format}/",
+            post(api::export::project_import_drawing_view)
+                .layer(DefaultBodyLimit::max(api::export::MAX_ARCHIVE_BYTES))
+        )
+        .route("/api/search/", get(api::search::search_view))
+        .route("/api/projects/{project_id}/ws-ticket/", post(api::whiteboard::ws_ticket_view))
+        .route("/api/projects/{project_id}/timelapse.svg", get(api::export::project_timelapse_svg_view))
+        .route("/api/projects/{project_id}/timelapse.png", get(api::export::project_timelapse_png_view))
+        .route("/api/projects/{project_id}/thumbnail.png", get(api::export::project_thumbnail_view))
+        .route("/api/projects/{project_id}/pages/",
+             post(api::page::page_creation_view)
+            .get(api::page::page_list_view)
+            )
+        .route("/api/projects/{project_id}/pages/{page_id}/",
+             get(api::page::page_detail_view)
+            .post(api::page::page_update_view)
+            .delete(api::page::page_delete_view)
+            )
+        .route("/api/projects/{project_id}/pages/{page_id}/drawing/", get(api::page::page_drawing_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/frames/", get(api::page::page_frame_list_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/stats/", get(api::page::page_stats_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/presence/", get(api::page::page_presence_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/replay/", get(api::page::page_replay_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/diff/", get(api::page::page_diff_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/diff.svg", get(api::export::page_diff_svg_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/elements/", get(api::page::page_viewport_view))
+        .route("/api/projects/{project_id}/pages/{page_id}/elements/at/", get(api::page::page_hit_test_view))
         .route("/ws/whiteboard/{project_id}/", get(ws_handler))
         .layer(ServiceBuilder::new().layer(cors_layer))
         .with_state(app_state);
@@ -1,5 +1,10 @@
+pub mod page;
+pub mod search;
+
 use chrono::{DateTime, Utc};
+use redis::AsyncCommands;
 use sqlx::PgPool;
+use crate::whiteboard::settings::BoardSettings;
 
 
 pub struct Project{
@@ -89,7 +94,9 @@ impl Project {
 
 
 
-    pub async fn update_collaborators(&self, pool: &PgPool, colabs: Vec<i64>) -> Result<(), sqlx::Error>{
+    /// Replaces the collaborators of the project. Removed collaborators lose
+    /// their open connections right away.
+    pub async fn update_collaborators(&self, pool: &PgPool, redis_cli: &redis::Client, colabs: Vec<i64>) -> Result<(), sqlx::Error>{
         // Start a transaction
         let mut tx = pool.begin().await?;
 
@@ -124,14 +131,93 @@ impl Project {
         }
 
 
-        collabrator_remover.await.unwrap();
-        bulk_query.execute(&mut *tx).await?;
+        collabrator_remover.await?;
+        // Removing every collaborator leaves nothing to insert
+        if !colabs.is_empty() {
+            bulk_query.execute(&mut *tx).await?;
+        }
 
         tx.commit().await?;
+        Self::publish_permission_change(redis_cli, self.get_id().unwrap()).await;
         Ok(())
 
     }
 
+    /// Tells every node that the collaborators of a project changed or that
+    /// it was deleted, so that open connections check their access again.
+    pub async fn publish_permission_change(redis_cli: &redis::Client, project_id: i64) {
+        let published = match redis_cli.get_multiplexed_async_connection().await {
+            Ok(mut conn) => conn.publish::<_, _, ()>(format!("permissions:{}", project_id), 1).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = published {
+            println!("Failed to publish permission change of project {}: {}", project_id, e);
+        }
+    }
+
+    /// Deletes the project with its collaborators. Pages and their indexed
+    /// texts go with it.
+    pub async fn delete(self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("DELETE FROM projects_collaborators WHERE project_id = $1")
+            .bind(self.get_id().unwrap())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM projects WHERE id = $1")
+            .bind(self.get_id().unwrap())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The board settings shared by every page of the project, the defaults
+    /// until the owner changes them.
+    pub async fn get_board_settings(pool: &PgPool, project_id: i64) -> Result<BoardSettings, sqlx::Error> {
+        let stored: Option<Option<String>> = sqlx::query_scalar("SELECT board_settings::text FROM projects WHERE id = $1")
+            .bind(project_id)
+            .fetch_optional(pool)
+            .await?;
+        return Self::parse_board_settings(stored.flatten());
+    }
+
+    /// Applies `change` to the board settings of the project and stores the
+    /// result unless `change` fails. The row stays locked in between, so
+    /// that concurrent changes are applied one after the other.
+    pub async fn update_board_settings<E, F>(pool: &PgPool, project_id: i64, change: F) -> Result<BoardSettings, E>
+    where
+        E: From<sqlx::Error>,
+        F: FnOnce(&mut BoardSettings) -> Result<(), E>,
+    {
+        let mut tx = pool.begin().await?;
+
+        let stored: Option<String> = sqlx::query_scalar("SELECT board_settings::text FROM projects WHERE id = $1 FOR UPDATE")
+            .bind(project_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let mut settings = Self::parse_board_settings(stored)?;
+        change(&mut settings)?;
+
+        sqlx::query("UPDATE projects SET board_settings = $1::jsonb WHERE id = $2")
+            .bind(serde_json::to_string(&settings).unwrap())
+            .bind(project_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        return Ok(settings);
+    }
+
+    // Fields added to the settings later take their defaults
+    fn parse_board_settings(stored: Option<String>) -> Result<BoardSettings, sqlx::Error> {
+        return match stored {
+            Some(value) => serde_json::from_str(&value).map_err(|e| sqlx::Error::Decode(Box::new(e))),
+            None => Ok(BoardSettings::default()),
+        };
+    }
+
     pub async fn is_collaborator(&self, pool: &PgPool, user_id: i64) -> Result<bool, sqlx::Error>{
         let result: Option<(i32,)> = sqlx::query_as("SELECT id FROM projects_collaborators WHERE project_id = $1 AND user_id = $2;")
         .bind(self.get_id().unwrap())
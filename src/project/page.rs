@@ -0,0 +1,184 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+
+
+#[derive(Debug, FromRow)]
+pub struct Page {
+    id: Option<i64>,
+    project_id: i64,
+    name: String,
+    position: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+
+impl Page {
+    pub fn create_new(
+        project_id: i64,
+        name: String,
+        position: i32,
+    ) -> Self{
+        let new_page = Self{
+            id: None,
+            project_id,
+            name,
+            position,
+            created_at: Utc::now(),
+            updated_at: Utc::now()
+        };
+        return new_page;
+    }
+
+
+    pub async fn get_by_id(pool: &PgPool, page_id: i64) -> Result<Option<Self>, sqlx::Error> {
+        let page = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT id, project_id, name, position, created_at, updated_at
+            FROM project_pages
+            WHERE id = $1
+            "#,
+        )
+        .bind(page_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(page)
+    }
+
+    pub async fn get_project_pages(pool: &PgPool, project_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        let pages = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT id, project_id, name, position, created_at, updated_at
+            FROM project_pages
+            WHERE project_id = $1
+            ORDER BY position, id
+            "#,
+        )
+        .bind(project_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(pages)
+    }
+
+    /// Every page of every project, for maintenance commands.
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, sqlx::Error> {
+        let pages = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT id, project_id, name, position, created_at, updated_at
+            FROM project_pages
+            ORDER BY project_id, position, id
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(pages)
+    }
+
+    /// The page shown when a client does not ask for a specific one.
+    pub async fn get_first_page(pool: &PgPool, project_id: i64) -> Result<Option<Self>, sqlx::Error> {
+        let mut pages = Self::get_project_pages(pool, project_id).await?;
+        if pages.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(pages.remove(0)));
+    }
+
+
+    pub fn get_id(&self) -> Option<i64>{
+        self.id
+    }
+
+    pub fn get_project_id(&self) -> i64{
+        self.project_id
+    }
+
+    pub fn get_name(&self) -> &String{
+        &self.name
+    }
+
+    pub fn get_position(&self) -> i32{
+        self.position
+    }
+
+    pub fn get_created_at(&self) -> DateTime<Utc>{
+        self.created_at
+    }
+
+    pub fn get_updated_at(&self) -> DateTime<Utc>{
+        self.updated_at
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn set_position(&mut self, position: i32) {
+        self.position = position;
+    }
+
+
+    pub async fn create_row(&mut self, pool: &PgPool) -> Result<(), String> {
+        if self.id.is_some() {
+            return Err("Can not create an already exists row. The page Id must be None to create new row.".to_string());
+        }
+
+        let created_page = sqlx::query_as::<_, Self>(
+            r#"
+            INSERT INTO project_pages (project_id, name, position, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, project_id, name, position, created_at, updated_at
+            "#,
+        )
+        .bind(self.project_id)
+        .bind(&self.name)
+        .bind(self.position)
+        .bind(self.created_at)
+        .bind(Utc::now())
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        *self = created_page;
+        return Ok(());
+    }
+
+
+    pub async fn update(&mut self, pool: &PgPool) -> Result<(), String> {
+        if self.id.is_none() {
+            return Err("Can not update the page. The page Id must not none to update the DB row.".to_string());
+        }
+
+        let updated = sqlx::query_as::<_, Self>(
+            r#"
+            UPDATE project_pages SET
+                name = $1,
+                position = $2,
+                updated_at = $3
+            WHERE id = $4
+            RETURNING id, project_id, name, position, created_at, updated_at
+            "#,
+        )
+        .bind(&self.name)
+        .bind(self.position)
+        .bind(Utc::now())
+        .bind(self.id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        *self = updated;
+        Ok(())
+    }
+
+
+    pub async fn delete(self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM project_pages WHERE id = $1")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use crate::whiteboard::WhiteBoardData;
+
+
+/// A project whose name matches a search.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ProjectHit {
+    id: i64,
+    name: String,
+}
+
+/// A text element that matches a search, with where to find it.
+#[derive(Debug, Serialize, FromRow)]
+pub struct TextHit {
+    project_id: i64,
+    project_name: String,
+    page_id: i64,
+    page_name: String,
+    element_id: String,
+    text: String,
+    x: f32,
+    y: f32,
+}
+
+
+/// Replaces the indexed texts of a page with the ones of `board`.
+pub async fn index_page(pool: &PgPool, project_id: i64, page_id: i64, board: &WhiteBoardData) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query("DELETE FROM board_texts WHERE page_id = $1")
+        .bind(page_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    for text in board.get_texts().iter() {
+        let (x, y) = text.get_position();
+        sqlx::query(
+            r#"
+            INSERT INTO board_texts (project_id, page_id, element_id, text, x, y)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (page_id, element_id) DO NOTHING
+            "#,
+        )
+        .bind(project_id)
+        .bind(page_id)
+        .bind(text.get_id())
+        .bind(text.get_text())
+        .bind(x)
+        .bind(y)
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+
+/// Projects the user owns or collaborates on whose name matches `query`,
+/// best matches first. `query` uses the web search syntax: words, "quoted
+/// phrases", `or` and `-excluded`.
+pub async fn search_projects(pool: &PgPool, user_id: i64, query: &str, limit: i64) -> Result<Vec<ProjectHit>, sqlx::Error> {
+    let hits = sqlx::query_as::<_, ProjectHit>(
+        r#"
+        SELECT projects.id, projects.name
+        FROM projects, websearch_to_tsquery('simple', $2) query
+        WHERE to_tsvector('simple', projects.name) @@ query
+            AND (
+                projects.owner_id = $1
+                OR EXISTS (
+                    SELECT 1 FROM projects_collaborators
+                    WHERE projects_collaborators.project_id = projects.id AND projects_collaborators.user_id = $1
+                )
+            )
+        ORDER BY ts_rank(to_tsvector('simple', projects.name), query) DESC, projects.id
+        LIMIT $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(query)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(hits)
+}
+
+/// Text elements matching `query` on the boards of projects the user owns
+/// or collaborates on, best matches first.
+pub async fn search_texts(pool: &PgPool, user_id: i64, query: &str, limit: i64) -> Result<Vec<TextHit>, sqlx::Error> {
+    let hits = sqlx::query_as::<_, TextHit>(
+        r#"
+        SELECT
+            board_texts.project_id, projects.name AS project_name,
+            board_texts.page_id, project_pages.name AS page_name,
+            board_texts.element_id, board_texts.text, board_texts.x, board_texts.y
+        FROM board_texts
+            JOIN projects ON projects.id = board_texts.project_id
+            JOIN project_pages ON project_pages.id = board_texts.page_id,
+            websearch_to_tsquery('simple', $2) query
+        WHERE board_texts.document @@ query
+            AND (
+                projects.owner_id = $1
+                OR EXISTS (
+                    SELECT 1 FROM projects_collaborators
+                    WHERE projects_collaborators.project_id = projects.id AND projects_collaborators.user_id = $1
+                )
+            )
+        ORDER BY ts_rank(board_texts.document, query) DESC, board_texts.page_id, board_texts.element_id
+        LIMIT $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(query)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(hits)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::Project;
+    use crate::project::page::Page;
+    use crate::user::User;
+    use crate::user::token::random_hex;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+
+    async fn get_test_pool() -> PgPool {
+        dotenv().ok();
+        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&db_url)
+            .await
+            .expect("Failed to connect to DB")
+    }
+
+    fn get_test_redis_client() -> redis::Client {
+        let redis_url = env::var("REDIS_CONNECTION_STRING").expect("REDIS_CONNECTION_STRING must be set");
+        return redis::Client::open(redis_url).expect("Invalid Redis connection string");
+    }
+
+    async fn create_user(pool: &PgPool) -> i64 {
+        let username = format!("search_{}", random_hex(6));
+        let mut user = User::create_new(
+            username.clone(),
+            "pass123".to_string(),
+            "Search".to_string(),
+            "Test".to_string(),
+            format!("{}@example.com", username),
+            false,
+            true,
+            false,
+        );
+        user.create_row(pool).await.unwrap();
+        return user.get_id().unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres and Redis"]
+    async fn test_search_only_finds_accessible_projects() {
+        let pool = get_test_pool().await;
+        let redis_cli = get_test_redis_client();
+        let owner_id = create_user(&pool).await;
+        let collaborator_id = create_user(&pool).await;
+        let stranger_id = create_user(&pool).await;
+
+        // A word no other test data contains
+        let word = format!("w{}", random_hex(8));
+        let mut project = Project::create_new(format!("Roadmap {}", word), owner_id);
+        project.create_row(&pool).await.unwrap();
+        let project_id = project.get_id().unwrap();
+        project.update_collaborators(&pool, &redis_cli, vec![collaborator_id]).await.unwrap();
+        let mut page = Page::create_new(project_id, "Page 1".to_string(), 0);
+        page.create_row(&pool).await.unwrap();
+        let page_id = page.get_id().unwrap();
+
+        let board: WhiteBoardData = serde_json::from_value(serde_json::json!({ "lines": [], "texts": [
+            { "id": "t", "x": 4.0, "y": 2.0, "text": format!("ship the {} release", word), "color": "#000000", "size": 12.0 }
+        ] })).unwrap();
+        index_page(&pool, project_id, page_id, &board).await.unwrap();
+
+        for user_id in [owner_id, collaborator_id] {
+            let projects = search_projects(&pool, user_id, &word, 20).await.unwrap();
+            assert_eq!(projects.iter().map(|p| p.id).collect::<Vec<i64>>(), vec![project_id]);
+            let texts = search_texts(&pool, user_id, &word, 20).await.unwrap();
+            assert_eq!(texts.len(), 1);
+            assert_eq!((texts[0].page_id, texts[0].element_id.as_str()), (page_id, "t"));
+        }
+
+        assert!(search_projects(&pool, stranger_id, &word, 20).await.unwrap().is_empty());
+        assert!(search_texts(&pool, stranger_id, &word, 20).await.unwrap().is_empty());
+
+        // Removed collaborators lose access to the texts as well
+        project.update_collaborators(&pool, &redis_cli, vec![]).await.unwrap();
+        assert!(search_texts(&pool, collaborator_id, &word, 20).await.unwrap().is_empty());
+    }
+}
@@ -1,4 +1,8 @@
 
+pub mod mailer;
+pub mod policy;
+pub mod token;
+
 use sqlx::FromRow;
 
 use chrono::{DateTime, Utc};
@@ -161,6 +165,26 @@ impl User {
     }
 
 
+    /// Emails are not unique, every account using the address is returned.
+    pub async fn get_by_email(pool: &PgPool, email: &str) -> Result<Vec<Self>, sqlx::Error> {
+
+        let users = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT id, password, last_login, is_superuser, username,
+                   first_name, last_name, email, is_staff, is_active,
+                   date_joined, created_at, updated_at
+            FROM users
+            WHERE lower(email) = lower($1)
+            "#,
+        )
+        .bind(email)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(users)
+    }
+
+
     pub async fn authenticate(pool: &PgPool, username: String, raw_password: String) -> Option<Self> {
         let user = Self::get_by_username(pool, username).await.unwrap();
         if user.is_none(){
@@ -193,6 +217,50 @@ impl User {
         &self.email
     }
 
+    pub fn is_active(&self) -> bool{
+        self.is_active
+    }
+
+    /// When the email address was verified, `None` until then.
+    pub async fn get_email_verified_at(&self, pool: &PgPool) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        let (verified_at,): (Option<DateTime<Utc>>,) = sqlx::query_as("SELECT email_verified_at FROM users WHERE id = $1")
+            .bind(self.id)
+            .fetch_one(pool)
+            .await?;
+        Ok(verified_at)
+    }
+
+    /// Records that the email address was verified. Verifying again keeps
+    /// the first time.
+    pub async fn mark_email_verified(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET email_verified_at = COALESCE(email_verified_at, $1) WHERE id = $2")
+            .bind(Utc::now())
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub fn get_last_login(&self) -> Option<DateTime<Utc>>{
+        self.last_login
+    }
+
+    pub fn get_date_joined(&self) -> DateTime<Utc>{
+        self.date_joined
+    }
+
+    /// Records a successful login, without touching the other fields.
+    pub async fn record_login(&mut self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let now = Utc::now();
+        sqlx::query("UPDATE users SET last_login = $1 WHERE id = $2")
+            .bind(now)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        self.last_login = Some(now);
+        Ok(())
+    }
+
     pub fn get_created_at(&self) -> DateTime<Utc>{
         self.created_at
     }
@@ -294,6 +362,7 @@ mod tests {
 
     use std::env;
     use sqlx::PgPool;
+    use sqlx::postgres::PgPoolOptions;
 
     #[test]
     fn test_hash_and_verify_password() {
@@ -386,4 +455,104 @@ mod tests {
         assert_eq!(fetched.first_name, "Updated");
         assert!(fetched.check_password("new_pass".to_string()));
     }
+
+    async fn create_test_user(pool: &PgPool) -> i64 {
+        let username = format!("token_{}", token::random_hex(6));
+        let mut user = User::create_new(
+            username.clone(),
+            "pass123".to_string(),
+            "Token".to_string(),
+            "Test".to_string(),
+            format!("{}@example.com", username),
+            false,
+            true,
+            false,
+        );
+        user.create_row(pool).await.unwrap();
+        return user.get_id().unwrap();
+    }
+
+    fn get_test_revocations() -> token::RevocationList {
+        dotenv().ok();
+        let redis_url = env::var("REDIS_CONNECTION_STRING").expect("REDIS_CONNECTION_STRING must be set");
+        let client = redis::Client::open(redis_url).expect("Invalid Redis connection string");
+        return token::RevocationList::new(std::sync::Arc::new(client));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis"]
+    async fn test_revoke_token() {
+        let revocations = get_test_revocations();
+        let user_id = i64::MAX;
+        let (jti, other) = (token::random_hex(16), token::random_hex(16));
+        let now = Utc::now().timestamp_millis();
+
+        revocations.revoke_token(&jti, 60).await.unwrap();
+        assert!(revocations.is_revoked(&jti, user_id, now).await.unwrap());
+        assert!(!revocations.is_revoked(&other, user_id, now).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis"]
+    async fn test_revoke_user_keeps_one_token() {
+        let revocations = get_test_revocations();
+        let user_id = -(rand_core::OsRng.next_u32() as i64) - 1;
+        let (kept, other) = (token::random_hex(16), token::random_hex(16));
+        let issued_at = Utc::now().timestamp_millis();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        revocations.revoke_user(user_id, 60, Some(&kept)).await.unwrap();
+        assert!(!revocations.is_revoked(&kept, user_id, issued_at).await.unwrap());
+        assert!(revocations.is_revoked(&other, user_id, issued_at).await.unwrap());
+
+        // Tokens issued afterwards, even within the same second, stay valid
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        let issued_at = Utc::now().timestamp_millis();
+        assert!(!revocations.is_revoked(&other, user_id, issued_at).await.unwrap());
+
+        // Without `keep` no earlier token survives
+        revocations.revoke_user(user_id, 60, None).await.unwrap();
+        assert!(revocations.is_revoked(&kept, user_id, issued_at).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres"]
+    async fn test_refresh_token_reuse_revokes_family() {
+        let pool = get_test_pool().await;
+        let user_id = create_test_user(&pool).await;
+        let first = token::RefreshToken::issue(&pool, user_id).await.unwrap();
+        let other_session = token::RefreshToken::issue(&pool, user_id).await.unwrap();
+
+        let (rotated_user, second) = token::RefreshToken::rotate(&pool, &first).await.unwrap();
+        assert_eq!(rotated_user, user_id);
+
+        // Replaying the first token revokes the second one too
+        assert!(matches!(token::RefreshToken::rotate(&pool, &first).await, Err(token::RefreshError::Reused)));
+        assert!(matches!(token::RefreshToken::rotate(&pool, &second).await, Err(token::RefreshError::Invalid)));
+        assert_eq!(token::RefreshToken::count_active(&pool, user_id).await.unwrap(), 1);
+        assert!(token::RefreshToken::rotate(&pool, &other_session).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres"]
+    async fn test_revoke_refresh_token_family() {
+        let pool = get_test_pool().await;
+        let user_id = create_test_user(&pool).await;
+        let stranger_id = create_test_user(&pool).await;
+        let first = token::RefreshToken::issue(&pool, user_id).await.unwrap();
+        let (_, second) = token::RefreshToken::rotate(&pool, &first).await.unwrap();
+        let other_session = token::RefreshToken::issue(&pool, user_id).await.unwrap();
+
+        // Tokens of other users are ignored
+        token::RefreshToken::revoke_family(&pool, stranger_id, &first).await.unwrap();
+        assert_eq!(token::RefreshToken::count_active(&pool, user_id).await.unwrap(), 2);
+
+        // A used token revokes the latest one of its family
+        token::RefreshToken::revoke_family(&pool, user_id, &first).await.unwrap();
+        assert!(matches!(token::RefreshToken::rotate(&pool, &second).await, Err(token::RefreshError::Invalid)));
+        assert_eq!(token::RefreshToken::count_active(&pool, user_id).await.unwrap(), 1);
+
+        token::RefreshToken::revoke_user(&pool, user_id).await.unwrap();
+        assert!(matches!(token::RefreshToken::rotate(&pool, &other_session).await, Err(token::RefreshError::Invalid)));
+    }
 }
@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+
+/// The policy new passwords are checked against, read from the environment:
+/// `AUTH_PASSWORD_MIN_LENGTH` (default 8), `AUTH_PASSWORD_MAX_LENGTH`
+/// (default 128) and `AUTH_BREACHED_PASSWORDS_FILE`, a list of known
+/// breached passwords, one per line.
+pub static PASSWORD_POLICY: LazyLock<PasswordPolicy> = LazyLock::new(|| {
+    PasswordPolicy::from_env().expect("password policy is invalid")
+});
+
+
+#[derive(Debug, PartialEq)]
+pub enum PasswordPolicyError {
+    TooShort(usize),
+    TooLong(usize),
+    Breached,
+}
+
+impl std::fmt::Display for PasswordPolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort(min) => write!(f, "Password must have at least {} characters", min),
+            Self::TooLong(max) => write!(f, "Password must have at most {} characters", max),
+            Self::Breached => write!(f, "Password appeared in a data breach, choose another one"),
+        }
+    }
+}
+
+
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    breached: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize, max_length: usize, breached: HashSet<String>) -> Self {
+        return Self { min_length, max_length, breached };
+    }
+
+    fn from_env() -> Result<Self, String> {
+        let min_length = std::env::var("AUTH_PASSWORD_MIN_LENGTH").ok().and_then(|v| v.parse().ok()).unwrap_or(8);
+        let max_length = std::env::var("AUTH_PASSWORD_MAX_LENGTH").ok().and_then(|v| v.parse().ok()).unwrap_or(128);
+        let breached = match std::env::var("AUTH_BREACHED_PASSWORDS_FILE") {
+            Ok(path) => {
+                let list = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("can't read {}: {}", path, e))?;
+                Self::parse_breached(&list)
+            }
+            Err(_) => HashSet::new(),
+        };
+        return Ok(Self::new(min_length, max_length, breached));
+    }
+
+    fn parse_breached(list: &str) -> HashSet<String> {
+        return list.lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect();
+    }
+
+    /// Lengths are counted in characters. Passwords are compared to the
+    /// breached list as they are, case included.
+    pub fn check(&self, password: &str) -> Result<(), PasswordPolicyError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(PasswordPolicyError::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(PasswordPolicyError::TooLong(self.max_length));
+        }
+        if self.breached.contains(password) {
+            return Err(PasswordPolicyError::Breached);
+        }
+        return Ok(());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_checks_length_and_breached_list() {
+        let breached = PasswordPolicy::parse_breached("password123\r\n\nletmein!!\n");
+        let policy = PasswordPolicy::new(8, 16, breached);
+
+        assert_eq!(policy.check("short"), Err(PasswordPolicyError::TooShort(8)));
+        assert_eq!(policy.check("a very long passphrase"), Err(PasswordPolicyError::TooLong(16)));
+        assert_eq!(policy.check("password123"), Err(PasswordPolicyError::Breached));
+        assert_eq!(policy.check("letmein!!"), Err(PasswordPolicyError::Breached));
+        assert!(policy.check("Password123").is_ok());
+        // Characters, not bytes
+        assert!(policy.check("ßßßßßßßß").is_ok());
+    }
+}
@@ -0,0 +1,353 @@
+use chrono::{DateTime, Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use redis::{AsyncCommands, Client};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use std::sync::{Arc, LazyLock};
+
+
+/// `AUTH_REFRESH_TOKEN_TTL`, seconds a refresh token can be used, default 30
+/// days.
+pub static REFRESH_TOKEN_TTL: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("AUTH_REFRESH_TOKEN_TTL").ok().and_then(|v| v.parse().ok()).unwrap_or(30 * 24 * 3600)
+});
+
+/// `AUTH_VERIFY_EMAIL_TTL`, seconds an email verification link works,
+/// default 2 days.
+pub static VERIFY_EMAIL_TTL: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("AUTH_VERIFY_EMAIL_TTL").ok().and_then(|v| v.parse().ok()).unwrap_or(2 * 24 * 3600)
+});
+
+/// `AUTH_PASSWORD_RESET_TTL`, seconds a password reset link works, default
+/// 1 hour.
+pub static PASSWORD_RESET_TTL: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("AUTH_PASSWORD_RESET_TTL").ok().and_then(|v| v.parse().ok()).unwrap_or(3600)
+});
+
+/// Random bytes in a refresh or account token, sent to clients hex encoded.
+const TOKEN_BYTES: usize = 32;
+const FAMILY_BYTES: usize = 16;
+
+
+pub fn random_hex(length: usize) -> String {
+    let mut bytes = vec![0u8; length];
+    OsRng.fill_bytes(&mut bytes);
+    return hex::encode(bytes);
+}
+
+/// Tokens are random, so a plain hash is enough to keep a database leak
+/// from exposing usable ones.
+fn hash_token(token: &str) -> String {
+    return hex::encode(Sha256::digest(token.as_bytes()));
+}
+
+
+#[derive(Debug)]
+pub enum RefreshError {
+    /// Unknown, expired or revoked.
+    Invalid,
+    /// Already replaced by a newer token, its family is revoked now.
+    Reused,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RefreshError {
+    fn from(value: sqlx::Error) -> Self {
+        Self::Database(value)
+    }
+}
+
+
+/// An opaque token that gets a new access token once. Tokens issued from
+/// one login form a family.
+#[derive(Debug, FromRow)]
+pub struct RefreshToken {
+    id: i64,
+    user_id: i64,
+    family_id: String,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl RefreshToken {
+    /// Starts a family for a new login and returns its first token.
+    pub async fn issue(pool: &PgPool, user_id: i64) -> Result<String, sqlx::Error> {
+        let token = random_hex(TOKEN_BYTES);
+        Self::insert(pool, user_id, &random_hex(FAMILY_BYTES), &token).await?;
+        return Ok(token);
+    }
+
+    async fn insert<'e, E>(executor: E, user_id: i64, family_id: &str, token: &str) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        sqlx::query(
+            r#"
+            INSERT INTO refresh_tokens (user_id, family_id, token_hash, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(user_id)
+        .bind(family_id)
+        .bind(hash_token(token))
+        .bind(Utc::now())
+        .bind(Utc::now() + Duration::seconds(*REFRESH_TOKEN_TTL))
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces a token with a new one of the same family and returns the
+    /// user it belongs to with the new token. A token that was already
+    /// replaced has leaked or been stolen: the whole family is revoked, so
+    /// whoever holds its latest token has to log in again.
+    pub async fn rotate(pool: &PgPool, token: &str) -> Result<(i64, String), RefreshError> {
+        let mut transaction = pool.begin().await?;
+
+        let stored = sqlx::query_as::<_, Self>(
+            r#"
+            SELECT id, user_id, family_id, expires_at, used_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        let stored = match stored {
+            Some(stored) if stored.revoked_at.is_none() => stored,
+            _ => return Err(RefreshError::Invalid),
+        };
+
+        if stored.used_at.is_some() {
+            sqlx::query("UPDATE refresh_tokens SET revoked_at = $1 WHERE family_id = $2 AND revoked_at IS NULL")
+                .bind(Utc::now())
+                .bind(&stored.family_id)
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
+            println!("Refresh token reused, token family of user {} revoked", stored.user_id);
+            return Err(RefreshError::Reused);
+        }
+        if stored.expires_at <= Utc::now() {
+            return Err(RefreshError::Invalid);
+        }
+
+        sqlx::query("UPDATE refresh_tokens SET used_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(stored.id)
+            .execute(&mut *transaction)
+            .await?;
+        let next = random_hex(TOKEN_BYTES);
+        Self::insert(&mut *transaction, stored.user_id, &stored.family_id, &next).await?;
+        transaction.commit().await?;
+
+        return Ok((stored.user_id, next));
+    }
+
+    /// Revokes the family of a token of the user, used or not. Unknown
+    /// tokens and tokens of other users are ignored.
+    pub async fn revoke_family(pool: &PgPool, user_id: i64, token: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens SET revoked_at = $1
+            WHERE revoked_at IS NULL AND family_id = (
+                SELECT family_id FROM refresh_tokens WHERE token_hash = $2 AND user_id = $3
+            )
+            "#,
+        )
+        .bind(Utc::now())
+        .bind(hash_token(token))
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Number of sessions of the user that can still be refreshed.
+    pub async fn count_active(pool: &PgPool, user_id: i64) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM refresh_tokens
+            WHERE user_id = $1 AND used_at IS NULL AND revoked_at IS NULL AND expires_at > $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(Utc::now())
+        .fetch_one(pool)
+        .await?;
+        Ok(count)
+    }
+
+    /// Revokes every refresh token of the user.
+    pub async fn revoke_user(pool: &PgPool, user_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE refresh_tokens SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL")
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+
+/// What an account token can be used for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccountTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl AccountTokenPurpose {
+    fn as_str(&self) -> &'static str {
+        return match self {
+            Self::VerifyEmail => "verify_email",
+            Self::ResetPassword => "reset_password",
+        };
+    }
+
+    fn get_ttl(&self) -> i64 {
+        return match self {
+            Self::VerifyEmail => *VERIFY_EMAIL_TTL,
+            Self::ResetPassword => *PASSWORD_RESET_TTL,
+        };
+    }
+}
+
+
+/// A single-use token mailed to a user, that proves they can read the mail
+/// of their account.
+pub struct AccountToken;
+
+impl AccountToken {
+    /// Returns a new token for the user, and invalidates the unused ones
+    /// issued before for the same purpose.
+    pub async fn issue(pool: &PgPool, user_id: i64, purpose: AccountTokenPurpose) -> Result<String, sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+
+        sqlx::query("UPDATE account_tokens SET used_at = $1 WHERE user_id = $2 AND purpose = $3 AND used_at IS NULL")
+            .bind(Utc::now())
+            .bind(user_id)
+            .bind(purpose.as_str())
+            .execute(&mut *transaction)
+            .await?;
+
+        let token = random_hex(TOKEN_BYTES);
+        sqlx::query(
+            r#"
+            INSERT INTO account_tokens (user_id, purpose, token_hash, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(user_id)
+        .bind(purpose.as_str())
+        .bind(hash_token(&token))
+        .bind(Utc::now())
+        .bind(Utc::now() + Duration::seconds(purpose.get_ttl()))
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+        return Ok(token);
+    }
+
+    /// Uses up a token and returns the user it was issued to, `None` when
+    /// it is unknown, expired, already used or for another purpose.
+    pub async fn redeem(pool: &PgPool, token: &str, purpose: AccountTokenPurpose) -> Result<Option<i64>, sqlx::Error> {
+        let user_id: Option<(i64,)> = sqlx::query_as(
+            r#"
+            UPDATE account_tokens SET used_at = $1
+            WHERE token_hash = $2 AND purpose = $3 AND used_at IS NULL AND expires_at > $1
+            RETURNING user_id
+            "#,
+        )
+        .bind(Utc::now())
+        .bind(hash_token(token))
+        .bind(purpose.as_str())
+        .fetch_optional(pool)
+        .await?;
+
+        return Ok(user_id.map(|(user_id,)| user_id));
+    }
+}
+
+
+/// Access tokens revoked before they expire, kept in Redis until they would
+/// have expired anyway. A token is revoked on its own by its `jti`, or with
+/// every other token of its user issued up to a given millisecond.
+pub struct RevocationList {
+    redis_cli: Arc<Client>,
+}
+
+impl RevocationList {
+    pub fn new(redis_cli: Arc<Client>) -> Self {
+        return Self { redis_cli };
+    }
+
+    fn get_token_key(jti: &str) -> String {
+        return format!("auth_revoked_token:{}", jti);
+    }
+
+    fn get_user_key(user_id: i64) -> String {
+        return format!("auth_revoked_before_ms:{}", user_id);
+    }
+
+    fn get_kept_key(user_id: i64) -> String {
+        return format!("auth_revoked_before_kept:{}", user_id);
+    }
+
+    /// `ttl` is the number of seconds the token has left.
+    pub async fn revoke_token(&self, jti: &str, ttl: i64) -> redis::RedisResult<()> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        return con.set_ex(Self::get_token_key(jti), 1, ttl.max(1) as u64).await;
+    }
+
+    /// Revokes every token of the user issued so far, except the one with
+    /// the `keep` jti. `ttl` is the lifetime of the longest lived token.
+    pub async fn revoke_user(&self, user_id: i64, ttl: i64, keep: Option<&str>) -> redis::RedisResult<()> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let ttl = ttl.max(1) as u64;
+        let mut pipe = redis::pipe();
+        pipe.atomic().set_ex(Self::get_user_key(user_id), Utc::now().timestamp_millis(), ttl);
+        match keep {
+            Some(jti) => pipe.set_ex(Self::get_kept_key(user_id), jti, ttl),
+            None => pipe.del(Self::get_kept_key(user_id)),
+        };
+        return pipe.query_async(&mut con).await;
+    }
+
+    /// `issued_at_ms` is in milliseconds, so a token issued right after a
+    /// revocation in the same second stays valid.
+    pub async fn is_revoked(&self, jti: &str, user_id: i64, issued_at_ms: i64) -> redis::RedisResult<bool> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let (token, before, kept): (Option<i64>, Option<i64>, Option<String>) = redis::pipe()
+            .get(Self::get_token_key(jti))
+            .get(Self::get_user_key(user_id))
+            .get(Self::get_kept_key(user_id))
+            .query_async(&mut con)
+            .await?;
+        let revoked_with_user = before.is_some_and(|before| issued_at_ms <= before) && kept.as_deref() != Some(jti);
+        return Ok(token.is_some() || revoked_with_user);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_random_and_stored_hashed() {
+        let token = random_hex(TOKEN_BYTES);
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+        assert_ne!(token, random_hex(TOKEN_BYTES));
+
+        let hash = hash_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token(&token));
+        assert_ne!(hash, token);
+    }
+}
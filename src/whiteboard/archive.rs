@@ -0,0 +1,333 @@
+use base64::{ engine::general_purpose::STANDARD as BASE64, Engine };
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+use std::collections::HashSet;
+use std::fmt::Display;
+use super::WhiteBoardData;
+use super::settings::BoardSettings;
+use super::storage::migration::{ self, MigrationContext, CURRENT_SCHEMA_VERSION };
+use super::validation::{ validate_board, validate_settings, BoardLimits };
+
+/// Value of `format`, telling archives apart from any other JSON.
+pub const ARCHIVE_FORMAT: &str = "whiteboard-archive";
+/// Version of the archive layout itself. Boards inside are versioned
+/// separately by `schema_version`.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const MAX_NAME_LENGTH: usize = 200;
+const MAX_PAGES: usize = 100;
+const MAX_ASSET_BYTES: usize = 10 * 1024 * 1024;
+const ASSET_MIME_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+
+/// A whole project in one self-describing JSON document, used to move
+/// projects between servers and to back them up.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Archive {
+    format: String,
+    archive_version: u32,
+    /// Schema version of the boards in `pages`. Older boards are migrated on
+    /// import like stored ones.
+    schema_version: u32,
+    exported_at: DateTime<Utc>,
+    project: ArchiveProject,
+    pages: Vec<ArchivePage>,
+    #[serde(default)]
+    assets: Vec<ArchiveAsset>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveProject {
+    name: String,
+    /// Board settings of every page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    settings: Option<Value>,
+}
+
+/// One page, with its board in the stored JSON form.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ArchivePage {
+    name: String,
+    position: i32,
+    data: Value,
+    /// Written when settings were kept per page. The first page's apply to
+    /// the project when the archive has no project settings.
+    #[serde(default, skip_serializing)]
+    settings: Option<Value>,
+}
+
+/// A binary file elements refer to by `id`, embedded as base64.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveAsset {
+    id: String,
+    mime_type: String,
+    data: String,
+}
+
+/// A page read from an archive, validated and ready to be stored.
+pub struct ImportedPage {
+    pub name: String,
+    pub position: i32,
+    pub data: WhiteBoardData,
+}
+
+pub struct ImportedProject {
+    pub name: String,
+    pub settings: BoardSettings,
+    pub pages: Vec<ImportedPage>,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum ArchiveError {
+    Malformed(String),
+    UnsupportedFormat,
+    UnsupportedVersion { found: u32 },
+    InvalidName(String),
+    PageCount { count: usize },
+    InvalidSettings(String),
+    InvalidPage { page: String, reason: String },
+    InvalidAsset { asset: String, reason: String },
+}
+
+impl Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(reason) =>
+                write!(f, "malformed archive: {}", reason),
+            Self::UnsupportedFormat =>
+                write!(f, "not a whiteboard archive, 'format' must be '{}'", ARCHIVE_FORMAT),
+            Self::UnsupportedVersion { found } =>
+                write!(f, "archive version {} is not supported, expected {}", found, ARCHIVE_VERSION),
+            Self::InvalidName(name) =>
+                write!(f, "name '{}' must be between 1 and {} characters", name, MAX_NAME_LENGTH),
+            Self::PageCount { count } =>
+                write!(f, "archive has {} pages, expected between 1 and {}", count, MAX_PAGES),
+            Self::InvalidSettings(reason) =>
+                write!(f, "settings: {}", reason),
+            Self::InvalidPage { page, reason } =>
+                write!(f, "page '{}': {}", page, reason),
+            Self::InvalidAsset { asset, reason } =>
+                write!(f, "asset '{}': {}", asset, reason),
+        }
+    }
+}
+
+
+fn check_name(name: &str) -> Result<String, ArchiveError> {
+    let trimmed = name.trim();
+    if trimmed.is_empty() || trimmed.chars().count() > MAX_NAME_LENGTH {
+        return Err(ArchiveError::InvalidName(name.to_string()));
+    }
+    return Ok(trimmed.to_string());
+}
+
+/// Element ids must be unique across lines, texts, frames and groups, and
+/// groups may only contain elements of the same page.
+fn check_references(board: &WhiteBoardData) -> Result<(), String> {
+    let mut ids = HashSet::new();
+    let all_ids = board.lines.iter().map(|l| &l.id)
+        .chain(board.texts.iter().map(|t| &t.id))
+        .chain(board.frames.iter().map(|f| &f.id))
+        .chain(board.groups.iter().map(|g| &g.id));
+    for id in all_ids {
+        if id.is_empty() {
+            return Err("an element has no id".to_string());
+        }
+        if !ids.insert(id.as_str()) {
+            return Err(format!("element id '{}' is used more than once", id));
+        }
+    }
+    for group in board.groups.iter() {
+        if let Some(child) = group.children.iter().find(|c| !ids.contains(c.as_str())) {
+            return Err(format!("group '{}' contains unknown element '{}'", group.id, child));
+        }
+    }
+    return Ok(());
+}
+
+fn check_asset(asset: &ArchiveAsset, referenced: &HashSet<String>) -> Result<(), String> {
+    if asset.id.is_empty() {
+        return Err("asset has no id".to_string());
+    }
+    if !ASSET_MIME_TYPES.contains(&asset.mime_type.as_str()) {
+        return Err(format!("unsupported type '{}'", asset.mime_type));
+    }
+    let bytes = BASE64.decode(&asset.data).map_err(|e| format!("invalid base64: {}", e))?;
+    if bytes.len() > MAX_ASSET_BYTES {
+        return Err(format!("larger than {} bytes", MAX_ASSET_BYTES));
+    }
+    // Unreferenced files would only be carried along and never shown
+    if !referenced.contains(&asset.id) {
+        return Err("not referenced by any element".to_string());
+    }
+    return Ok(());
+}
+
+
+impl Archive {
+    pub fn new(project_name: &str, settings: &BoardSettings, pages: Vec<(String, i32, WhiteBoardData)>) -> Self {
+        return Self {
+            format: ARCHIVE_FORMAT.to_string(),
+            archive_version: ARCHIVE_VERSION,
+            schema_version: CURRENT_SCHEMA_VERSION,
+            exported_at: Utc::now(),
+            project: ArchiveProject {
+                name: project_name.to_string(),
+                settings: Some(serde_json::to_value(settings).unwrap()),
+            },
+            pages: pages.into_iter()
+                .map(|(name, position, data)| ArchivePage {
+                    name,
+                    position,
+                    data: serde_json::to_value(data).unwrap(),
+                    settings: None,
+                })
+                .collect(),
+            // No element refers to files yet
+            assets: Vec::new(),
+        };
+    }
+
+    /// Reads an archive and checks everything in it before anything is
+    /// created: the format, every name, every board and setting against
+    /// `limits`, element references and assets. Any problem rejects the
+    /// whole archive.
+    pub fn import(bytes: &[u8], limits: &BoardLimits) -> Result<ImportedProject, ArchiveError> {
+        let raw: Value = serde_json::from_slice(bytes).map_err(|e| ArchiveError::Malformed(e.to_string()))?;
+        // Checked before the full parse so that other JSON gets a clear error
+        if raw.get("format").and_then(|f| f.as_str()) != Some(ARCHIVE_FORMAT) {
+            return Err(ArchiveError::UnsupportedFormat);
+        }
+        let archive: Archive = serde_json::from_value(raw).map_err(|e| ArchiveError::Malformed(e.to_string()))?;
+        if archive.archive_version != ARCHIVE_VERSION {
+            return Err(ArchiveError::UnsupportedVersion { found: archive.archive_version });
+        }
+
+        let name = check_name(&archive.project.name)?;
+        if archive.pages.is_empty() || archive.pages.len() > MAX_PAGES {
+            return Err(ArchiveError::PageCount { count: archive.pages.len() });
+        }
+
+        let mut settings = archive.project.settings
+            .or_else(|| archive.pages.iter().find_map(|page| page.settings.clone()))
+            .map(|settings| serde_json::from_value::<BoardSettings>(settings))
+            .transpose()
+            .map_err(|e| ArchiveError::InvalidSettings(e.to_string()))?
+            .unwrap_or_default();
+        validate_settings(&mut settings, limits).map_err(|e| ArchiveError::InvalidSettings(e.to_string()))?;
+
+        let mut pages = Vec::new();
+        for page in archive.pages {
+            let page_name = check_name(&page.name)?;
+            let invalid = |reason: String| ArchiveError::InvalidPage { page: page_name.clone(), reason };
+
+            let document = json!({
+                "schema_version": archive.schema_version,
+                "project_id": 0,
+                "page_id": 0,
+                "data": page.data,
+            });
+            let document = migration::migrate(document, &MigrationContext::new(0, 0))
+                .map_err(|e| invalid(e.to_string()))?;
+
+            let mut data: WhiteBoardData = serde_json::from_value(document["data"].clone())
+                .map_err(|e| invalid(e.to_string()))?;
+            data.unpack_points();
+            validate_board(&mut data, limits).map_err(|e| invalid(e.to_string()))?;
+            check_references(&data).map_err(invalid)?;
+
+            pages.push(ImportedPage { name: page_name, position: page.position, data });
+        }
+
+        // No element type refers to assets yet, so any asset is unreferenced
+        let referenced = HashSet::new();
+        let mut asset_ids = HashSet::new();
+        for asset in archive.assets.iter() {
+            let invalid = |reason: String| ArchiveError::InvalidAsset { asset: asset.id.clone(), reason };
+            check_asset(asset, &referenced).map_err(invalid)?;
+            if !asset_ids.insert(&asset.id) {
+                return Err(invalid("id is used more than once".to_string()));
+            }
+        }
+
+        return Ok(ImportedProject { name, settings, pages });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> WhiteBoardData {
+        serde_json::from_str(r##"{"lines":[{"id":"a","p":[[0,0],[10,10]],"c":"#000000","w":2}],
+            "groups":[{"id":"g","children":["a"]}]}"##).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let settings: BoardSettings = serde_json::from_str(r#"{"grid":"dots"}"#).unwrap();
+        let archive = Archive::new("Staging", &settings, vec![("Page 1".to_string(), 0, board())]);
+        let bytes = serde_json::to_vec(&archive).unwrap();
+
+        let imported = Archive::import(&bytes, &BoardLimits::default()).unwrap();
+        assert_eq!(imported.name, "Staging");
+        assert_eq!(imported.pages.len(), 1);
+        assert_eq!(imported.pages[0].data.lines[0].id, "a");
+        assert_eq!(imported.settings, settings);
+    }
+
+    #[test]
+    fn test_page_settings_of_older_archives_apply_to_the_project() {
+        let archive = json!({
+            "format": ARCHIVE_FORMAT, "archive_version": 1, "schema_version": 5,
+            "exported_at": "2024-01-01T00:00:00Z", "project": {"name": "Old"},
+            "pages": [{"name": "Page 1", "position": 0, "data": {"lines": []}, "settings": {"background": "#FDF6E3"}}]
+        });
+        let imported = Archive::import(archive.to_string().as_bytes(), &BoardLimits::default()).unwrap();
+        assert_eq!(imported.settings.get_background(), "#fdf6e3");
+
+        let mut invalid = archive.clone();
+        invalid["pages"][0]["settings"]["grid_spacing"] = json!(-1.0);
+        assert!(matches!(
+            Archive::import(invalid.to_string().as_bytes(), &BoardLimits::default()),
+            Err(ArchiveError::InvalidSettings(_))
+        ));
+    }
+
+    #[test]
+    fn test_old_boards_are_migrated_and_bad_archives_rejected() {
+        let v1 = json!({
+            "format": ARCHIVE_FORMAT, "archive_version": 1, "schema_version": 1,
+            "exported_at": "2024-01-01T00:00:00Z", "project": {"name": "Old"},
+            "pages": [{"name": "Page 1", "position": 0,
+                "data": {"lines": [{"p": [[0, 0], [1, 1]], "c": "red", "w": 2}], "cursorPosition": null}}]
+        });
+        let imported = Archive::import(v1.to_string().as_bytes(), &BoardLimits::default()).unwrap();
+        let line = &imported.pages[0].data.lines[0];
+        assert!(!line.id.is_empty());
+        assert_eq!(line.color, "#ff0000");
+
+        let limits = BoardLimits::default();
+        let mut unknown_field = v1.clone();
+        unknown_field["extra"] = json!(true);
+        assert!(matches!(Archive::import(unknown_field.to_string().as_bytes(), &limits), Err(ArchiveError::Malformed(_))));
+
+        let mut dangling = v1.clone();
+        dangling["pages"][0]["data"]["groups"] = json!([{"id": "g", "children": ["missing"]}]);
+        dangling["pages"][0]["data"]["lines"][0]["id"] = json!("a");
+        assert!(matches!(Archive::import(dangling.to_string().as_bytes(), &limits), Err(ArchiveError::InvalidPage { .. })));
+
+        let mut asset = v1.clone();
+        asset["assets"] = json!([{"id": "img", "mime_type": "image/png", "data": "aGVsbG8="}]);
+        assert!(matches!(Archive::import(asset.to_string().as_bytes(), &limits), Err(ArchiveError::InvalidAsset { .. })));
+
+        assert_eq!(Archive::import(br#"{"lines": []}"#, &limits).err(), Some(ArchiveError::UnsupportedFormat));
+    }
+}
@@ -0,0 +1,205 @@
+use serde::{ Deserialize, Serialize };
+use std::collections::{ HashMap, HashSet };
+use super::{ Frame, Group, Line, Text, WhiteBoardData };
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ElementKind {
+    Line,
+    Text,
+    Frame,
+    Group,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ElementChange {
+    pub id: String,
+    pub kind: ElementKind,
+}
+
+/// Elements that differ between two versions of a board, matched by id.
+/// Added and modified elements are in the order of the newer board, removed
+/// ones in the order of the older one.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct BoardDiff {
+    pub added: Vec<ElementChange>,
+    pub removed: Vec<ElementChange>,
+    pub modified: Vec<ElementChange>,
+}
+
+impl BoardDiff {
+    /// Ids of the elements the newer board drew differently, or did not
+    /// have at all: what an overlay highlights.
+    pub fn get_changed_ids(&self) -> HashSet<&str> {
+        return self.added.iter()
+            .chain(self.removed.iter())
+            .chain(self.modified.iter())
+            .map(|change| change.id.as_str())
+            .collect();
+    }
+
+    fn compare<T: PartialEq>(&mut self, kind: ElementKind, before: &[T], after: &[T], get_id: fn(&T) -> &str) {
+        let old: HashMap<&str, &T> = before.iter().map(|e| (get_id(e), e)).collect();
+        let new: HashSet<&str> = after.iter().map(get_id).collect();
+
+        for element in after.iter() {
+            let id = get_id(element);
+            match old.get(id) {
+                None => self.added.push(ElementChange { id: id.to_string(), kind }),
+                Some(previous) if *previous != element => self.modified.push(ElementChange { id: id.to_string(), kind }),
+                Some(_) => {}
+            }
+        }
+        for element in before.iter() {
+            let id = get_id(element);
+            if !new.contains(id) {
+                self.removed.push(ElementChange { id: id.to_string(), kind });
+            }
+        }
+    }
+}
+
+/// Compares every line, text, frame and group of two boards. An element
+/// counts as modified when any of its fields changed, its points included.
+pub fn diff_boards(before: &WhiteBoardData, after: &WhiteBoardData) -> BoardDiff {
+    let mut diff = BoardDiff::default();
+    diff.compare(ElementKind::Line, &before.lines, &after.lines, |line| &line.id);
+    diff.compare(ElementKind::Text, &before.texts, &after.texts, |text| &text.id);
+    diff.compare(ElementKind::Frame, &before.frames, &after.frames, |frame| &frame.id);
+    diff.compare(ElementKind::Group, &before.groups, &after.groups, |group| &group.id);
+    return diff;
+}
+
+
+/// An element of a delta: the id of an element the older board has
+/// unchanged, or the element itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum DeltaItem<T> {
+    Kept(String),
+    Changed(T),
+}
+
+/// A newer version of a board written against an older one, what the op
+/// log keeps for drawing updates instead of the whole board. Each list is
+/// the newer one in order, with the elements the older board already had
+/// referenced by id; elements missing from it are removed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct BoardDelta {
+    lines: Vec<DeltaItem<Line>>,
+    #[serde(default)]
+    groups: Vec<DeltaItem<Group>>,
+    #[serde(default)]
+    frames: Vec<DeltaItem<Frame>>,
+    #[serde(default)]
+    texts: Vec<DeltaItem<Text>>,
+}
+
+// Ids can repeat, lines saved before elements had ids all have an empty
+// one. Only the first element with an id can be referenced, every other
+// one is written out.
+fn delta_list<T: Clone + PartialEq>(before: &[T], after: &[T], get_id: fn(&T) -> &str) -> Vec<DeltaItem<T>> {
+    let mut old: HashMap<&str, &T> = HashMap::new();
+    for element in before.iter().rev() {
+        old.insert(get_id(element), element);
+    }
+    return after.iter()
+        .map(|element| match old.get(get_id(element)) {
+            Some(previous) if *previous == element => DeltaItem::Kept(get_id(element).to_string()),
+            _ => DeltaItem::Changed(element.clone()),
+        })
+        .collect();
+}
+
+fn apply_list<T: Clone>(board: &mut Vec<T>, delta: &[DeltaItem<T>], get_id: fn(&T) -> &str) {
+    let mut old: HashMap<&str, &T> = HashMap::new();
+    for element in board.iter().rev() {
+        old.insert(get_id(element), element);
+    }
+    let list = delta.iter()
+        .filter_map(|item| match item {
+            // Missing when the older board was not logged either
+            DeltaItem::Kept(id) => old.get(id.as_str()).map(|element| (*element).clone()),
+            DeltaItem::Changed(element) => Some(element.clone()),
+        })
+        .collect();
+    *board = list;
+}
+
+impl BoardDelta {
+    /// Turns the board the delta was made against into the newer one.
+    pub fn apply_to(&self, board: &mut WhiteBoardData) {
+        apply_list(&mut board.lines, &self.lines, |line| &line.id);
+        apply_list(&mut board.groups, &self.groups, |group| &group.id);
+        apply_list(&mut board.frames, &self.frames, |frame| &frame.id);
+        apply_list(&mut board.texts, &self.texts, |text| &text.id);
+    }
+}
+
+/// The delta that turns `before` into `after`, both with their points
+/// unpacked.
+pub fn delta_boards(before: &WhiteBoardData, after: &WhiteBoardData) -> BoardDelta {
+    return BoardDelta {
+        lines: delta_list(&before.lines, &after.lines, |line| &line.id),
+        groups: delta_list(&before.groups, &after.groups, |group| &group.id),
+        frames: delta_list(&before.frames, &after.frames, |frame| &frame.id),
+        texts: delta_list(&before.texts, &after.texts, |text| &text.id),
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_reports_changes_by_id() {
+        let before: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[10,0]],"c":"#000000","w":2},
+            {"id":"b","p":[[0,5],[10,5]],"c":"#000000","w":2},
+            {"id":"c","p":[[0,9],[10,9]],"c":"#000000","w":2}
+        ],"groups":[{"id":"g","children":["a","b"]}]}"##).unwrap();
+        let after: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[10,0]],"c":"#000000","w":2},
+            {"id":"c","p":[[0,9],[10,9]],"c":"#ff0000","w":2},
+            {"id":"d","p":[[0,12],[10,12]],"c":"#000000","w":2}
+        ],"groups":[{"id":"g","children":["a"]}],
+        "texts":[{"id":"t","x":0,"y":0,"text":"hi","color":"#000000","size":12}]}"##).unwrap();
+
+        let change = |id: &str, kind| ElementChange { id: id.to_string(), kind };
+        let diff = diff_boards(&before, &after);
+        assert_eq!(diff.added, vec![change("d", ElementKind::Line), change("t", ElementKind::Text)]);
+        assert_eq!(diff.removed, vec![change("b", ElementKind::Line)]);
+        assert_eq!(diff.modified, vec![change("c", ElementKind::Line), change("g", ElementKind::Group)]);
+        assert_eq!(diff_boards(&after, &after), BoardDiff::default());
+    }
+
+    #[test]
+    fn test_delta_rebuilds_the_newer_board() {
+        let before: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"p":[[0,0],[10,0]],"c":"#000000","w":2},
+            {"p":[[0,3],[10,3]],"c":"#000000","w":2},
+            {"id":"a","p":[[0,5],[10,5]],"c":"#000000","w":2},
+            {"id":"b","p":[[0,9],[10,9]],"c":"#000000","w":2}
+        ],"groups":[{"id":"g","children":["a","b"]}]}"##).unwrap();
+        // Reordered, one line changed, one removed and one added
+        let after: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"c","p":[[0,12],[10,12]],"c":"#000000","w":2},
+            {"id":"b","p":[[0,9],[10,9]],"c":"#000000","w":2},
+            {"p":[[0,0],[10,0]],"c":"#000000","w":2},
+            {"p":[[0,3],[10,3]],"c":"#000000","w":2},
+            {"id":"a","p":[[0,5],[10,5]],"c":"#ff0000","w":2}
+        ],"groups":[{"id":"g","children":["a","b"]}]}"##).unwrap();
+
+        let delta = delta_boards(&before, &after);
+        let kept = delta.lines.iter().filter(|item| matches!(item, DeltaItem::Kept(_))).count();
+        assert_eq!(kept, 2);
+        assert_eq!(delta.groups, vec![DeltaItem::Kept("g".to_string())]);
+
+        // As it is read back from the op log
+        let delta: BoardDelta = serde_json::from_value(serde_json::to_value(&delta).unwrap()).unwrap();
+        let mut board = before.clone();
+        delta.apply_to(&mut board);
+        assert_eq!(serde_json::to_value(&board).unwrap(), serde_json::to_value(&after).unwrap());
+    }
+}
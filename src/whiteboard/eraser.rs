@@ -0,0 +1,392 @@
+use serde::{ Deserialize, Serialize };
+use std::collections::HashSet;
+use rstar::AABB;
+use super::{ new_element_id, Line, Point, WhiteBoardData };
+use super::live::LiveBoard;
+use super::simplify::distance_to_segment;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EraseMode {
+    /// Removes every stroke the eraser touches.
+    Stroke,
+    /// Removes only the touched part of a stroke, splitting it into fragments.
+    Partial,
+}
+
+/// One line changed by an eraser. `fragments` take its place in drawing
+/// order and in its groups; they are empty when the whole line is gone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErasedElement {
+    id: String,
+    fragments: Vec<Line>,
+}
+
+/// The result of an erase, as broadcast to clients and applied to storage.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EraseOps {
+    erased: Vec<ErasedElement>,
+}
+
+impl EraseOps {
+    pub fn is_empty(&self) -> bool {
+        return self.erased.is_empty();
+    }
+
+    pub(super) fn fragments_mut(&mut self) -> impl Iterator<Item = &mut Line> {
+        return self.erased.iter_mut().flat_map(|e| e.fragments.iter_mut());
+    }
+}
+
+/// The lines an erase changed, as they were before it. Kept in the op log so
+/// that the erase can be undone.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EraseUndo {
+    lines: Vec<Line>,
+}
+
+
+fn distance_to_path(point: Point, path: &[Point]) -> f32 {
+    if path.len() == 1 {
+        return distance_to_segment(point, path[0], path[0]);
+    }
+    return path.windows(2)
+        .map(|segment| distance_to_segment(point, segment[0], segment[1]))
+        .fold(f32::INFINITY, f32::min);
+}
+
+fn cross(o: Point, a: Point, b: Point) -> f32 {
+    return (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+}
+
+fn segments_distance(a: Point, b: Point, c: Point, d: Point) -> f32 {
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return 0.0;
+    }
+    return distance_to_segment(a, c, d)
+        .min(distance_to_segment(b, c, d))
+        .min(distance_to_segment(c, a, b))
+        .min(distance_to_segment(d, a, b));
+}
+
+/// Segments of a path; a single point becomes a zero-length segment.
+fn segments(points: &[Point]) -> Vec<(Point, Point)> {
+    if points.len() == 1 {
+        return vec![(points[0], points[0])];
+    }
+    return points.windows(2).map(|s| (s[0], s[1])).collect();
+}
+
+fn stroke_touched(line: &Line, path: &[Point], radius: f32) -> bool {
+    let reach = radius + line.width as f32 / 2.0;
+    let eraser = segments(path);
+    return segments(&line.points).iter().any(|(a, b)| {
+        eraser.iter().any(|(c, d)| segments_distance(*a, *b, *c, *d) <= reach)
+    });
+}
+
+/// The part of the segment `a`-`b`, as a range of `t` in `[0, 1]`, that lies
+/// within `reach` of the segment `c`-`d`. That area is convex, the union of a
+/// rectangle and a disc at each end, so the part is a single range.
+fn reached_range(a: Point, b: Point, c: Point, d: Point, reach: f32) -> Option<(f32, f32)> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let mut ranges = Vec::new();
+
+    for q in [c, d] {
+        let (fx, fy) = (a.0 - q.0, a.1 - q.1);
+        let qa = dx * dx + dy * dy;
+        let qb = 2.0 * (fx * dx + fy * dy);
+        let qc = fx * fx + fy * fy - reach * reach;
+        if qa == 0.0 {
+            if qc <= 0.0 {
+                ranges.push((0.0, 1.0));
+            }
+            continue;
+        }
+        let disc = qb * qb - 4.0 * qa * qc;
+        if disc >= 0.0 {
+            let root = disc.sqrt();
+            ranges.push(((-qb - root) / (2.0 * qa), (-qb + root) / (2.0 * qa)));
+        }
+    }
+
+    let length = ((d.0 - c.0).powi(2) + (d.1 - c.1).powi(2)).sqrt();
+    if length > 0.0 {
+        let (ux, uy) = ((d.0 - c.0) / length, (d.1 - c.1) / length);
+        // Each side of the rectangle bounds a coordinate that is linear in t
+        let along = ((a.0 - c.0) * ux + (a.1 - c.1) * uy, dx * ux + dy * uy);
+        let across = ((a.0 - c.0) * -uy + (a.1 - c.1) * ux, dx * -uy + dy * ux);
+        let mut range = Some((f32::NEG_INFINITY, f32::INFINITY));
+        for ((start, slope), low, high) in [(along, 0.0, length), (across, -reach, reach)] {
+            range = range.and_then(|(from, to)| {
+                if slope == 0.0 {
+                    return (low..=high).contains(&start).then_some((from, to));
+                }
+                let (t0, t1) = ((low - start) / slope, (high - start) / slope);
+                let (from, to) = (from.max(t0.min(t1)), to.min(t0.max(t1)));
+                return (from <= to).then_some((from, to));
+            });
+        }
+        ranges.extend(range);
+    }
+
+    let from = ranges.iter().map(|r| r.0).fold(f32::INFINITY, f32::min).max(0.0);
+    let to = ranges.iter().map(|r| r.1).fold(f32::NEG_INFINITY, f32::max).min(1.0);
+    // Merely touching the edge of the area cuts nothing
+    return (from < to).then_some((from, to));
+}
+
+/// The parts of the segment `a`-`b` the eraser doesn't reach, sorted.
+fn kept_ranges(a: Point, b: Point, path: &[Point], reach: f32) -> Vec<(f32, f32)> {
+    let mut erased: Vec<(f32, f32)> = segments(path).iter()
+        .filter_map(|(c, d)| reached_range(a, b, *c, *d, reach))
+        .collect();
+    erased.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    let mut kept = Vec::new();
+    let mut from = 0.0;
+    for (start, end) in erased {
+        if start > from {
+            kept.push((from, start));
+        }
+        from = f32::max(from, end);
+    }
+    if from < 1.0 {
+        kept.push((from, 1.0));
+    }
+    return kept;
+}
+
+/// Collects the points of a fragment. Pressures and timestamps of cut points
+/// are interpolated along with them.
+struct FragmentBuilder<'a> {
+    line: &'a Line,
+    points: Vec<Point>,
+    pressures: Vec<f32>,
+    timestamps: Vec<u32>,
+}
+
+impl<'a> FragmentBuilder<'a> {
+    fn new(line: &'a Line) -> Self {
+        return Self { line, points: Vec::new(), pressures: Vec::new(), timestamps: Vec::new() };
+    }
+
+    fn push_vertex(&mut self, i: usize) {
+        self.points.push(self.line.points[i]);
+        if let Some(pr) = self.line.pressures.as_ref() {
+            self.pressures.push(pr[i]);
+        }
+        if let Some(ts) = self.line.timestamps.as_ref() {
+            self.timestamps.push(ts[i]);
+        }
+    }
+
+    /// A point at `t` along the segment from vertex `i` to the next one.
+    fn push_cut(&mut self, i: usize, t: f32) {
+        if t <= 0.0 {
+            return self.push_vertex(i);
+        }
+        if t >= 1.0 {
+            return self.push_vertex(i + 1);
+        }
+        let (a, b) = (self.line.points[i], self.line.points[i + 1]);
+        self.points.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+        if let Some(pr) = self.line.pressures.as_ref() {
+            self.pressures.push(pr[i] + (pr[i + 1] - pr[i]) * t);
+        }
+        if let Some(ts) = self.line.timestamps.as_ref() {
+            let (start, end) = (ts[i] as f32, ts[i + 1] as f32);
+            self.timestamps.push((start + (end - start) * t).round() as u32);
+        }
+    }
+
+    /// Ends the current fragment and starts the next one.
+    fn finish(&mut self, fragments: &mut Vec<Line>) {
+        // A lone point left between two cuts is not worth keeping
+        if self.points.len() >= 2 {
+            let mut fragment = self.line.clone();
+            fragment.id = new_element_id();
+            fragment.points = std::mem::take(&mut self.points);
+            fragment.pressures = self.line.pressures.as_ref().map(|_| std::mem::take(&mut self.pressures));
+            fragment.timestamps = self.line.timestamps.as_ref().map(|_| std::mem::take(&mut self.timestamps));
+            fragments.push(fragment);
+        }
+        self.points.clear();
+        self.pressures.clear();
+        self.timestamps.clear();
+    }
+}
+
+/// Cuts the part of `line` within reach of the eraser. Returns `None` when
+/// the line is untouched, otherwise the fragments left over. Fragments keep
+/// the original vertices and only gain a point where they were cut.
+fn split_line(line: &Line, path: &[Point], radius: f32) -> Option<Vec<Line>> {
+    let reach = radius + line.width as f32 / 2.0;
+    if line.points.len() < 2 {
+        let touched = line.points.iter().any(|p| distance_to_path(*p, path) <= reach);
+        return touched.then(Vec::new);
+    }
+
+    let mut fragments = Vec::new();
+    let mut builder = FragmentBuilder::new(line);
+    let mut touched = false;
+    for i in 0..line.points.len() - 1 {
+        let kept = kept_ranges(line.points[i], line.points[i + 1], path, reach);
+        if kept != [(0.0, 1.0)] {
+            touched = true;
+        }
+        if kept.first().is_none_or(|(from, _)| *from > 0.0) {
+            builder.finish(&mut fragments);
+        }
+        for (from, to) in kept {
+            // A fragment still open goes on through vertex `i`
+            if builder.points.is_empty() {
+                builder.push_cut(i, from);
+            }
+            builder.push_cut(i, to);
+            if to < 1.0 {
+                builder.finish(&mut fragments);
+            }
+        }
+    }
+    if !touched {
+        return None;
+    }
+    builder.finish(&mut fragments);
+    return Some(fragments);
+}
+
+
+impl LiveBoard {
+    /// Works out what an eraser dragged along `path` removes. Candidates come
+    /// from the spatial index and are then checked against the exact strokes.
+    pub fn erase(&self, path: &[Point], radius: f32, mode: EraseMode) -> (EraseOps, EraseUndo) {
+        let mut candidates: HashSet<&str> = HashSet::new();
+        for (a, b) in segments(path) {
+            let rect = AABB::from_corners(
+                [a.0.min(b.0) - radius, a.1.min(b.1) - radius],
+                [a.0.max(b.0) + radius, a.1.max(b.1) + radius],
+            );
+            candidates.extend(self.index.query_rect(&rect));
+        }
+
+        let mut ops = EraseOps::default();
+        let mut undo = EraseUndo::default();
+        for line in self.data.lines.iter().filter(|l| candidates.contains(l.id.as_str())) {
+            let fragments = match mode {
+                EraseMode::Stroke if stroke_touched(line, path, radius) => Some(Vec::new()),
+                EraseMode::Stroke => None,
+                EraseMode::Partial => split_line(line, path, radius),
+            };
+            if let Some(fragments) = fragments {
+                ops.erased.push(ErasedElement { id: line.id.clone(), fragments });
+                undo.lines.push(line.clone());
+            }
+        }
+        return (ops, undo);
+    }
+
+    pub fn apply_erase(&mut self, ops: &EraseOps) -> usize {
+        let changed = self.data.apply_erase(ops);
+        for erased in ops.erased.iter() {
+            self.index.remove(&erased.id);
+            for fragment in erased.fragments.iter() {
+                self.index.update_line(fragment);
+            }
+        }
+        return changed;
+    }
+}
+
+impl WhiteBoardData {
+    /// Replaces erased lines with their fragments, in place and in every
+    /// group that contained them. Returns how many lines were found.
+    pub fn apply_erase(&mut self, ops: &EraseOps) -> usize {
+        let mut changed = 0;
+        for erased in ops.erased.iter() {
+            if let Some(index) = self.lines.iter().position(|l| l.id == erased.id) {
+                self.lines.splice(index..index + 1, erased.fragments.iter().cloned());
+                changed += 1;
+            }
+            let fragment_ids: Vec<String> = erased.fragments.iter().map(|f| f.id.clone()).collect();
+            for group in self.groups.iter_mut() {
+                if let Some(index) = group.children.iter().position(|c| c == &erased.id) {
+                    group.children.splice(index..index + 1, fragment_ids.iter().cloned());
+                }
+            }
+        }
+        self.groups.retain(|g| !g.children.is_empty());
+        return changed;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> LiveBoard {
+        LiveBoard::new(serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[100,0]],"c":"#000","w":2,"pr":[0.0,1.0],"ts":[0,100]},
+            {"id":"b","p":[[0,50],[100,50]],"c":"#000","w":2}
+        ],"groups":[{"id":"g","children":["a","b"]}]}"##).unwrap())
+    }
+
+    #[test]
+    fn test_stroke_mode_removes_touched_lines() {
+        let mut board = board();
+        let (ops, undo) = board.erase(&[(50.0, -10.0), (50.0, 10.0)], 1.0, EraseMode::Stroke);
+        assert_eq!(ops.erased.len(), 1);
+        assert!(ops.erased[0].fragments.is_empty());
+        assert_eq!(undo.lines[0].id, "a");
+
+        board.apply_erase(&ops);
+        assert_eq!(board.data.lines.len(), 1);
+        assert_eq!(board.data.groups[0].children, vec!["b"]);
+    }
+
+    #[test]
+    fn test_partial_mode_splits_and_keeps_attributes_aligned() {
+        let mut board = board();
+        let (ops, _) = board.erase(&[(50.0, 0.0)], 5.0, EraseMode::Partial);
+        let fragments = &ops.erased[0].fragments;
+        assert_eq!(fragments.len(), 2);
+
+        for fragment in fragments {
+            let count = fragment.points.len();
+            assert_eq!(fragment.pressures.as_ref().unwrap().len(), count);
+            assert_eq!(fragment.timestamps.as_ref().unwrap().len(), count);
+            assert_eq!(count, 2);
+            assert!(fragment.points.iter().all(|p| (p.0 - 50.0).abs() >= 5.999));
+        }
+        assert_eq!(fragments[0].points[0], (0.0, 0.0));
+        assert_eq!(fragments[1].points.last(), Some(&(100.0, 0.0)));
+        assert_eq!(fragments[1].pressures.as_ref().unwrap().last(), Some(&1.0));
+
+        board.apply_erase(&ops);
+        assert_eq!(board.data.lines.len(), 3);
+        assert_eq!(board.data.groups[0].children.len(), 3);
+        assert!(board.hit_test((50.0, 0.0), 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_partial_mode_keeps_original_vertices() {
+        let board = LiveBoard::new(serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[10,0],[10,10],[0,10]],"c":"#000","w":2}
+        ]}"##).unwrap());
+        // Cuts the middle of the second segment and nothing else
+        let (ops, _) = board.erase(&[(11.0, 5.0)], 1.0, EraseMode::Partial);
+        let fragments = &ops.erased[0].fragments;
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(fragments[0].points[..2], [(0.0, 0.0), (10.0, 0.0)]);
+        assert_eq!(fragments[0].points.len(), 3);
+        assert_eq!(fragments[1].points.len(), 3);
+        assert_eq!(fragments[1].points[1..], [(10.0, 10.0), (0.0, 10.0)]);
+
+        // An eraser that misses leaves the line alone
+        let (ops, _) = board.erase(&[(50.0, 50.0)], 1.0, EraseMode::Partial);
+        assert!(ops.is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+pub mod svg;
+pub mod png;
+pub mod pdf;
+use super::{ Frame, Line, Text, WhiteBoardData };
+use super::spatial::line_bounds;
+use super::validation::normalize_color;
+
+/// The part of a board to export, in board units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportArea {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ExportArea {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        return Self { x, y, width: width.abs(), height: height.abs() };
+    }
+
+    pub fn from_frame(frame: &Frame) -> Self {
+        return Self::new(frame.x, frame.y, frame.width, frame.height);
+    }
+
+    /// The smallest area holding every stroke and text of the board, grown
+    /// by `padding` on each side. An empty board gives a 1x1 area at the
+    /// origin.
+    pub fn from_content(board: &WhiteBoardData, padding: f32) -> Self {
+        return Self::from_boards(&[board], padding);
+    }
+
+    /// Like `from_content`, for the content of several boards together.
+    pub fn from_boards(boards: &[&WhiteBoardData], padding: f32) -> Self {
+        let mut bounds = boards.iter().flat_map(|board| {
+            board.lines.iter()
+                .filter_map(line_bounds)
+                .map(|rect| (rect.lower(), rect.upper()))
+                .chain(board.texts.iter().map(text_bounds))
+        });
+        let (mut min, mut max) = match bounds.next() {
+            Some(first) => first,
+            None => return Self::new(0.0, 0.0, 1.0, 1.0),
+        };
+        for (lower, upper) in bounds {
+            min = [min[0].min(lower[0]), min[1].min(lower[1])];
+            max = [max[0].max(upper[0]), max[1].max(upper[1])];
+        }
+        return Self::new(
+            min[0] - padding,
+            min[1] - padding,
+            max[0] - min[0] + 2.0 * padding,
+            max[1] - min[1] + 2.0 * padding,
+        );
+    }
+}
+
+/// Text is drawn with the viewer's sans-serif font, so its size can only be
+/// estimated from these multiples of the font size.
+const TEXT_CHAR_WIDTH: f32 = 0.6;
+const TEXT_LINE_HEIGHT: f32 = 1.25;
+/// Distance from the top of a line of text to its baseline.
+const TEXT_ASCENT: f32 = 0.8;
+
+/// Offset of the baseline of line `i` of a text from its anchor, before
+/// rotation.
+fn text_baseline(text: &Text, i: usize) -> f32 {
+    return text.size * (TEXT_ASCENT + TEXT_LINE_HEIGHT * i as f32);
+}
+
+/// Estimated bounding box of a text, including its rotation.
+fn text_bounds(text: &Text) -> ([f32; 2], [f32; 2]) {
+    let lines: Vec<&str> = text.text.split('\n').collect();
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let width = longest as f32 * text.size * TEXT_CHAR_WIDTH;
+    let height = lines.len() as f32 * text.size * TEXT_LINE_HEIGHT;
+
+    let (sin, cos) = text.rotation.unwrap_or(0.0).sin_cos();
+    let corners = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)]
+        .map(|(dx, dy)| [text.x + dx * cos - dy * sin, text.y + dx * sin + dy * cos]);
+    let mut min = corners[0];
+    let mut max = corners[0];
+    for [x, y] in corners {
+        min = [min[0].min(x), min[1].min(y)];
+        max = [max[0].max(x), max[1].max(y)];
+    }
+    return (min, max);
+}
+
+/// Splits a normalized `#rrggbbaa` color into `#rrggbb` and its alpha.
+/// Other colors are returned as they are with an alpha of 1.
+fn split_alpha(color: &str) -> (String, f32) {
+    if color.len() == 9 && color.starts_with('#') {
+        if let Ok(alpha) = u8::from_str_radix(&color[7..9], 16) {
+            return (color[..7].to_string(), alpha as f32 / 255.0);
+        }
+    }
+    return (color.to_string(), 1.0);
+}
+
+/// Parses any color the board accepts into its RGBA channels.
+fn parse_rgba(color: &str) -> Option<[u8; 4]> {
+    let color = normalize_color(color)?;
+    let channel = |i: usize| u8::from_str_radix(&color[i..i + 2], 16).ok();
+    let alpha = if color.len() == 9 { channel(7)? } else { 255 };
+    return Some([channel(1)?, channel(3)?, channel(5)?, alpha]);
+}
+
+/// Width of the segment between points `i` and `i + 1` of a line. Pressure,
+/// when recorded, scales the width by its average over the segment.
+fn segment_width(line: &Line, i: usize) -> f32 {
+    let width = line.width as f32;
+    match line.pressures.as_ref() {
+        Some(pressures) if pressures.len() == line.points.len() && i + 1 < pressures.len() =>
+            (width * (pressures[i] + pressures[i + 1]) / 2.0).max(width * 0.05),
+        _ => width,
+    }
+}
+
+fn has_pressure(line: &Line) -> bool {
+    return line.pressures.as_ref().is_some_and(|p| p.len() == line.points.len() && line.points.len() > 1);
+}
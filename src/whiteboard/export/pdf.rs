@@ -0,0 +1,410 @@
+use chrono::{ DateTime, Utc };
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use super::{ has_pressure, parse_rgba, segment_width, text_baseline, ExportArea };
+use crate::whiteboard::{ Line, LineCap, LineJoin, Text, WhiteBoardData };
+
+/// Board units are CSS pixels, which are 3/4 of a PDF point.
+const POINTS_PER_UNIT: f32 = 0.75;
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PageSize {
+    #[default]
+    A4,
+    A3,
+    A5,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    /// Width and height in portrait, in points.
+    fn get_points(&self) -> (f32, f32) {
+        match self {
+            PageSize::A4 => (595.28, 841.89),
+            PageSize::A3 => (841.89, 1190.55),
+            PageSize::A5 => (419.53, 595.28),
+            PageSize::Letter => (612.0, 792.0),
+            PageSize::Legal => (612.0, 1008.0),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    /// Landscape for areas wider than they are tall, portrait otherwise.
+    #[default]
+    Auto,
+    Portrait,
+    Landscape,
+}
+
+#[derive(Debug, Clone)]
+pub struct PdfOptions {
+    pub page_size: PageSize,
+    pub orientation: Orientation,
+    /// Margin on every side, in millimeters.
+    pub margin: f32,
+    /// Scales each area to fill the page. Otherwise it is drawn at its real
+    /// size, centered, and whatever does not fit is cut off.
+    pub fit_to_page: bool,
+}
+
+/// One page of the document: part of a board and what to draw behind it.
+pub struct PdfPage<'a> {
+    pub board: &'a WhiteBoardData,
+    pub area: ExportArea,
+    pub background: Option<String>,
+}
+
+pub struct PdfMetadata {
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+}
+
+
+fn num(value: f32) -> String {
+    let text = format!("{:.3}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    return if text == "-0" { "0".to_string() } else { text.to_string() };
+}
+
+/// A text string as UTF-16 with a byte order mark, the encoding every PDF
+/// reader understands for non-ASCII text.
+fn text_string(value: &str) -> String {
+    let mut hex = String::from("<FEFF");
+    for unit in value.encode_utf16() {
+        write!(hex, "{:04X}", unit).unwrap();
+    }
+    hex.push('>');
+    return hex;
+}
+
+fn date_string(date: &DateTime<Utc>) -> String {
+    return format!("(D:{}Z)", date.format("%Y%m%d%H%M%S"));
+}
+
+fn color_operands(color: [u8; 4]) -> String {
+    return format!(
+        "{} {} {}",
+        num(color[0] as f32 / 255.0), num(color[1] as f32 / 255.0), num(color[2] as f32 / 255.0)
+    );
+}
+
+/// Opacities are rounded to hundredths so that they can share graphics states.
+fn alpha_key(alpha: f32) -> u8 {
+    return (alpha.clamp(0.0, 1.0) * 100.0).round() as u8;
+}
+
+fn line_alpha(line: &Line, color: [u8; 4]) -> u8 {
+    return alpha_key(color[3] as f32 / 255.0 * line.opacity.unwrap_or(1.0));
+}
+
+
+fn write_line(content: &mut String, line: &Line) {
+    if line.points.is_empty() {
+        return;
+    }
+    let color = parse_rgba(&line.color).unwrap_or([0, 0, 0, 255]);
+    let alpha = line_alpha(line, color);
+
+    content.push_str("q\n");
+    if alpha < 100 {
+        writeln!(content, "/GS{} gs", alpha).unwrap();
+    }
+    let cap = match line.cap.unwrap_or(LineCap::Round) {
+        LineCap::Butt => 0,
+        LineCap::Round => 1,
+        LineCap::Square => 2,
+    };
+    let join = match line.join.unwrap_or(LineJoin::Round) {
+        LineJoin::Miter => 0,
+        LineJoin::Round => 1,
+        LineJoin::Bevel => 2,
+    };
+    writeln!(content, "{} RG {} J {} j", color_operands(color), cap, join).unwrap();
+    if let Some(dash) = line.dash.as_ref() {
+        let dash: Vec<String> = dash.iter().map(|d| num(*d)).collect();
+        writeln!(content, "[{}] 0 d", dash.join(" ")).unwrap();
+    }
+
+    if has_pressure(line) {
+        for (i, segment) in line.points.windows(2).enumerate() {
+            writeln!(
+                content,
+                "{} w {} {} m {} {} l S",
+                num(segment_width(line, i)),
+                num(segment[0].0), num(segment[0].1), num(segment[1].0), num(segment[1].1)
+            ).unwrap();
+        }
+    } else {
+        writeln!(content, "{} w", line.width).unwrap();
+        let (x, y) = line.points[0];
+        write!(content, "{} {} m", num(x), num(y)).unwrap();
+        // A single point becomes a zero-length line, drawn as a dot by its cap
+        let rest = if line.points.len() == 1 { &line.points[..] } else { &line.points[1..] };
+        for (x, y) in rest {
+            write!(content, " {} {} l", num(*x), num(*y)).unwrap();
+        }
+        content.push_str(" S\n");
+    }
+    content.push_str("Q\n");
+}
+
+/// Encodes text for the built-in Helvetica font as a hex string. WinAnsi
+/// matches Latin-1 for printable characters, anything else becomes `?`.
+fn win_ansi_string(value: &str) -> String {
+    let mut hex = String::from("<");
+    for c in value.chars() {
+        let code = c as u32;
+        let byte = if (0x20..0x7F).contains(&code) || (0xA0..=0xFF).contains(&code) { code as u8 } else { b'?' };
+        write!(hex, "{:02X}", byte).unwrap();
+    }
+    hex.push('>');
+    return hex;
+}
+
+fn text_alpha(color: [u8; 4]) -> u8 {
+    return alpha_key(color[3] as f32 / 255.0);
+}
+
+/// The page is flipped, so the text matrix flips glyphs back upright while
+/// rotating them around the anchor.
+fn write_text(content: &mut String, text: &Text) {
+    let color = parse_rgba(&text.color).unwrap_or([0, 0, 0, 255]);
+    let alpha = text_alpha(color);
+    let (sin, cos) = text.rotation.unwrap_or(0.0).sin_cos();
+
+    content.push_str("q
+");
+    if alpha < 100 {
+        writeln!(content, "/GS{} gs", alpha).unwrap();
+    }
+    writeln!(content, "{} rg BT /F1 {} Tf", color_operands(color), num(text.size)).unwrap();
+    for (i, line) in text.text.split('\n').enumerate() {
+        let offset = text_baseline(text, i);
+        writeln!(
+            content,
+            "{} {} {} {} {} {} Tm {} Tj",
+            num(cos), num(sin), num(sin), num(-cos),
+            num(text.x - offset * sin), num(text.y + offset * cos), win_ansi_string(line)
+        ).unwrap();
+    }
+    content.push_str("ET
+Q
+");
+}
+
+/// Size of the PDF page for `area`, in points.
+fn page_dimensions(area: &ExportArea, options: &PdfOptions) -> (f32, f32) {
+    let (short, long) = options.page_size.get_points();
+    let landscape = match options.orientation {
+        Orientation::Auto => area.width > area.height,
+        Orientation::Portrait => false,
+        Orientation::Landscape => true,
+    };
+    return if landscape { (long, short) } else { (short, long) };
+}
+
+/// The content stream of one page. Board coordinates grow downwards and PDF
+/// coordinates upwards, so the page is flipped while mapping the area into
+/// the margins.
+fn page_content(page: &PdfPage, options: &PdfOptions, page_width: f32, page_height: f32) -> String {
+    let margin = (options.margin * POINTS_PER_MM).clamp(0.0, page_width.min(page_height) / 2.0 - 1.0);
+    let available_width = page_width - 2.0 * margin;
+    let available_height = page_height - 2.0 * margin;
+    let area = &page.area;
+
+    let scale = if options.fit_to_page {
+        (available_width / area.width).min(available_height / area.height)
+    } else {
+        POINTS_PER_UNIT
+    };
+    let offset_x = margin + (available_width - area.width * scale) / 2.0;
+    let offset_y = margin + (available_height - area.height * scale) / 2.0;
+
+    let mut content = String::new();
+    // Nothing is drawn in the margins
+    writeln!(content, "{} {} {} {} re W n", num(margin), num(margin), num(available_width), num(available_height)).unwrap();
+    writeln!(
+        content,
+        "{} 0 0 {} {} {} cm",
+        num(scale), num(-scale), num(offset_x - area.x * scale), num(page_height - offset_y + area.y * scale)
+    ).unwrap();
+    writeln!(content, "{} {} {} {} re W n", num(area.x), num(area.y), num(area.width), num(area.height)).unwrap();
+
+    if let Some(background) = page.background.as_deref().and_then(parse_rgba) {
+        let alpha = alpha_key(background[3] as f32 / 255.0);
+        content.push_str("q\n");
+        if alpha < 100 {
+            writeln!(content, "/GS{} gs", alpha).unwrap();
+        }
+        writeln!(
+            content,
+            "{} rg {} {} {} {} re f\nQ",
+            color_operands(background), num(area.x), num(area.y), num(area.width), num(area.height)
+        ).unwrap();
+    }
+
+    for line in page.board.lines.iter() {
+        write_line(&mut content, line);
+    }
+    for text in page.board.texts.iter() {
+        write_text(&mut content, text);
+    }
+    return content;
+}
+
+/// Every opacity used in the document, so that one shared resource
+/// dictionary can hold a graphics state for each of them.
+fn collect_alphas(pages: &[PdfPage]) -> BTreeSet<u8> {
+    let mut alphas = BTreeSet::new();
+    for page in pages {
+        if let Some(background) = page.background.as_deref().and_then(parse_rgba) {
+            alphas.insert(alpha_key(background[3] as f32 / 255.0));
+        }
+        for line in page.board.lines.iter() {
+            alphas.insert(line_alpha(line, parse_rgba(&line.color).unwrap_or([0, 0, 0, 255])));
+        }
+        for text in page.board.texts.iter() {
+            alphas.insert(text_alpha(parse_rgba(&text.color).unwrap_or([0, 0, 0, 255])));
+        }
+    }
+    alphas.remove(&100);
+    return alphas;
+}
+
+
+/// Objects are written in the order of their numbers, recording where each
+/// one starts for the cross-reference table.
+struct PdfWriter {
+    buffer: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+        return Self { buffer, offsets: Vec::new() };
+    }
+
+    fn write_object(&mut self, body: &[u8]) {
+        self.offsets.push(self.buffer.len());
+        writeln!(self.buffer, "{} 0 obj", self.offsets.len()).unwrap();
+        self.buffer.extend_from_slice(body);
+        self.buffer.extend_from_slice(b"\nendobj\n");
+    }
+
+    fn write_stream(&mut self, data: &[u8]) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut body = format!("<< /Length {} /Filter /FlateDecode >>\nstream\n", compressed.len()).into_bytes();
+        body.extend_from_slice(&compressed);
+        body.extend_from_slice(b"\nendstream");
+        self.write_object(&body);
+    }
+
+    fn finish(mut self, root: usize, info: usize) -> Vec<u8> {
+        let xref = self.buffer.len();
+        write!(self.buffer, "xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1).unwrap();
+        for offset in self.offsets.iter() {
+            writeln!(self.buffer, "{:010} 00000 n ", offset).unwrap();
+        }
+        write!(
+            self.buffer,
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1, root, info, xref
+        ).unwrap();
+        return self.buffer;
+    }
+}
+
+
+/// Renders each page as vector paths into one PDF document.
+pub fn render_pdf(pages: &[PdfPage], options: &PdfOptions, metadata: &PdfMetadata) -> Vec<u8> {
+    // 1: catalog, 2: page tree, 3: info, 4: shared resources,
+    // then a page object and its content stream for every page
+    let page_object = |i: usize| 5 + 2 * i;
+
+    let mut writer = PdfWriter::new();
+    writer.write_object(b"<< /Type /Catalog /Pages 2 0 R >>");
+
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", page_object(i))).collect();
+    writer.write_object(format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()).as_bytes());
+
+    let date = date_string(&metadata.created_at);
+    writer.write_object(format!(
+        "<< /Title {} /Producer {} /Creator {} /CreationDate {} /ModDate {} >>",
+        text_string(&metadata.title), text_string("Whiteboard"), text_string("Whiteboard"), date, date
+    ).as_bytes());
+
+    let states: Vec<String> = collect_alphas(pages).iter()
+        .map(|alpha| format!("/GS{} << /Type /ExtGState /CA {} /ca {} >>", alpha, num(*alpha as f32 / 100.0), num(*alpha as f32 / 100.0)))
+        .collect();
+    writer.write_object(format!(
+        "<< /ExtGState << {} >> /Font << /F1 << /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >> >> >>",
+        states.join(" ")
+    ).as_bytes());
+
+    for (i, page) in pages.iter().enumerate() {
+        let (width, height) = page_dimensions(&page.area, options);
+        writer.write_object(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources 4 0 R /Contents {} 0 R >>",
+            num(width), num(height), page_object(i) + 1
+        ).as_bytes());
+        writer.write_stream(page_content(page, options, width, height).as_bytes());
+    }
+
+    return writer.finish(1, 3);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_render_pdf_pages_and_cross_references() {
+        let board: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[100,50]],"c":"#ff000080","w":4}
+        ],"texts":[{"id":"t","x":0,"y":0,"text":"Größe €","color":"#000000","size":12}]}"##).unwrap();
+        let pages = vec![
+            PdfPage { board: &board, area: ExportArea::from_content(&board, 10.0), background: Some("#ffffff".to_string()) },
+            PdfPage { board: &board, area: ExportArea::new(0.0, 0.0, 50.0, 100.0), background: None },
+        ];
+        let options = PdfOptions { page_size: PageSize::A4, orientation: Orientation::Auto, margin: 10.0, fit_to_page: true };
+        let metadata = PdfMetadata { title: "Tafel ü".to_string(), created_at: Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap() };
+
+        let pdf = render_pdf(&pages, &options, &metadata);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("/MediaBox [0 0 841.89 595.28]"));
+        assert!(text.contains("/MediaBox [0 0 595.28 841.89]"));
+        assert!(text.contains("/Title <FEFF0054006100660065006C002000FC>"));
+        assert!(text.contains("/CreationDate (D:20250301120000Z)"));
+        assert!(text.contains("/GS50 << /Type /ExtGState /CA 0.5 /ca 0.5 >>"));
+        assert!(text.contains("/BaseFont /Helvetica"));
+        assert_eq!(win_ansi_string("Größe €"), "<4772F6DF65203F>");
+
+        // Every entry of the cross-reference table points at its object
+        let start: usize = text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        let entries: Vec<&str> = text[text.rfind("xref\n0 ").unwrap()..].lines().skip(3).take(8).collect();
+        assert!(pdf[start..].starts_with(b"xref\n"));
+        for (i, entry) in entries.iter().enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj", i + 1).as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use tiny_skia::{
+    FillRule, Paint, PathBuilder, Pixmap, PixmapPaint, Stroke, StrokeDash, Transform,
+    LineCap as SkiaLineCap, LineJoin as SkiaLineJoin,
+};
+use super::{ has_pressure, parse_rgba, segment_width, ExportArea };
+use crate::whiteboard::{ Line, LineCap, LineJoin, WhiteBoardData };
+
+/// Largest image, in pixels, the rasterizer agrees to allocate.
+pub const MAX_PIXELS: u64 = 4096 * 4096;
+
+/// Longest side of a thumbnail, in pixels.
+pub const THUMBNAIL_SIZE: f32 = 320.0;
+
+/// Sent with PNG exports of boards that have texts, the rasterizer has no
+/// font support and leaves them out.
+pub const TEXTS_NOT_DRAWN: &str = "texts are not drawn in PNG images, export as SVG or PDF to include them";
+
+
+fn paint_for(color: [u8; 4]) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(color[0], color[1], color[2], color[3]);
+    paint.anti_alias = true;
+    return paint;
+}
+
+fn stroke_for(line: &Line, width: f32) -> Stroke {
+    let line_cap = match line.cap.unwrap_or(LineCap::Round) {
+        LineCap::Butt => SkiaLineCap::Butt,
+        LineCap::Round => SkiaLineCap::Round,
+        LineCap::Square => SkiaLineCap::Square,
+    };
+    let line_join = match line.join.unwrap_or(LineJoin::Round) {
+        LineJoin::Miter => SkiaLineJoin::Miter,
+        LineJoin::Round => SkiaLineJoin::Round,
+        LineJoin::Bevel => SkiaLineJoin::Bevel,
+    };
+    let dash = line.dash.clone().and_then(|dash| StrokeDash::new(dash, 0.0));
+    return Stroke { width, line_cap, line_join, dash, ..Stroke::default() };
+}
+
+/// Draws `line` at full opacity; the caller applies the line's opacity.
+fn draw_line(pixmap: &mut Pixmap, line: &Line, color: [u8; 4], transform: Transform) {
+    let paint = paint_for(color);
+
+    // A single point is a dot as wide as the stroke
+    if line.points.len() == 1 {
+        let (x, y) = line.points[0];
+        if let Some(dot) = PathBuilder::from_circle(x, y, (line.width as f32 / 2.0).max(0.5)) {
+            pixmap.fill_path(&dot, &paint, FillRule::Winding, transform, None);
+        }
+        return;
+    }
+
+    if has_pressure(line) {
+        for (i, segment) in line.points.windows(2).enumerate() {
+            let mut builder = PathBuilder::new();
+            builder.move_to(segment[0].0, segment[0].1);
+            builder.line_to(segment[1].0, segment[1].1);
+            if let Some(path) = builder.finish() {
+                pixmap.stroke_path(&path, &paint, &stroke_for(line, segment_width(line, i)), transform, None);
+            }
+        }
+        return;
+    }
+
+    let mut builder = PathBuilder::new();
+    builder.move_to(line.points[0].0, line.points[0].1);
+    for (x, y) in line.points.iter().skip(1) {
+        builder.line_to(*x, *y);
+    }
+    if let Some(path) = builder.finish() {
+        pixmap.stroke_path(&path, &paint, &stroke_for(line, line.width as f32), transform, None);
+    }
+}
+
+/// Size in pixels of `area` at `scale`, if it is within `MAX_PIXELS`.
+fn image_size(area: &ExportArea, scale: f32) -> Result<(u32, u32), String> {
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err("scale must be a positive number".to_string());
+    }
+    let width = (area.width * scale).ceil().max(1.0);
+    let height = (area.height * scale).ceil().max(1.0);
+    if !width.is_finite() || !height.is_finite() || width as u64 * height as u64 > MAX_PIXELS {
+        return Err(format!("image would be larger than {} pixels", MAX_PIXELS));
+    }
+    return Ok((width as u32, height as u32));
+}
+
+/// Renders the lines of `board` inside `area`, `scale` pixels per board
+/// unit. Without a background the image is transparent. Texts are left out,
+/// see `TEXTS_NOT_DRAWN`.
+fn render_pixmap(board: &WhiteBoardData, area: &ExportArea, scale: f32, background: Option<&str>) -> Result<Pixmap, String> {
+    let (width, height) = image_size(area, scale)?;
+    let mut pixmap = Pixmap::new(width, height)
+        .ok_or_else(|| "could not allocate the image".to_string())?;
+    if let Some(background) = background {
+        let color = parse_rgba(background).ok_or_else(|| format!("invalid background '{}'", background))?;
+        pixmap.fill(tiny_skia::Color::from_rgba8(color[0], color[1], color[2], color[3]));
+    }
+
+    let transform = Transform::from_scale(scale, scale).pre_translate(-area.x, -area.y);
+    for line in board.lines.iter().filter(|l| !l.points.is_empty()) {
+        let color = parse_rgba(&line.color).unwrap_or([0, 0, 0, 255]);
+        let opacity = line.opacity.unwrap_or(1.0).clamp(0.0, 1.0);
+
+        // Overlapping segments of a translucent pressure line would darken
+        // each other, so the line is drawn on its own layer first.
+        if opacity < 1.0 && has_pressure(line) {
+            let mut layer = Pixmap::new(pixmap.width(), pixmap.height())
+                .ok_or_else(|| "could not allocate the image".to_string())?;
+            draw_line(&mut layer, line, [color[0], color[1], color[2], 255], transform);
+            let paint = PixmapPaint { opacity: opacity * color[3] as f32 / 255.0, ..PixmapPaint::default() };
+            pixmap.draw_pixmap(0, 0, layer.as_ref(), &paint, Transform::identity(), None);
+            continue;
+        }
+
+        let alpha = (color[3] as f32 * opacity).round() as u8;
+        draw_line(&mut pixmap, line, [color[0], color[1], color[2], alpha], transform);
+    }
+
+    return Ok(pixmap);
+}
+
+/// Renders `board` inside `area` to a PNG image, see `render_pixmap`.
+pub fn render_png(board: &WhiteBoardData, area: &ExportArea, scale: f32, background: Option<&str>) -> Result<Vec<u8>, String> {
+    return render_pixmap(board, area, scale, background)?.encode_png().map_err(|e| e.to_string());
+}
+
+/// Renders boards as the frames of an animated PNG that plays once, each
+/// frame shown for `frame_delay` milliseconds. Viewers without animation
+/// support show the first frame. Frames are rendered one at a time, so only
+/// one is held in memory.
+pub fn render_animated_png(frames: &[(&WhiteBoardData, Option<&str>)], area: &ExportArea, scale: f32, frame_delay: u16) -> Result<Vec<u8>, String> {
+    if frames.is_empty() {
+        return Err("an animation needs at least one frame".to_string());
+    }
+    let (width, height) = image_size(area, scale)?;
+
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 1).map_err(|e| e.to_string())?;
+    encoder.set_frame_delay(frame_delay, 1000).map_err(|e| e.to_string())?;
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+
+    for (board, background) in frames {
+        let pixmap = render_pixmap(board, area, scale, *background)?;
+        // Pixmaps hold premultiplied colors, PNG expects straight alpha
+        let rgba: Vec<u8> = pixmap.pixels().iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect();
+        writer.write_image_data(&rgba).map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())?;
+    return Ok(data);
+}
+
+/// A small preview of the whole board, fitted into `THUMBNAIL_SIZE` pixels.
+pub fn render_thumbnail(board: &WhiteBoardData, background: &str) -> Result<Vec<u8>, String> {
+    let area = ExportArea::from_content(board, 10.0);
+    let scale = (THUMBNAIL_SIZE / area.width.max(area.height)).min(1.0);
+    return render_png(board, &area, scale, Some(background));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_png_draws_strokes_at_scale() {
+        let board: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,5],[20,5]],"c":"#ff0000","w":4,"lc":"butt"}
+        ]}"##).unwrap();
+        let area = ExportArea::new(0.0, 0.0, 20.0, 10.0);
+
+        let png = render_png(&board, &area, 2.0, Some("#ffffff")).unwrap();
+        let pixmap = Pixmap::decode_png(&png).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (40, 20));
+
+        let stroke = pixmap.pixel(20, 10).unwrap();
+        assert_eq!((stroke.red(), stroke.green(), stroke.blue()), (255, 0, 0));
+        let background = pixmap.pixel(20, 1).unwrap();
+        assert_eq!((background.red(), background.green(), background.blue()), (255, 255, 255));
+
+        assert!(render_png(&board, &ExportArea::new(0.0, 0.0, 1e6, 1e6), 1.0, None).is_err());
+    }
+
+    #[test]
+    fn test_render_animated_png_has_a_frame_per_board() {
+        let empty = WhiteBoardData::new_empty();
+        let board: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,5],[20,5]],"c":"#ff0000","w":4}
+        ]}"##).unwrap();
+        let area = ExportArea::new(0.0, 0.0, 20.0, 10.0);
+
+        let apng = render_animated_png(&[(&empty, Some("#ffffff")), (&board, Some("#ffffff"))], &area, 1.0, 250).unwrap();
+        let decoder = png::Decoder::new(apng.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let control = reader.info().animation_control().unwrap();
+        assert_eq!(control.num_frames, 2);
+        assert_eq!((reader.info().width, reader.info().height), (20, 10));
+
+        assert!(render_animated_png(&[], &area, 1.0, 250).is_err());
+    }
+}
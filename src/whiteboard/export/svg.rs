@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use super::{ has_pressure, segment_width, split_alpha, text_baseline, ExportArea };
+use crate::whiteboard::{ Line, LineCap, LineJoin, Text, WhiteBoardData };
+use crate::whiteboard::diff::BoardDiff;
+
+/// Colors of a diff overlay.
+const DIFF_ADDED: &str = "#16a34a";
+const DIFF_REMOVED: &str = "#dc2626";
+/// Opacity of the elements a diff leaves unchanged.
+const DIFF_UNCHANGED_OPACITY: f32 = 0.25;
+
+/// Formats a number with at most two decimals and no trailing zeros.
+fn num(value: f32) -> String {
+    let text = format!("{:.2}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    return if text == "-0" { "0".to_string() } else { text.to_string() };
+}
+
+fn escape(value: &str) -> String {
+    return value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+fn cap_name(cap: Option<LineCap>) -> &'static str {
+    match cap.unwrap_or(LineCap::Round) {
+        LineCap::Butt => "butt",
+        LineCap::Round => "round",
+        LineCap::Square => "square",
+    }
+}
+
+fn join_name(join: Option<LineJoin>) -> &'static str {
+    match join.unwrap_or(LineJoin::Round) {
+        LineJoin::Miter => "miter",
+        LineJoin::Round => "round",
+        LineJoin::Bevel => "bevel",
+    }
+}
+
+/// Attributes shared by every shape of a line: color, opacity, caps, joins
+/// and dashes.
+fn style_attributes(line: &Line) -> String {
+    let (color, alpha) = split_alpha(&line.color);
+    let opacity = alpha * line.opacity.unwrap_or(1.0);
+
+    let mut attributes = format!(
+        r#"stroke="{}" stroke-linecap="{}" stroke-linejoin="{}""#,
+        escape(&color), cap_name(line.cap), join_name(line.join)
+    );
+    if opacity < 1.0 {
+        write!(attributes, r#" opacity="{}""#, num(opacity)).unwrap();
+    }
+    if let Some(dash) = line.dash.as_ref() {
+        let dash: Vec<String> = dash.iter().map(|d| num(*d)).collect();
+        write!(attributes, r#" stroke-dasharray="{}""#, dash.join(" ")).unwrap();
+    }
+    return attributes;
+}
+
+/// Element ids are written with `prefix`, so that several renders of a
+/// board can share one document.
+fn write_line(svg: &mut String, line: &Line, prefix: &str) {
+    if line.points.is_empty() {
+        return;
+    }
+    let style = style_attributes(line);
+    let id = escape(&format!("{}{}", prefix, line.id));
+
+    // Pressure changes the width along the stroke, which one path can't
+    // express. Each segment becomes its own line inside a group, so that the
+    // opacity applies to the stroke as a whole.
+    if has_pressure(line) {
+        write!(svg, r#"<g id="{}" {}>"#, id, style).unwrap();
+        for (i, segment) in line.points.windows(2).enumerate() {
+            write!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke-width="{}"/>"#,
+                num(segment[0].0), num(segment[0].1), num(segment[1].0), num(segment[1].1),
+                num(segment_width(line, i))
+            ).unwrap();
+        }
+        svg.push_str("</g>");
+        return;
+    }
+
+    let mut d = String::new();
+    for (i, (x, y)) in line.points.iter().enumerate() {
+        write!(d, "{}{} {}", if i == 0 { "M" } else { " L" }, num(*x), num(*y)).unwrap();
+    }
+    // A single point is drawn as a dot by its caps
+    if line.points.len() == 1 {
+        write!(d, " L{} {}", num(line.points[0].0), num(line.points[0].1)).unwrap();
+    }
+    write!(
+        svg,
+        r#"<path id="{}" d="{}" fill="none" stroke-width="{}" {}/>"#,
+        id, d, line.width, style
+    ).unwrap();
+}
+
+/// Each line of the text becomes a `tspan` placed on its own baseline.
+fn write_text(svg: &mut String, text: &Text, prefix: &str) {
+    let (color, alpha) = split_alpha(&text.color);
+    write!(
+        svg,
+        r#"<text id="{}" font-family="sans-serif" font-size="{}" fill="{}""#,
+        escape(&format!("{}{}", prefix, text.id)), num(text.size), escape(&color)
+    ).unwrap();
+    if alpha < 1.0 {
+        write!(svg, r#" fill-opacity="{}""#, num(alpha)).unwrap();
+    }
+    if let Some(rotation) = text.rotation {
+        write!(svg, r#" transform="rotate({} {} {})""#, num(rotation.to_degrees()), num(text.x), num(text.y)).unwrap();
+    }
+    svg.push_str(r#" xml:space="preserve">"#);
+    for (i, line) in text.text.split('\n').enumerate() {
+        write!(
+            svg,
+            r#"<tspan x="{}" y="{}">{}</tspan>"#,
+            num(text.x), num(text.y + text_baseline(text, i)), escape(line)
+        ).unwrap();
+    }
+    svg.push_str("</text>");
+}
+
+fn write_header(svg: &mut String, area: &ExportArea) {
+    write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="{x} {y} {w} {h}">"#,
+        x = num(area.x), y = num(area.y), w = num(area.width), h = num(area.height)
+    ).unwrap();
+}
+
+fn write_background(svg: &mut String, area: &ExportArea, background: Option<&str>) {
+    if let Some(background) = background {
+        let (color, alpha) = split_alpha(background);
+        write!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" fill-opacity="{}"/>"#,
+            num(area.x), num(area.y), num(area.width), num(area.height), escape(&color), num(alpha)
+        ).unwrap();
+    }
+}
+
+fn write_board(svg: &mut String, board: &WhiteBoardData, area: &ExportArea, background: Option<&str>, prefix: &str) {
+    write_background(svg, area, background);
+    for line in board.lines.iter() {
+        write_line(svg, line, prefix);
+    }
+    for text in board.texts.iter() {
+        write_text(svg, text, prefix);
+    }
+}
+
+/// Renders the lines and texts of `board` inside `area` as a standalone SVG
+/// document.
+pub fn render_svg(board: &WhiteBoardData, area: &ExportArea, background: Option<&str>) -> String {
+    let mut svg = String::new();
+    write_header(&mut svg, area);
+    write_board(&mut svg, board, area, background, "");
+    svg.push_str("</svg>");
+    return svg;
+}
+
+/// Renders boards as the frames of an animation, each shown for
+/// `frame_delay` seconds. The last frame stays visible once the animation
+/// ends.
+pub fn render_animated_svg(frames: &[(&WhiteBoardData, Option<&str>)], area: &ExportArea, frame_delay: f32) -> String {
+    let mut svg = String::new();
+    write_header(&mut svg, area);
+    for (i, (board, background)) in frames.iter().enumerate() {
+        let begin = num(i as f32 * frame_delay);
+        svg.push_str(r#"<g visibility="hidden">"#);
+        if i + 1 < frames.len() {
+            write!(
+                svg,
+                r#"<set attributeName="visibility" to="visible" begin="{}s" end="{}s"/>"#,
+                begin, num((i + 1) as f32 * frame_delay)
+            ).unwrap();
+        } else {
+            write!(svg, r#"<set attributeName="visibility" to="visible" begin="{}s"/>"#, begin).unwrap();
+        }
+        write_board(&mut svg, board, area, *background, &format!("f{}-", i));
+        svg.push_str("</g>");
+    }
+    svg.push_str("</svg>");
+    return svg;
+}
+
+
+/// Lines and texts of `board` whose id is (or, with `changed` false, is not)
+/// in `ids`, drawn in `color` when given.
+fn write_elements(svg: &mut String, board: &WhiteBoardData, ids: &HashSet<&str>, changed: bool, color: Option<&str>, prefix: &str) {
+    for line in board.lines.iter().filter(|line| ids.contains(line.id.as_str()) == changed) {
+        match color {
+            Some(color) => write_line(svg, &Line { color: color.to_string(), opacity: None, ..line.clone() }, prefix),
+            None => write_line(svg, line, prefix),
+        }
+    }
+    for text in board.texts.iter().filter(|text| ids.contains(text.id.as_str()) == changed) {
+        match color {
+            Some(color) => write_text(svg, &Text { color: color.to_string(), ..text.clone() }, prefix),
+            None => write_text(svg, text, prefix),
+        }
+    }
+}
+
+/// Renders the newer board faded, with what `diff` found highlighted over
+/// it: removed elements and the old form of modified ones in red, added
+/// elements and the new form of modified ones in green.
+pub fn render_diff_svg(
+    before: &WhiteBoardData,
+    after: &WhiteBoardData,
+    diff: &BoardDiff,
+    area: &ExportArea,
+    background: Option<&str>,
+) -> String {
+    let changed = diff.get_changed_ids();
+    let mut svg = String::new();
+    write_header(&mut svg, area);
+    write_background(&mut svg, area, background);
+
+    write!(svg, r#"<g id="unchanged" opacity="{}">"#, num(DIFF_UNCHANGED_OPACITY)).unwrap();
+    write_elements(&mut svg, after, &changed, false, None, "");
+    svg.push_str(r#"</g><g id="removed">"#);
+    write_elements(&mut svg, before, &changed, true, Some(DIFF_REMOVED), "before-");
+    svg.push_str(r#"</g><g id="added">"#);
+    write_elements(&mut svg, after, &changed, true, Some(DIFF_ADDED), "after-");
+    svg.push_str("</g></svg>");
+    return svg;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_crops_to_content_and_keeps_styles() {
+        let board: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[10,10],[30,20]],"c":"#ff000080","w":4,"d":[5,2],"lc":"square"},
+            {"id":"b","p":[[20,20],[40,40]],"c":"#000000","w":2,"pr":[0.5,1.0],"o":0.5}
+        ],"texts":[{"id":"t","x":10,"y":30,"text":"a<b\nc","color":"#0000ff","size":10}]}"##).unwrap();
+
+        let area = ExportArea::from_content(&board, 0.0);
+        assert_eq!(area, ExportArea::new(8.0, 8.0, 33.0, 47.0));
+
+        let svg = render_svg(&board, &area, Some("#ffffff"));
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="33" height="47" viewBox="8 8 33 47">"#));
+        assert!(svg.contains(r##"<path id="a" d="M10 10 L30 20" fill="none" stroke-width="4" stroke="#ff0000" stroke-linecap="square" stroke-linejoin="round" opacity="0.5" stroke-dasharray="5 2"/>"##));
+        assert!(svg.contains(r#"<line x1="20" y1="20" x2="40" y2="40" stroke-width="1.5"/>"#));
+        assert!(svg.contains(r##"<text id="t" font-family="sans-serif" font-size="10" fill="#0000ff" xml:space="preserve"><tspan x="10" y="38">a&lt;b</tspan><tspan x="10" y="50.5">c</tspan></text>"##));
+        assert!(svg.ends_with("</svg>"));
+    }
+
+    #[test]
+    fn test_animated_frames_are_shown_in_turn() {
+        let first: WhiteBoardData = serde_json::from_str(r##"{"lines":[{"id":"a","p":[[0,0],[1,1]],"c":"#000000","w":1}]}"##).unwrap();
+        let second: WhiteBoardData = serde_json::from_str(r##"{"lines":[{"id":"a","p":[[0,0],[1,1]],"c":"#000000","w":1},
+            {"id":"b","p":[[1,1],[2,2]],"c":"#000000","w":1}]}"##).unwrap();
+
+        let svg = render_animated_svg(&[(&first, None), (&second, Some("#ffffff"))], &ExportArea::new(0.0, 0.0, 2.0, 2.0), 0.5);
+        assert!(svg.contains(r#"<g visibility="hidden"><set attributeName="visibility" to="visible" begin="0s" end="0.5s"/><path id="f0-a""#));
+        assert!(svg.contains(r#"<set attributeName="visibility" to="visible" begin="0.5s"/><rect"#));
+        assert!(svg.contains(r#"id="f1-b""#));
+    }
+
+    #[test]
+    fn test_diff_overlay_highlights_changes() {
+        let before: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[1,1]],"c":"#000000","w":1},
+            {"id":"b","p":[[1,1],[2,2]],"c":"#000000","w":1,"o":0.5}
+        ]}"##).unwrap();
+        let after: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[1,1]],"c":"#000000","w":1},
+            {"id":"c","p":[[2,2],[3,3]],"c":"#0000ff","w":1}
+        ]}"##).unwrap();
+
+        let diff = crate::whiteboard::diff::diff_boards(&before, &after);
+        let svg = render_diff_svg(&before, &after, &diff, &ExportArea::new(0.0, 0.0, 3.0, 3.0), None);
+        assert!(svg.contains(r#"<g id="unchanged" opacity="0.25"><path id="a""#));
+        assert!(svg.contains(r##"<g id="removed"><path id="before-b" d="M1 1 L2 2" fill="none" stroke-width="1" stroke="#dc2626""##));
+        assert!(svg.contains(r##"<g id="added"><path id="after-c" d="M2 2 L3 3" fill="none" stroke-width="1" stroke="#16a34a""##));
+        assert!(!svg.contains("opacity=\"0.5\""));
+    }
+}
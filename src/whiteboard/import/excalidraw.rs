@@ -0,0 +1,258 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use super::{
+    add_arrowhead, add_start_arrowhead, diamond, ellipse, new_frame, new_line, new_text, rectangle,
+    ImportError, ImportSummary, ImportedDrawing, Placement, Stroke,
+};
+use crate::whiteboard::{ Group, Point, WhiteBoardData };
+
+/// The parts of an `.excalidraw` file the board can use. Unknown fields are
+/// ignored, the format adds new ones often.
+#[derive(Deserialize, Debug)]
+struct ExcalidrawFile {
+    #[serde(rename = "type")]
+    file_type: String,
+    #[serde(default)]
+    elements: Vec<Element>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Element {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    x: f32,
+    #[serde(default)]
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    /// Clockwise rotation in radians around the center of the element.
+    #[serde(default)]
+    angle: f32,
+    #[serde(default = "default_color")]
+    stroke_color: String,
+    #[serde(default = "default_stroke_width")]
+    stroke_width: f32,
+    #[serde(default)]
+    stroke_style: String,
+    /// `0..=100`
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default)]
+    is_deleted: bool,
+    /// Innermost group first.
+    #[serde(default)]
+    group_ids: Vec<String>,
+    /// Relative to `(x, y)`.
+    #[serde(default)]
+    points: Vec<Point>,
+    #[serde(default)]
+    pressures: Vec<f32>,
+    #[serde(default)]
+    simulate_pressure: bool,
+    #[serde(default)]
+    text: String,
+    #[serde(default = "default_font_size")]
+    font_size: f32,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    start_arrowhead: Option<String>,
+    #[serde(default = "default_end_arrowhead")]
+    end_arrowhead: Option<String>,
+}
+
+fn default_color() -> String {
+    return "#1e1e1e".to_string();
+}
+
+fn default_stroke_width() -> f32 {
+    return 2.0;
+}
+
+fn default_opacity() -> f32 {
+    return 100.0;
+}
+
+fn default_font_size() -> f32 {
+    return 20.0;
+}
+
+fn default_end_arrowhead() -> Option<String> {
+    return Some("arrow".to_string());
+}
+
+
+impl Element {
+    /// Lines, arrows and freehand strokes are rotated around the center of
+    /// their points, other elements around the center of their box.
+    fn get_placement(&self) -> Placement {
+        let center = if self.points.is_empty() {
+            (self.x + self.width / 2.0, self.y + self.height / 2.0)
+        } else {
+            let (mut min, mut max) = (self.points[0], self.points[0]);
+            for (x, y) in self.points.iter() {
+                min = (min.0.min(*x), min.1.min(*y));
+                max = (max.0.max(*x), max.1.max(*y));
+            }
+            (self.x + (min.0 + max.0) / 2.0, self.y + (min.1 + max.1) / 2.0)
+        };
+        return Placement::around(self.x, self.y, center, self.angle);
+    }
+
+    fn get_stroke(&self) -> Stroke {
+        return Stroke::new(&self.stroke_color, self.stroke_width, self.opacity / 100.0, &self.stroke_style);
+    }
+
+    /// Adds the element to `board` and returns whether it could be.
+    fn convert(&self, board: &mut WhiteBoardData, summary: &mut ImportSummary) -> bool {
+        let placement = self.get_placement();
+        let stroke = self.get_stroke();
+        let outline = match self.kind.as_str() {
+            "rectangle" => Some(rectangle(self.width, self.height)),
+            "ellipse" => Some(ellipse(self.width, self.height)),
+            "diamond" => Some(diamond(self.width, self.height)),
+            _ => None,
+        };
+        if let Some(outline) = outline {
+            let points = outline.into_iter().map(|p| placement.apply(p)).collect();
+            board.lines.push(new_line(self.id.clone(), points, &stroke, None));
+            summary.approximated(&self.kind);
+            return true;
+        }
+
+        match self.kind.as_str() {
+            "freedraw" | "line" | "arrow" if !self.points.is_empty() => {
+                let mut points: Vec<Point> = self.points.iter().map(|p| placement.apply(*p)).collect();
+                let pressures = if self.kind == "freedraw" && !self.simulate_pressure && self.pressures.len() == points.len() {
+                    Some(self.pressures.clone())
+                } else {
+                    None
+                };
+                if self.kind == "arrow" {
+                    if self.end_arrowhead.is_some() {
+                        add_arrowhead(&mut points, stroke.width);
+                    }
+                    if self.start_arrowhead.is_some() {
+                        add_start_arrowhead(&mut points, stroke.width);
+                    }
+                }
+                board.lines.push(new_line(self.id.clone(), points, &stroke, pressures));
+            }
+            "text" => {
+                board.texts.push(new_text(
+                    self.id.clone(), &placement, self.text.clone(), &self.stroke_color, self.font_size, self.opacity / 100.0,
+                ));
+            }
+            "frame" | "magicframe" => {
+                let name = self.name.clone().unwrap_or_else(|| "Frame".to_string());
+                board.frames.push(new_frame(self.id.clone(), name, &placement, self.width, self.height));
+            }
+            _ => {
+                summary.dropped(&self.kind);
+                return false;
+            }
+        }
+        summary.converted(&self.kind);
+        return true;
+    }
+}
+
+
+/// Converts an `.excalidraw` file into a single page. Excalidraw groups,
+/// which an element lists from the innermost out, become nested groups.
+pub(super) fn convert(bytes: &[u8]) -> Result<ImportedDrawing, ImportError> {
+    let file: ExcalidrawFile = serde_json::from_slice(bytes).map_err(|e| ImportError::Malformed(e.to_string()))?;
+    if file.file_type != "excalidraw" && file.file_type != "excalidraw/clipboard" {
+        return Err(ImportError::UnsupportedFormat { expected: "Excalidraw" });
+    }
+
+    let mut board = WhiteBoardData::new_empty();
+    let mut summary = ImportSummary::default();
+    let mut groups: Vec<Group> = Vec::new();
+    let mut group_index: HashMap<String, usize> = HashMap::new();
+
+    for element in file.elements.iter().filter(|e| !e.is_deleted) {
+        if !element.convert(&mut board, &mut summary) {
+            continue;
+        }
+        let mut child = element.id.clone();
+        for group_id in element.group_ids.iter() {
+            let index = *group_index.entry(group_id.clone()).or_insert_with(|| {
+                groups.push(Group::new(group_id.clone(), Vec::new()));
+                groups.len() - 1
+            });
+            let children = &mut groups[index].children;
+            if !children.contains(&child) {
+                children.push(child);
+            }
+            child = group_id.clone();
+        }
+    }
+    board.groups = groups;
+
+    return Ok(ImportedDrawing {
+        name: None,
+        pages: vec![("Page 1".to_string(), board)],
+        summary,
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_shapes_and_groups() {
+        let file = r##"{"type":"excalidraw","version":2,"source":"https://excalidraw.com","elements":[
+            {"id":"r","type":"rectangle","x":10,"y":10,"width":100,"height":50,"angle":0,"strokeColor":"#e03131","strokeWidth":2,"strokeStyle":"dashed","opacity":100,"groupIds":["inner","outer"]},
+            {"id":"f","type":"freedraw","x":0,"y":0,"width":10,"height":10,"angle":0,"strokeColor":"#1e1e1e","strokeWidth":1,"opacity":50,"points":[[0,0],[5,5],[10,10]],"pressures":[0.2,0.5,0.8],"simulatePressure":false,"groupIds":["inner","outer"]},
+            {"id":"a","type":"arrow","x":0,"y":100,"width":100,"height":0,"angle":0,"strokeColor":"#1e1e1e","strokeWidth":2,"points":[[0,0],[100,0]],"startArrowhead":null,"endArrowhead":"arrow","groupIds":["outer"]},
+            {"id":"t","type":"text","x":20,"y":20,"width":60,"height":25,"angle":0,"strokeColor":"#1971c2","opacity":100,"text":"Hello","fontSize":20,"groupIds":[]},
+            {"id":"i","type":"image","x":0,"y":0,"width":10,"height":10,"fileId":"abc"},
+            {"id":"d","type":"ellipse","x":0,"y":0,"width":10,"height":10,"isDeleted":true}
+        ],"appState":{},"files":{}}"##;
+
+        let drawing = convert(file.as_bytes()).unwrap();
+        let (name, board) = &drawing.pages[0];
+        assert_eq!(name, "Page 1");
+        assert_eq!(board.lines.len(), 3);
+        assert_eq!(board.lines[0].points, vec![(10.0, 10.0), (110.0, 10.0), (110.0, 60.0), (10.0, 60.0), (10.0, 10.0)]);
+        assert_eq!(board.lines[0].color, "#e03131");
+        assert_eq!(board.lines[0].dash, Some(vec![8.0, 8.0]));
+        assert_eq!(board.lines[1].pressures, Some(vec![0.2, 0.5, 0.8]));
+        assert_eq!(board.lines[1].opacity, Some(0.5));
+        // Shaft, then one wing, the tip again and the other wing
+        assert_eq!(board.lines[2].points.len(), 5);
+        assert_eq!(board.lines[2].points[3], (100.0, 100.0));
+        assert_eq!(board.texts[0].text, "Hello");
+        assert_eq!((board.texts[0].x, board.texts[0].y), (20.0, 20.0));
+
+        assert_eq!(board.groups.len(), 2);
+        assert_eq!(board.groups[0].children, vec!["r", "f"]);
+        assert_eq!(board.groups[1].children, vec!["inner", "a"]);
+
+        assert_eq!(drawing.summary.converted.get("freedraw"), Some(&1));
+        assert_eq!(drawing.summary.approximated.get("rectangle"), Some(&1));
+        assert_eq!(drawing.summary.dropped.get("image"), Some(&1));
+        assert!(!drawing.summary.approximated.contains_key("ellipse"));
+    }
+
+    #[test]
+    fn test_rotation_and_other_files() {
+        let file = r##"{"type":"excalidraw","elements":[
+            {"id":"r","type":"rectangle","x":0,"y":0,"width":20,"height":10,"angle":3.14159265}
+        ]}"##;
+        let drawing = convert(file.as_bytes()).unwrap();
+        let (x, y) = drawing.pages[0].1.lines[0].points[0];
+        assert!((x - 20.0).abs() < 1e-3 && (y - 10.0).abs() < 1e-3);
+
+        assert_eq!(convert(br#"{"type":"tldraw","elements":[]}"#).err(), Some(ImportError::UnsupportedFormat { expected: "Excalidraw" }));
+        assert!(matches!(convert(b"not json"), Err(ImportError::Malformed(_))));
+    }
+}
@@ -0,0 +1,253 @@
+pub mod excalidraw;
+pub mod tldraw;
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::fmt::Display;
+use super::{ Frame, Line, LineCap, LineJoin, Point, Text, WhiteBoardData };
+use super::validation::{ normalize_color, validate_board, BoardLimits };
+
+const MAX_PAGES: usize = 100;
+/// Longest project or page name, in characters. Longer names in files are
+/// cut.
+pub const MAX_NAME_LENGTH: usize = 200;
+/// Segments used to approximate an ellipse.
+const ELLIPSE_SEGMENTS: usize = 48;
+
+/// Drawing tools whose files can be imported.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Excalidraw,
+    Tldraw,
+}
+
+/// What happened to the shapes of an imported file, counted by their type in
+/// that file.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    /// Shapes the board has an equivalent for.
+    pub converted: BTreeMap<String, usize>,
+    /// Shapes without an equivalent, drawn as freehand lines instead.
+    pub approximated: BTreeMap<String, usize>,
+    /// Shapes that could not be represented at all, such as images.
+    pub dropped: BTreeMap<String, usize>,
+}
+
+impl ImportSummary {
+    fn count(map: &mut BTreeMap<String, usize>, kind: &str) {
+        *map.entry(kind.to_string()).or_insert(0) += 1;
+    }
+
+    fn converted(&mut self, kind: &str) {
+        Self::count(&mut self.converted, kind);
+    }
+
+    fn approximated(&mut self, kind: &str) {
+        Self::count(&mut self.approximated, kind);
+    }
+
+    fn dropped(&mut self, kind: &str) {
+        Self::count(&mut self.dropped, kind);
+    }
+}
+
+/// A file converted to boards, one per page, checked against the limits.
+pub struct ImportedDrawing {
+    /// Document name, when the file records one.
+    pub name: Option<String>,
+    pub pages: Vec<(String, WhiteBoardData)>,
+    pub summary: ImportSummary,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    Malformed(String),
+    UnsupportedFormat { expected: &'static str },
+    PageCount { count: usize },
+    InvalidPage { page: String, reason: String },
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(reason) =>
+                write!(f, "malformed file: {}", reason),
+            Self::UnsupportedFormat { expected } =>
+                write!(f, "not a {} file", expected),
+            Self::PageCount { count } =>
+                write!(f, "file has {} pages, expected between 1 and {}", count, MAX_PAGES),
+            Self::InvalidPage { page, reason } =>
+                write!(f, "page '{}': {}", page, reason),
+        }
+    }
+}
+
+
+/// Converts a file of the given tool and validates every resulting board
+/// like one sent by a client.
+pub fn import(format: ImportFormat, bytes: &[u8], limits: &BoardLimits) -> Result<ImportedDrawing, ImportError> {
+    let mut drawing = match format {
+        ImportFormat::Excalidraw => excalidraw::convert(bytes)?,
+        ImportFormat::Tldraw => tldraw::convert(bytes)?,
+    };
+    if drawing.pages.is_empty() || drawing.pages.len() > MAX_PAGES {
+        return Err(ImportError::PageCount { count: drawing.pages.len() });
+    }
+    drawing.name = drawing.name.map(|name| truncate(&name));
+    for (name, board) in drawing.pages.iter_mut() {
+        *name = truncate(name);
+        validate_board(board, limits)
+            .map_err(|e| ImportError::InvalidPage { page: name.clone(), reason: e.to_string() })?;
+    }
+    return Ok(drawing);
+}
+
+fn truncate(name: &str) -> String {
+    return name.chars().take(MAX_NAME_LENGTH).collect();
+}
+
+
+/// Where a shape sits: its local origin on the board and its clockwise
+/// rotation around that origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Placement {
+    x: f32,
+    y: f32,
+    rotation: f32,
+}
+
+impl Placement {
+    const ORIGIN: Placement = Placement { x: 0.0, y: 0.0, rotation: 0.0 };
+
+    fn apply(&self, point: Point) -> Point {
+        let (sin, cos) = self.rotation.sin_cos();
+        return (self.x + point.0 * cos - point.1 * sin, self.y + point.0 * sin + point.1 * cos);
+    }
+
+    /// A placement given relative to this one.
+    fn then(&self, inner: &Placement) -> Placement {
+        let (x, y) = self.apply((inner.x, inner.y));
+        return Placement { x, y, rotation: self.rotation + inner.rotation };
+    }
+
+    /// A shape at `(x, y)` rotated by `rotation` around `center`.
+    fn around(x: f32, y: f32, center: Point, rotation: f32) -> Placement {
+        let rotated = Placement { x: center.0, y: center.1, rotation }.apply((x - center.0, y - center.1));
+        return Placement { x: rotated.0, y: rotated.1, rotation };
+    }
+}
+
+
+/// How a converted shape is stroked.
+struct Stroke {
+    color: String,
+    width: u32,
+    opacity: f32,
+    dash: Option<Vec<f32>>,
+}
+
+impl Stroke {
+    /// Unknown colors fall back to black, the board rejects them otherwise.
+    fn new(color: &str, width: f32, opacity: f32, dash_style: &str) -> Self {
+        let width = width.round().max(1.0);
+        let dash = match dash_style {
+            "dashed" => Some(vec![width * 4.0, width * 4.0]),
+            "dotted" => Some(vec![width, width * 3.0]),
+            _ => None,
+        };
+        return Self {
+            color: normalize_color(color).unwrap_or_else(|| "#000000".to_string()),
+            width: width as u32,
+            opacity: opacity.clamp(0.0, 1.0),
+            dash,
+        };
+    }
+}
+
+fn new_line(id: String, points: Vec<Point>, stroke: &Stroke, pressures: Option<Vec<f32>>) -> Line {
+    return Line {
+        id,
+        points,
+        color: stroke.color.clone(),
+        width: stroke.width,
+        pressures,
+        timestamps: None,
+        opacity: if stroke.opacity < 1.0 { Some(stroke.opacity) } else { None },
+        dash: stroke.dash.clone(),
+        cap: Some(LineCap::Round),
+        join: Some(LineJoin::Round),
+        packed_points: None,
+    };
+}
+
+/// Text has no opacity of its own, so it is folded into the color.
+fn new_text(id: String, placement: &Placement, text: String, color: &str, size: f32, opacity: f32) -> Text {
+    let color = normalize_color(color).unwrap_or_else(|| "#000000".to_string());
+    let alpha = if color.len() == 9 { u8::from_str_radix(&color[7..9], 16).unwrap_or(255) } else { 255 };
+    let alpha = (alpha as f32 * opacity.clamp(0.0, 1.0)).round() as u8;
+    let color = if alpha == 255 { color[..7].to_string() } else { format!("{}{:02x}", &color[..7], alpha) };
+    return Text {
+        id,
+        x: placement.x,
+        y: placement.y,
+        text,
+        color,
+        size,
+        rotation: if placement.rotation == 0.0 { None } else { Some(placement.rotation) },
+    };
+}
+
+fn new_frame(id: String, name: String, placement: &Placement, width: f32, height: f32) -> Frame {
+    // Frames are never rotated on the board
+    return Frame { id, name, x: placement.x, y: placement.y, width, height };
+}
+
+
+fn rectangle(width: f32, height: f32) -> Vec<Point> {
+    return vec![(0.0, 0.0), (width, 0.0), (width, height), (0.0, height), (0.0, 0.0)];
+}
+
+fn ellipse(width: f32, height: f32) -> Vec<Point> {
+    let (rx, ry) = (width / 2.0, height / 2.0);
+    return (0..=ELLIPSE_SEGMENTS)
+        .map(|i| {
+            let angle = 2.0 * PI * i as f32 / ELLIPSE_SEGMENTS as f32;
+            (rx + rx * angle.cos(), ry + ry * angle.sin())
+        })
+        .collect();
+}
+
+fn diamond(width: f32, height: f32) -> Vec<Point> {
+    let (cx, cy) = (width / 2.0, height / 2.0);
+    return vec![(cx, 0.0), (width, cy), (cx, height), (0.0, cy), (cx, 0.0)];
+}
+
+fn triangle(width: f32, height: f32) -> Vec<Point> {
+    return vec![(width / 2.0, 0.0), (width, height), (0.0, height), (width / 2.0, 0.0)];
+}
+
+/// Draws an arrowhead at the end of `points` by going from the tip to one
+/// wing, back to the tip and to the other wing, so that the arrow stays a
+/// single line.
+fn add_arrowhead(points: &mut Vec<Point>, width: u32) {
+    let tip = match points.last() {
+        Some(tip) => *tip,
+        None => return,
+    };
+    let from = match points.iter().rev().find(|p| **p != tip) {
+        Some(from) => *from,
+        None => return,
+    };
+    let angle = (tip.1 - from.1).atan2(tip.0 - from.0);
+    let length = (width as f32 * 4.0).max(10.0);
+    let wing = |offset: f32| (tip.0 - length * (angle + offset).cos(), tip.1 - length * (angle + offset).sin());
+    points.extend([wing(PI / 6.0), tip, wing(-PI / 6.0)]);
+}
+
+/// Same as `add_arrowhead`, at the start of the line.
+fn add_start_arrowhead(points: &mut Vec<Point>, width: u32) {
+    points.reverse();
+    add_arrowhead(points, width);
+    points.reverse();
+}
@@ -0,0 +1,418 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use super::{
+    add_arrowhead, add_start_arrowhead, diamond, ellipse, new_frame, new_line, new_text, rectangle, triangle,
+    ImportError, ImportSummary, ImportedDrawing, Placement, Stroke,
+};
+use crate::whiteboard::{ Group, Point, WhiteBoardData };
+
+/// Hex values of tldraw's named colors, as drawn on a light background.
+const COLORS: &[(&str, &str)] = &[
+    ("black", "#1d1d1d"),
+    ("grey", "#9fa8b2"),
+    ("light-violet", "#e085f4"),
+    ("violet", "#ae3ec9"),
+    ("blue", "#4465e9"),
+    ("light-blue", "#4ba1f1"),
+    ("yellow", "#f1ac4b"),
+    ("orange", "#e16919"),
+    ("green", "#099268"),
+    ("light-green", "#4cb05e"),
+    ("light-red", "#f87777"),
+    ("red", "#e03131"),
+    ("white", "#ffffff"),
+];
+
+/// A note is a square sticky of this size.
+const NOTE_SIZE: f32 = 200.0;
+
+
+/// One entry of `records`. Only pages, shapes and the document are used.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    id: String,
+    type_name: String,
+    #[serde(default)]
+    name: String,
+    /// Fractional index, ordering pages and siblings as strings.
+    #[serde(default)]
+    index: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    parent_id: String,
+    #[serde(default)]
+    x: f32,
+    #[serde(default)]
+    y: f32,
+    /// Clockwise rotation in radians around `(x, y)`.
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default)]
+    props: Value,
+}
+
+fn default_opacity() -> f32 {
+    return 1.0;
+}
+
+
+fn get_f32(props: &Value, key: &str, default: f32) -> f32 {
+    return props.get(key).and_then(|v| v.as_f64()).map(|v| v as f32).unwrap_or(default);
+}
+
+fn get_str<'a>(props: &'a Value, key: &str, default: &'a str) -> &'a str {
+    return props.get(key).and_then(|v| v.as_str()).unwrap_or(default);
+}
+
+fn get_color(props: &Value) -> &'static str {
+    let name = get_str(props, "color", "black");
+    return COLORS.iter().find(|(n, _)| *n == name).map(|(_, hex)| *hex).unwrap_or("#1d1d1d");
+}
+
+/// `scale` is only recorded by newer versions.
+fn get_scale(props: &Value) -> f32 {
+    return get_f32(props, "scale", 1.0);
+}
+
+fn get_stroke_width(props: &Value) -> f32 {
+    let width = match get_str(props, "size", "m") {
+        "s" => 2.0,
+        "l" => 5.0,
+        "xl" => 10.0,
+        _ => 3.5,
+    };
+    return width * get_scale(props);
+}
+
+fn get_font_size(props: &Value) -> f32 {
+    let size = match get_str(props, "size", "m") {
+        "s" => 18.0,
+        "l" => 36.0,
+        "xl" => 44.0,
+        _ => 24.0,
+    };
+    return size * get_scale(props);
+}
+
+/// Plain text of a shape. Older versions store `text`, newer ones a
+/// rich text document whose paragraphs become lines.
+fn get_text(props: &Value) -> String {
+    if let Some(text) = props.get("text").and_then(|t| t.as_str()) {
+        return text.to_string();
+    }
+    fn collect(node: &Value, out: &mut String) {
+        if let Some(text) = node.get("text").and_then(|t| t.as_str()) {
+            out.push_str(text);
+        }
+        if node.get("type").and_then(|t| t.as_str()) == Some("hardBreak") {
+            out.push('\n');
+        }
+        for child in node.get("content").and_then(|c| c.as_array()).into_iter().flatten() {
+            collect(child, out);
+        }
+    }
+    let paragraphs = props.get("richText")
+        .and_then(|doc| doc.get("content"))
+        .and_then(|c| c.as_array());
+    let lines: Vec<String> = paragraphs.into_iter().flatten()
+        .map(|paragraph| {
+            let mut line = String::new();
+            collect(paragraph, &mut line);
+            line
+        })
+        .collect();
+    return lines.join("\n");
+}
+
+/// Points of a `line` shape, stored as a map keyed by id in newer versions
+/// and as a list in older ones, each with its own fractional index.
+fn get_line_points(props: &Value) -> Vec<Point> {
+    let mut points: Vec<(&str, Point)> = match props.get("points") {
+        Some(Value::Object(map)) => map.values().collect::<Vec<&Value>>(),
+        Some(Value::Array(list)) => list.iter().collect(),
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .map(|p| (get_str(p, "index", ""), (get_f32(p, "x", 0.0), get_f32(p, "y", 0.0))))
+    .collect();
+    points.sort_by(|a, b| a.0.cmp(b.0));
+    return points.into_iter().map(|(_, p)| p).collect();
+}
+
+
+/// Converts the shapes of one page, walking frames and groups so that each
+/// shape is placed relative to its parent.
+struct PageConverter<'a> {
+    children: &'a HashMap<&'a str, Vec<&'a Record>>,
+    board: WhiteBoardData,
+    summary: &'a mut ImportSummary,
+}
+
+impl<'a> PageConverter<'a> {
+    /// Converts the children of `parent_id` and returns the ids of the board
+    /// elements they became.
+    fn convert_children(&mut self, parent_id: &str, parent: &Placement, opacity: f32) -> Vec<String> {
+        let children = match self.children.get(parent_id) {
+            Some(children) => children.clone(),
+            None => return Vec::new(),
+        };
+        let mut ids = Vec::new();
+        for shape in children {
+            let placement = parent.then(&Placement { x: shape.x, y: shape.y, rotation: shape.rotation });
+            if let Some(id) = self.convert_shape(shape, &placement, opacity * shape.opacity) {
+                ids.push(id);
+            }
+        }
+        return ids;
+    }
+
+    /// Adds the outline of a shape. Shapes with a label become the outline
+    /// and a text at `anchor`, grouped under the id of the shape.
+    fn add_outline(&mut self, shape: &Record, placement: &Placement, points: Vec<Point>, stroke: &Stroke, anchor: Point, opacity: f32) {
+        let points = points.into_iter().map(|p| placement.apply(p)).collect();
+        let text = get_text(&shape.props);
+        if text.trim().is_empty() {
+            self.board.lines.push(new_line(shape.id.clone(), points, stroke, None));
+            return;
+        }
+
+        let outline_id = format!("{}:outline", shape.id);
+        let text_id = format!("{}:text", shape.id);
+        self.board.lines.push(new_line(outline_id.clone(), points, stroke, None));
+        let text_placement = placement.then(&Placement { x: anchor.0, y: anchor.1, rotation: 0.0 });
+        let size = get_font_size(&shape.props);
+        self.board.texts.push(new_text(text_id.clone(), &text_placement, text, get_color(&shape.props), size, opacity));
+        self.board.groups.push(Group::new(shape.id.clone(), vec![outline_id, text_id]));
+    }
+
+    fn convert_shape(&mut self, shape: &Record, placement: &Placement, opacity: f32) -> Option<String> {
+        let props = &shape.props;
+        let stroke = Stroke::new(get_color(props), get_stroke_width(props), opacity, get_str(props, "dash", "draw"));
+
+        match shape.kind.as_str() {
+            "draw" | "highlight" => {
+                let mut points = Vec::new();
+                let mut pressures = Vec::new();
+                let segments = props.get("segments").and_then(|s| s.as_array());
+                for point in segments.into_iter().flatten().filter_map(|s| s.get("points")?.as_array()).flatten() {
+                    points.push(placement.apply((get_f32(point, "x", 0.0), get_f32(point, "y", 0.0))));
+                    pressures.push(get_f32(point, "z", 0.5));
+                }
+                // Newer versions compress points into a string the board can't read
+                if points.is_empty() {
+                    self.summary.dropped(&shape.kind);
+                    return None;
+                }
+                if props.get("isClosed").and_then(|c| c.as_bool()) == Some(true) {
+                    points.push(points[0]);
+                    pressures.push(pressures[0]);
+                }
+                let is_pen = props.get("isPen").and_then(|p| p.as_bool()) == Some(true);
+                let mut stroke = stroke;
+                if shape.kind == "highlight" {
+                    stroke.width *= 4;
+                    stroke.opacity *= 0.5;
+                    stroke.dash = None;
+                }
+                self.board.lines.push(new_line(shape.id.clone(), points, &stroke, if is_pen { Some(pressures) } else { None }));
+                self.summary.converted(&shape.kind);
+            }
+            "geo" => {
+                let geo = get_str(props, "geo", "rectangle");
+                let (width, height) = (get_f32(props, "w", 100.0), get_f32(props, "h", 100.0));
+                let outline = match geo {
+                    "ellipse" | "oval" => ellipse(width, height),
+                    "diamond" | "rhombus" => diamond(width, height),
+                    "triangle" => triangle(width, height),
+                    _ => rectangle(width, height),
+                };
+                let anchor = label_anchor(props, width, height);
+                self.add_outline(shape, placement, outline, &stroke, anchor, opacity);
+                self.summary.approximated(&format!("geo:{}", geo));
+            }
+            "note" => {
+                let side = NOTE_SIZE * get_scale(props);
+                let anchor = label_anchor(props, side, side);
+                self.add_outline(shape, placement, rectangle(side, side), &stroke, anchor, opacity);
+                self.summary.approximated(&shape.kind);
+            }
+            "line" => {
+                let points: Vec<Point> = get_line_points(props).into_iter().map(|p| placement.apply(p)).collect();
+                if points.is_empty() {
+                    self.summary.dropped(&shape.kind);
+                    return None;
+                }
+                self.board.lines.push(new_line(shape.id.clone(), points, &stroke, None));
+                self.summary.converted(&shape.kind);
+            }
+            "arrow" => {
+                let end_point = |key: &str| props.get(key).map(|p| (get_f32(p, "x", 0.0), get_f32(p, "y", 0.0))).unwrap_or((0.0, 0.0));
+                let (start, end) = (end_point("start"), end_point("end"));
+                let mut points = vec![start, end];
+                if get_str(props, "arrowheadEnd", "arrow") != "none" {
+                    add_arrowhead(&mut points, stroke.width);
+                }
+                if get_str(props, "arrowheadStart", "none") != "none" {
+                    add_start_arrowhead(&mut points, stroke.width);
+                }
+                // Labels sit on the middle of the arrow
+                let offset = label_anchor(props, 0.0, 0.0);
+                let anchor = ((start.0 + end.0) / 2.0 + offset.0, (start.1 + end.1) / 2.0 + offset.1);
+                self.add_outline(shape, placement, points, &stroke, anchor, opacity);
+                self.summary.converted(&shape.kind);
+            }
+            "text" => {
+                let size = get_font_size(props);
+                self.board.texts.push(new_text(shape.id.clone(), placement, get_text(props), get_color(props), size, opacity));
+                self.summary.converted(&shape.kind);
+            }
+            "frame" => {
+                let name = get_str(props, "name", "").trim();
+                let name = if name.is_empty() { "Frame" } else { name };
+                let (width, height) = (get_f32(props, "w", 0.0), get_f32(props, "h", 0.0));
+                self.board.frames.push(new_frame(shape.id.clone(), name.to_string(), placement, width, height));
+                self.summary.converted(&shape.kind);
+                self.convert_children(&shape.id, placement, opacity);
+            }
+            "group" => {
+                let children = self.convert_children(&shape.id, placement, opacity);
+                if children.is_empty() {
+                    return None;
+                }
+                self.board.groups.push(Group::new(shape.id.clone(), children));
+                self.summary.converted(&shape.kind);
+            }
+            _ => {
+                self.summary.dropped(&shape.kind);
+                return None;
+            }
+        }
+        return Some(shape.id.clone());
+    }
+}
+
+/// Top-left corner of the label of a shape centered in a `width` x `height`
+/// box, using the same estimate of text size as the exporters.
+fn label_anchor(props: &Value, width: f32, height: f32) -> Point {
+    let text = get_text(props);
+    let size = get_font_size(props);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let text_width = longest as f32 * size * 0.6;
+    let text_height = lines.len() as f32 * size * 1.25;
+    return ((width - text_width) / 2.0, (height - text_height) / 2.0);
+}
+
+
+/// Converts a `.tldr` file, one board per tldraw page.
+pub(super) fn convert(bytes: &[u8]) -> Result<ImportedDrawing, ImportError> {
+    let file: Value = serde_json::from_slice(bytes).map_err(|e| ImportError::Malformed(e.to_string()))?;
+    if file.get("tldrawFileFormatVersion").is_none() {
+        return Err(ImportError::UnsupportedFormat { expected: "tldraw" });
+    }
+    let records: Vec<Record> = serde_json::from_value(file.get("records").cloned().unwrap_or(Value::Null))
+        .map_err(|e| ImportError::Malformed(e.to_string()))?;
+
+    let name = records.iter()
+        .find(|r| r.type_name == "document")
+        .map(|r| r.name.trim().to_string())
+        .filter(|name| !name.is_empty());
+
+    let mut pages: Vec<&Record> = records.iter().filter(|r| r.type_name == "page").collect();
+    pages.sort_by(|a, b| a.index.cmp(&b.index));
+
+    let mut children: HashMap<&str, Vec<&Record>> = HashMap::new();
+    for shape in records.iter().filter(|r| r.type_name == "shape") {
+        children.entry(shape.parent_id.as_str()).or_default().push(shape);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by(|a, b| a.index.cmp(&b.index));
+    }
+
+    let mut summary = ImportSummary::default();
+    let mut boards = Vec::new();
+    for page in pages {
+        let mut converter = PageConverter { children: &children, board: WhiteBoardData::new_empty(), summary: &mut summary };
+        converter.convert_children(&page.id, &Placement::ORIGIN, 1.0);
+        let page_name = if page.name.trim().is_empty() { "Page" } else { page.name.trim() };
+        boards.push((page_name.to_string(), converter.board));
+    }
+
+    return Ok(ImportedDrawing { name, pages: boards, summary });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn file(records: Value) -> Vec<u8> {
+        return json!({ "tldrawFileFormatVersion": 1, "schema": {}, "records": records }).to_string().into_bytes();
+    }
+
+    #[test]
+    fn test_convert_pages_and_nested_shapes() {
+        let bytes = file(json!([
+            { "id": "document:document", "typeName": "document", "name": "Retro" },
+            { "id": "page:b", "typeName": "page", "name": "Second", "index": "a2" },
+            { "id": "page:a", "typeName": "page", "name": "First", "index": "a1" },
+            { "id": "shape:frame", "typeName": "shape", "type": "frame", "parentId": "page:a", "index": "a1",
+              "x": 100, "y": 100, "rotation": 0, "props": { "w": 400, "h": 300, "name": "Board" } },
+            { "id": "shape:draw", "typeName": "shape", "type": "draw", "parentId": "shape:frame", "index": "a1",
+              "x": 10, "y": 10, "rotation": 0, "opacity": 0.5,
+              "props": { "color": "red", "size": "l", "dash": "draw", "isPen": true,
+                         "segments": [{ "type": "free", "points": [{ "x": 0, "y": 0, "z": 0.3 }, { "x": 5, "y": 5, "z": 0.6 }] }] } },
+            { "id": "shape:box", "typeName": "shape", "type": "geo", "parentId": "page:a", "index": "a2",
+              "x": 0, "y": 0, "rotation": 0,
+              "props": { "geo": "ellipse", "w": 100, "h": 50, "color": "blue", "size": "m", "dash": "dashed",
+                         "richText": { "type": "doc", "content": [
+                             { "type": "paragraph", "content": [{ "type": "text", "text": "Hi" }] },
+                             { "type": "paragraph", "content": [{ "type": "text", "text": "there" }] } ] } } },
+            { "id": "shape:image", "typeName": "shape", "type": "image", "parentId": "page:b", "index": "a1",
+              "x": 0, "y": 0, "rotation": 0, "props": {} },
+            { "id": "shape:label", "typeName": "shape", "type": "text", "parentId": "page:b", "index": "a2",
+              "x": 5, "y": 5, "rotation": 0.5, "props": { "text": "Done", "size": "s", "color": "green" } }
+        ]));
+
+        let drawing = convert(&bytes).unwrap();
+        assert_eq!(drawing.name.as_deref(), Some("Retro"));
+        assert_eq!(drawing.pages.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(), vec!["First", "Second"]);
+
+        let first = &drawing.pages[0].1;
+        assert_eq!(first.frames[0].name, "Board");
+        let draw = first.lines.iter().find(|l| l.id == "shape:draw").unwrap();
+        assert_eq!(draw.points, vec![(110.0, 110.0), (115.0, 115.0)]);
+        assert_eq!(draw.pressures, Some(vec![0.3, 0.6]));
+        assert_eq!(draw.color, "#e03131");
+        assert_eq!(draw.width, 5);
+        assert_eq!(draw.opacity, Some(0.5));
+
+        let outline = first.lines.iter().find(|l| l.id == "shape:box:outline").unwrap();
+        assert_eq!(outline.color, "#4465e9");
+        assert!(outline.dash.is_some());
+        assert_eq!(first.texts[0].text, "Hi\nthere");
+        assert_eq!(first.groups[0].children, vec!["shape:box:outline", "shape:box:text"]);
+
+        let second = &drawing.pages[1].1;
+        assert_eq!(second.texts[0].rotation, Some(0.5));
+        assert_eq!(second.texts[0].size, 18.0);
+
+        assert_eq!(drawing.summary.converted.get("draw"), Some(&1));
+        assert_eq!(drawing.summary.approximated.get("geo:ellipse"), Some(&1));
+        assert_eq!(drawing.summary.dropped.get("image"), Some(&1));
+    }
+
+    #[test]
+    fn test_other_files_are_rejected() {
+        assert_eq!(
+            convert(br#"{"type":"excalidraw","elements":[]}"#).err(),
+            Some(ImportError::UnsupportedFormat { expected: "tldraw" })
+        );
+    }
+}
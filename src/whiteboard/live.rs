@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+use super::{ Group, Point, WhiteBoardData };
+use super::spatial::{ line_hit, Rect, SpatialIndex };
+use super::transform::Transform;
+use super::validation::{ validate_transformed, BoardLimits, ValidationError };
+
+/// A page's board held in memory while clients are connected, together with
+/// a spatial index of its elements. Every change goes through the methods
+/// below so that the index never falls behind the data.
+pub struct LiveBoard {
+    pub(super) data: WhiteBoardData,
+    pub(super) index: SpatialIndex,
+}
+
+impl LiveBoard {
+    pub fn new(data: WhiteBoardData) -> Self {
+        let index = SpatialIndex::build(&data);
+        return Self { data, index };
+    }
+
+    /// Replaces the board with a full drawing update. Only lines whose
+    /// bounding box changed are moved in the index.
+    pub fn set_data(&mut self, data: WhiteBoardData) {
+        let kept: HashSet<&str> = data.lines.iter().map(|l| l.id.as_str()).collect();
+        for line in self.data.lines.iter().filter(|l| !kept.contains(l.id.as_str())) {
+            self.index.remove(&line.id);
+        }
+        for line in data.lines.iter() {
+            self.index.update_line(line);
+        }
+        self.data = data;
+    }
+
+    pub fn apply_transform(&mut self, ids: &[String], transform: &Transform) -> usize {
+        let targets = self.data.expand_ids(ids);
+        let changed = self.data.apply_transform(ids, transform);
+        for line in self.data.lines.iter().filter(|l| targets.contains(&l.id)) {
+            self.index.update_line(line);
+        }
+        return changed;
+    }
+
+    pub fn validate_transform(&self, ids: &[String], transform: &Transform, limits: &BoardLimits) -> Result<(), ValidationError> {
+        return validate_transformed(&self.data, ids, transform, limits);
+    }
+
+    pub fn add_group(&mut self, group: Group) {
+        self.data.add_group(group);
+    }
+
+    pub fn remove_group(&mut self, group_id: &str) -> bool {
+        return self.data.remove_group(group_id);
+    }
+
+    /// The part of the board visible in `rect`: the lines crossing it, with
+    /// all groups, frames and texts of the page.
+    pub fn viewport(&self, rect: &Rect) -> WhiteBoardData {
+        let visible: HashSet<&str> = self.index.query_rect(rect).into_iter().collect();
+        return WhiteBoardData {
+            lines: self.data.lines.iter().filter(|l| visible.contains(l.id.as_str())).cloned().collect(),
+            groups: self.data.groups.clone(),
+            frames: self.data.frames.clone(),
+            texts: self.data.texts.clone(),
+        };
+    }
+
+    /// Ids of the lines whose stroke passes within `radius` of `point`.
+    pub fn hit_test(&self, point: Point, radius: f32) -> Vec<String> {
+        let candidates: HashSet<&str> = self.index.query_point(point, radius).into_iter().collect();
+        return self.data.lines.iter()
+            .filter(|l| candidates.contains(l.id.as_str()) && line_hit(l, point, radius))
+            .map(|l| l.id.clone())
+            .collect();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstar::AABB;
+
+    #[test]
+    fn test_index_follows_updates_and_transforms() {
+        let data: WhiteBoardData = serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[10,0]],"c":"#000","w":2},
+            {"id":"b","p":[[100,100],[110,100]],"c":"#000","w":2}
+        ]}"##).unwrap();
+        let mut board = LiveBoard::new(data);
+        assert_eq!(board.hit_test((5.0, 0.5), 1.0), vec!["a"]);
+
+        board.apply_transform(&["b".to_string()], &Transform::translate(-100.0, -90.0));
+        assert_eq!(board.hit_test((5.0, 10.0), 1.0), vec!["b"]);
+
+        let mut update = board.data.clone();
+        update.lines.remove(0);
+        board.set_data(update);
+        assert!(board.hit_test((5.0, 0.5), 1.0).is_empty());
+
+        let view = board.viewport(&AABB::from_corners([0.0, 0.0], [20.0, 20.0]));
+        assert_eq!(view.lines.len(), 1);
+    }
+}
@@ -1,30 +1,136 @@
 pub mod storage;
+pub mod transform;
+pub mod validation;
+pub mod simplify;
+pub mod presence;
+pub mod spatial;
+pub mod live;
+pub mod eraser;
+pub mod settings;
+pub mod export;
+pub mod archive;
+pub mod import;
+pub mod timelapse;
+pub mod diff;
 use serde::{ Deserialize, Serialize };
+use rand::{ distributions::Alphanumeric, Rng };
+use std::collections::HashSet;
+use transform::Transform;
+use simplify::DeltaPoints;
 
-type Point = (f32, f32);
+pub type Point = (f32, f32);
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct Line {
+    // Boards saved before elements had ids get one on their next update.
+    #[serde(default)]
+    id: String,
     #[serde(rename = "p")]
     points: Vec<Point>,
     #[serde(rename = "c")]
     color: String,
     #[serde(rename = "w")]
     width: u32,
+    // Optional stylus attributes. Absent fields keep the compact format that
+    // older clients send and expect.
+    /// Pressure in `0.0..=1.0` for each point, scaling `width`.
+    #[serde(rename = "pr", default, skip_serializing_if = "Option::is_none")]
+    pressures: Option<Vec<f32>>,
+    /// Milliseconds since the start of the stroke for each point.
+    #[serde(rename = "ts", default, skip_serializing_if = "Option::is_none")]
+    timestamps: Option<Vec<u32>>,
+    #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+    opacity: Option<f32>,
+    /// Alternating dash and gap lengths, as in SVG's `stroke-dasharray`.
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    dash: Option<Vec<f32>>,
+    #[serde(rename = "lc", default, skip_serializing_if = "Option::is_none")]
+    cap: Option<LineCap>,
+    #[serde(rename = "lj", default, skip_serializing_if = "Option::is_none")]
+    join: Option<LineJoin>,
+    /// Storage-only form of `points`, see `simplify::DeltaPoints`.
+    #[serde(rename = "pd", default, skip_serializing_if = "Option::is_none")]
+    packed_points: Option<DeltaPoints>,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct CursorPosition {
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineCap {
+    Butt,
+    Round,
+    Square,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineJoin {
+    Miter,
+    Round,
+    Bevel,
+}
+
+/// A named rectangle on a page that clients can navigate to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Frame {
+    id: String,
+    name: String,
     x: f32,
     y: f32,
-    #[serde(rename = "userId")]
-    user_id: String,
+    width: f32,
+    height: f32,
+}
+/// A block of text. `(x, y)` is the top-left corner of its first line and
+/// `size` the font size, both in board units. Lines are separated by `\n`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Text {
+    id: String,
+    x: f32,
+    y: f32,
+    text: String,
     color: String,
+    size: f32,
+    /// Clockwise rotation in radians around `(x, y)`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotation: Option<f32>,
+}
+
+impl Text {
+    pub fn get_id(&self) -> &str {
+        return &self.id;
+    }
+
+    pub fn get_text(&self) -> &str {
+        return &self.text;
+    }
+
+    pub fn get_position(&self) -> Point {
+        return (self.x, self.y);
+    }
 }
+
+/// A set of elements that are selected and transformed together. Children are
+/// element ids and may themselves be groups.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Group {
+    id: String,
+    children: Vec<String>,
+}
+
+impl Group {
+    pub fn new(id: String, children: Vec<String>) -> Self {
+        return Self { id, children };
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WhiteBoardData {
     lines: Vec<Line>,
-    #[serde(rename = "cursorPosition")]
-    cursor_position: Option<CursorPosition>,
+    #[serde(default)]
+    groups: Vec<Group>,
+    #[serde(default)]
+    frames: Vec<Frame>,
+    #[serde(default)]
+    texts: Vec<Text>,
 }
 
 
@@ -32,9 +138,105 @@ impl WhiteBoardData {
     pub fn new_empty() -> Self {
         return Self {
             lines: Vec::new(),
-            cursor_position: None
+            groups: Vec::new(),
+            frames: Vec::new(),
+            texts: Vec::new(),
         };
     }
+
+    pub fn get_frames(&self) -> &Vec<Frame> {
+        &self.frames
+    }
+
+    pub fn get_texts(&self) -> &Vec<Text> {
+        &self.texts
+    }
+
+    /// Gives an id to every line and text that arrived without one.
+    pub fn ensure_element_ids(&mut self) {
+        for line in self.lines.iter_mut() {
+            if line.id.is_empty() {
+                line.id = new_element_id();
+            }
+        }
+        for text in self.texts.iter_mut() {
+            if text.id.is_empty() {
+                text.id = new_element_id();
+            }
+        }
+    }
+
+    /// Resolves group ids to the ids of the elements they contain.
+    fn expand_ids(&self, ids: &[String]) -> HashSet<String> {
+        let mut expanded = HashSet::new();
+        let mut pending: Vec<&String> = ids.iter().collect();
+        let mut seen_groups = HashSet::new();
+
+        while let Some(id) = pending.pop() {
+            match self.groups.iter().find(|g| &g.id == id) {
+                Some(group) => {
+                    if seen_groups.insert(&group.id) {
+                        pending.extend(group.children.iter());
+                    }
+                }
+                None => {
+                    expanded.insert(id.clone());
+                }
+            }
+        }
+        return expanded;
+    }
+
+    /// Bakes `transform` into the points of the given elements and returns how
+    /// many lines and texts were changed. Group ids apply to all of their
+    /// members.
+    pub fn apply_transform(&mut self, ids: &[String], transform: &Transform) -> usize {
+        let targets = self.expand_ids(ids);
+        let width_scale = transform.width_scale();
+        let mut changed = 0;
+
+        for line in self.lines.iter_mut().filter(|l| targets.contains(&l.id)) {
+            for point in line.points.iter_mut() {
+                *point = transform.apply(*point);
+            }
+            line.width = ((line.width as f32) * width_scale).round().max(1.0) as u32;
+            if let Some(dash) = line.dash.as_mut() {
+                dash.iter_mut().for_each(|length| *length *= width_scale);
+            }
+            changed += 1;
+        }
+
+        let [a, b, ..] = transform.0;
+        for text in self.texts.iter_mut().filter(|t| targets.contains(&t.id)) {
+            (text.x, text.y) = transform.apply((text.x, text.y));
+            text.size = (text.size * width_scale).max(1.0);
+            let rotation = text.rotation.unwrap_or(0.0) + b.atan2(a);
+            text.rotation = if rotation == 0.0 { None } else { Some(rotation) };
+            changed += 1;
+        }
+        return changed;
+    }
+
+    pub fn add_group(&mut self, group: Group) {
+        self.groups.retain(|g| g.id != group.id);
+        self.groups.push(group);
+    }
+
+    /// Removes a group, leaving its members on the board.
+    pub fn remove_group(&mut self, group_id: &str) -> bool {
+        let before = self.groups.len();
+        self.groups.retain(|g| g.id != group_id);
+        return self.groups.len() != before;
+    }
+}
+
+
+pub fn new_element_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect()
 }
 
 
@@ -42,6 +244,7 @@ impl WhiteBoardData {
 mod tests {
     use super::storage::mongo::MongoDBStorage;
     use super::storage::WhiteBoardStorage;
+    use super::WhiteBoardData;
 
     use mongodb::Client;
     use tokio;
@@ -55,7 +258,7 @@ mod tests {
         let collection = database.collection("whiteboards");
 
         // Create MongoDBStorage instance
-        let mut storage = MongoDBStorage::new(1, collection.clone(), None);
+        let mut storage = MongoDBStorage::new(1, 1, collection.clone(), None);
 
         // Retrieve whiteboard data
         let retrieved_data = storage.get_whiteboard().await;
@@ -64,4 +267,41 @@ mod tests {
 
         println!("Test passed: Retrieved whiteboard data matches expected.");
     }
+
+    #[test]
+    fn test_compact_line_format_round_trips() {
+        let old_client = r##"{"lines":[{"p":[[1.0,2.0],[3.0,4.0]],"c":"#000","w":3}],"cursorPosition":null}"##;
+        let data: WhiteBoardData = serde_json::from_str(old_client).unwrap();
+        let line = serde_json::to_value(&data).unwrap()["lines"][0].clone();
+        assert_eq!(line, serde_json::json!({"id": "", "p": [[1.0, 2.0], [3.0, 4.0]], "c": "#000", "w": 3}));
+
+        let stylus = r##"{"lines":[{"p":[[0.0,0.0],[1.0,1.0]],"c":"#000","w":3,"pr":[0.2,0.9],"o":0.5,"lc":"round"}],"cursorPosition":null}"##;
+        let data: WhiteBoardData = serde_json::from_str(stylus).unwrap();
+        assert_eq!(data.lines[0].pressures, Some(vec![0.2, 0.9]));
+        assert_eq!(data.lines[0].cap, Some(super::LineCap::Round));
+    }
+
+    #[test]
+    fn test_stylus_attributes_round_trip() {
+        let stylus = serde_json::json!({
+            "id": "a", "p": [[0.0, 0.0], [1.0, 1.0]], "c": "#000", "w": 3,
+            "pr": [0.25, 1.0], "ts": [0, 16], "o": 0.5, "d": [4.0, 2.0], "lc": "square", "lj": "bevel",
+        });
+        let data: WhiteBoardData = serde_json::from_value(serde_json::json!({ "lines": [stylus.clone()] })).unwrap();
+        assert_eq!(data.lines[0].timestamps, Some(vec![0, 16]));
+        assert_eq!(data.lines[0].join, Some(super::LineJoin::Bevel));
+        assert_eq!(serde_json::to_value(&data).unwrap()["lines"][0], stylus);
+    }
+
+    #[test]
+    fn test_transform_scales_width_and_dash() {
+        let line = serde_json::json!({ "id": "a", "p": [[0.0, 0.0], [1.0, 1.0]], "c": "#000", "w": 3, "d": [4.0, 2.0] });
+        let mut data: WhiteBoardData = serde_json::from_value(serde_json::json!({ "lines": [line] })).unwrap();
+
+        let changed = data.apply_transform(&["a".to_string()], &super::Transform::scale(2.0, 2.0, (0.0, 0.0)));
+        assert_eq!(changed, 1);
+        assert_eq!(data.lines[0].points, vec![(0.0, 0.0), (2.0, 2.0)]);
+        assert_eq!(data.lines[0].width, 6);
+        assert_eq!(data.lines[0].dash, Some(vec![8.0, 4.0]));
+    }
 }
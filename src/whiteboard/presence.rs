@@ -0,0 +1,156 @@
+use redis::{ Client, AsyncCommands };
+use serde::{ Deserialize, Serialize };
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+
+/// `WHITEBOARD_PRESENCE_TTL`, seconds a user's presence is kept after their
+/// last cursor or presence update.
+pub static PRESENCE_TTL: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("WHITEBOARD_PRESENCE_TTL").ok().and_then(|v| v.parse().ok()).unwrap_or(30)
+});
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CursorPosition {
+    pub(super) x: f32,
+    pub(super) y: f32,
+    #[serde(rename = "userId")]
+    pub(super) user_id: String,
+    pub(super) color: String,
+}
+
+/// The part of the board a user is looking at.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Viewport {
+    pub(super) x: f32,
+    pub(super) y: f32,
+    pub(super) width: f32,
+    pub(super) height: f32,
+    pub(super) zoom: f32,
+}
+
+/// Ephemeral state of one user on one page. It is shared with the other
+/// users of the page but never saved with the board.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Presence {
+    user_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<CursorPosition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    selection: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    viewport: Option<Viewport>,
+    /// Milliseconds since the Unix epoch.
+    updated_at: u64,
+}
+
+impl Presence {
+    pub fn set_cursor(&mut self, cursor: CursorPosition) {
+        self.cursor = Some(cursor);
+    }
+
+    pub fn set_selection(&mut self, selection: Vec<String>) {
+        self.selection = Some(selection);
+    }
+
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = Some(viewport);
+    }
+
+    fn is_expired(&self, now: u64, ttl: u64) -> bool {
+        return now.saturating_sub(self.updated_at) > ttl * 1000;
+    }
+}
+
+
+/// Presence of every user on a page, kept in one Redis hash keyed by user id.
+/// Entries older than `PRESENCE_TTL` are dropped when the page is read, and
+/// the whole hash expires once nobody has updated it for that long.
+pub struct PresenceStore {
+    project_id: i64,
+    page_id: i64,
+    redis_cli: std::sync::Arc<Client>,
+}
+
+impl PresenceStore {
+    pub fn new(project_id: i64, page_id: i64, redis_cli: std::sync::Arc<Client>) -> Self {
+        return Self { project_id, page_id, redis_cli };
+    }
+
+    fn get_key(&self) -> String {
+        return format!("whiteboard_presence:{}:{}", self.project_id, self.page_id);
+    }
+
+    fn get_current_time_ms() -> u64 {
+        let since_the_epoch = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards");
+        return since_the_epoch.as_millis() as u64;
+    }
+
+    /// Loads the presence of `user_id`, lets `change` modify it and stores it
+    /// again with a fresh timestamp.
+    pub async fn update<F>(&self, user_id: i64, change: F) -> redis::RedisResult<()>
+    where
+        F: FnOnce(&mut Presence),
+    {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let key = self.get_key();
+        let now = Self::get_current_time_ms();
+
+        let stored: Option<String> = con.hget(&key, user_id).await?;
+        let mut presence = stored
+            .and_then(|value| serde_json::from_str::<Presence>(&value).ok())
+            .filter(|presence| !presence.is_expired(now, *PRESENCE_TTL))
+            .unwrap_or_default();
+        presence.user_id = user_id;
+        presence.updated_at = now;
+        change(&mut presence);
+
+        redis::pipe()
+            .hset(&key, user_id, serde_json::to_string(&presence).unwrap()).ignore()
+            .expire(&key, *PRESENCE_TTL as i64).ignore()
+            .query_async::<()>(&mut con)
+            .await
+    }
+
+    /// Everyone currently present on the page.
+    pub async fn list(&self) -> redis::RedisResult<Vec<Presence>> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let key = self.get_key();
+        let now = Self::get_current_time_ms();
+
+        let stored: HashMap<i64, String> = con.hgetall(&key).await?;
+        let mut present = Vec::new();
+        let mut expired = Vec::new();
+        for (user_id, value) in stored {
+            match serde_json::from_str::<Presence>(&value) {
+                Ok(presence) if !presence.is_expired(now, *PRESENCE_TTL) => present.push(presence),
+                _ => expired.push(user_id),
+            }
+        }
+        if !expired.is_empty() {
+            let _: () = con.hdel(&key, expired).await?;
+        }
+
+        present.sort_by_key(|presence| presence.user_id);
+        return Ok(present);
+    }
+
+    pub async fn remove(&self, user_id: i64) -> redis::RedisResult<()> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        con.hdel(self.get_key(), user_id).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presence_expires_after_ttl() {
+        let presence = Presence { updated_at: 10_000, ..Default::default() };
+        assert!(!presence.is_expired(39_000, 30));
+        assert!(presence.is_expired(40_001, 30));
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{ Deserialize, Serialize };
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GridType {
+    None,
+    Lines,
+    Dots,
+}
+
+/// The drawable area of a board. Clients keep the view and new strokes
+/// inside it; boards without bounds are infinite.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CanvasBounds {
+    pub(super) x: f32,
+    pub(super) y: f32,
+    pub(super) width: f32,
+    pub(super) height: f32,
+}
+
+/// Board-wide settings, shared by every page of a project. They are stored
+/// with the project, not in the board data, so that drawing updates from
+/// clients can never change them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BoardSettings {
+    pub(super) background: String,
+    pub(super) grid: GridType,
+    pub(super) grid_spacing: f32,
+    pub(super) snap_to_grid: bool,
+    pub(super) bounds: Option<CanvasBounds>,
+}
+
+impl BoardSettings {
+    pub fn get_background(&self) -> &str {
+        return &self.background;
+    }
+}
+
+impl Default for BoardSettings {
+    fn default() -> Self {
+        return Self {
+            background: "#ffffff".to_string(),
+            grid: GridType::None,
+            grid_spacing: 20.0,
+            snap_to_grid: false,
+            bounds: None,
+        };
+    }
+}
+
+/// A partial change of the settings. Missing fields are left as they are;
+/// `bounds: null` removes the bounds.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BoardSettingsUpdate {
+    background: Option<String>,
+    grid: Option<GridType>,
+    grid_spacing: Option<f32>,
+    snap_to_grid: Option<bool>,
+    #[serde(default, with = "double_option")]
+    bounds: Option<Option<CanvasBounds>>,
+}
+
+impl BoardSettingsUpdate {
+    pub fn apply_to(self, settings: &mut BoardSettings) {
+        if let Some(background) = self.background {
+            settings.background = background;
+        }
+        if let Some(grid) = self.grid {
+            settings.grid = grid;
+        }
+        if let Some(grid_spacing) = self.grid_spacing {
+            settings.grid_spacing = grid_spacing;
+        }
+        if let Some(snap_to_grid) = self.snap_to_grid {
+            settings.snap_to_grid = snap_to_grid;
+        }
+        if let Some(bounds) = self.bounds {
+            settings.bounds = bounds;
+        }
+    }
+}
+
+// Tells a missing field (`None`) apart from an explicit `null` (`Some(None)`).
+mod double_option {
+    use serde::{ Deserialize, Deserializer, Serialize, Serializer };
+
+    pub fn serialize<T: Serialize, S: Serializer>(value: &Option<Option<T>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(inner) => inner.serialize(serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+        return Option::<T>::deserialize(deserializer).map(Some);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_update() {
+        let mut settings = BoardSettings {
+            bounds: Some(CanvasBounds { x: 0.0, y: 0.0, width: 100.0, height: 100.0 }),
+            ..BoardSettings::default()
+        };
+
+        let update: BoardSettingsUpdate = serde_json::from_str(r#"{"grid":"dots","snap_to_grid":true}"#).unwrap();
+        update.apply_to(&mut settings);
+        assert_eq!(settings.grid, GridType::Dots);
+        assert!(settings.snap_to_grid);
+        assert!(settings.bounds.is_some());
+
+        let update: BoardSettingsUpdate = serde_json::from_str(r#"{"bounds":null}"#).unwrap();
+        update.apply_to(&mut settings);
+        assert_eq!(settings.bounds, None);
+        assert_eq!(settings.background, "#ffffff");
+    }
+}
@@ -0,0 +1,205 @@
+use serde::{ Deserialize, Serialize };
+use std::sync::LazyLock;
+use super::{ Line, Point, WhiteBoardData };
+
+
+pub static PIPELINE: LazyLock<SimplifyConfig> = LazyLock::new(SimplifyConfig::from_env);
+
+/// Settings of the optional ingest pipeline. A value of `0` disables a step.
+#[derive(Debug, Clone)]
+pub struct SimplifyConfig {
+    /// `WHITEBOARD_SIMPLIFY_TOLERANCE`, max distance in board units a point
+    /// may be moved by Ramer–Douglas–Peucker simplification.
+    pub tolerance: f32,
+    /// `WHITEBOARD_QUANTIZE_GRID`, coordinates are rounded to multiples of it.
+    pub grid: f32,
+    /// `WHITEBOARD_DELTA_STORAGE`, store points delta-encoded in Redis.
+    pub delta_storage: bool,
+}
+
+impl SimplifyConfig {
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok();
+        return Self {
+            tolerance: env("WHITEBOARD_SIMPLIFY_TOLERANCE").and_then(|v| v.parse().ok()).unwrap_or(0.0),
+            grid: env("WHITEBOARD_QUANTIZE_GRID").and_then(|v| v.parse().ok()).unwrap_or(0.0),
+            delta_storage: env("WHITEBOARD_DELTA_STORAGE").map(|v| v == "true" || v == "1").unwrap_or(false),
+        };
+    }
+
+    /// Grid used for delta-encoded storage. Without quantization the points
+    /// are stored with a precision of 1/100 of a board unit.
+    pub fn storage_grid(&self) -> f32 {
+        if self.grid > 0.0 { self.grid } else { 0.01 }
+    }
+
+    /// Simplifies and quantizes every line of the board in place.
+    pub fn apply(&self, board: &mut WhiteBoardData) {
+        for line in board.lines.iter_mut() {
+            if self.grid > 0.0 {
+                quantize_line(line, self.grid);
+            }
+            if self.tolerance > 0.0 {
+                simplify_line(line, self.tolerance);
+            }
+        }
+    }
+}
+
+
+/// Keeps only the points at `keep` (sorted indices), together with their
+/// pressure and timestamp values.
+fn retain_indices(line: &mut Line, keep: &[usize]) {
+    if keep.len() == line.points.len() {
+        return;
+    }
+    line.points = keep.iter().map(|&i| line.points[i]).collect();
+    if let Some(pressures) = line.pressures.as_mut() {
+        *pressures = keep.iter().map(|&i| pressures[i]).collect();
+    }
+    if let Some(timestamps) = line.timestamps.as_mut() {
+        *timestamps = keep.iter().map(|&i| timestamps[i]).collect();
+    }
+}
+
+fn quantize_line(line: &mut Line, grid: f32) {
+    for point in line.points.iter_mut() {
+        *point = ((point.0 / grid).round() * grid, (point.1 / grid).round() * grid);
+    }
+    // Neighbouring samples that collapsed onto the same grid point add nothing
+    let keep: Vec<usize> = (0..line.points.len())
+        .filter(|&i| i == 0 || line.points[i] != line.points[i - 1])
+        .collect();
+    retain_indices(line, &keep);
+}
+
+fn simplify_line(line: &mut Line, tolerance: f32) {
+    let keep = rdp_indices(&line.points, tolerance);
+    retain_indices(line, &keep);
+}
+
+pub(super) fn distance_to_segment(p: Point, a: Point, b: Point) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_sq = dx * dx + dy * dy;
+    if length_sq == 0.0 {
+        return ((p.0 - a.0).powi(2) + (p.1 - a.1).powi(2)).sqrt();
+    }
+    let t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_sq).clamp(0.0, 1.0);
+    let (px, py) = (a.0 + t * dx, a.1 + t * dy);
+    return ((p.0 - px).powi(2) + (p.1 - py).powi(2)).sqrt();
+}
+
+/// Ramer–Douglas–Peucker simplification, returning the sorted indices of the
+/// points to keep. The first and last points are always kept.
+pub fn rdp_indices(points: &[Point], tolerance: f32) -> Vec<usize> {
+    if points.len() < 3 {
+        return (0..points.len()).collect();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = ranges.pop() {
+        let mut farthest = (0, 0.0);
+        for i in start + 1..end {
+            let distance = distance_to_segment(points[i], points[start], points[end]);
+            if distance > farthest.1 {
+                farthest = (i, distance);
+            }
+        }
+        if farthest.1 > tolerance {
+            keep[farthest.0] = true;
+            ranges.push((start, farthest.0));
+            ranges.push((farthest.0, end));
+        }
+    }
+
+    return (0..points.len()).filter(|&i| keep[i]).collect();
+}
+
+
+/// Points stored as integer grid steps: the first point absolute, every
+/// following point relative to the previous one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeltaPoints {
+    #[serde(rename = "g")]
+    grid: f32,
+    #[serde(rename = "v")]
+    values: Vec<i64>,
+}
+
+impl DeltaPoints {
+    pub fn encode(points: &[Point], grid: f32) -> Self {
+        let mut values = Vec::with_capacity(points.len() * 2);
+        let mut previous = (0, 0);
+        for (x, y) in points {
+            let current = ((x / grid).round() as i64, (y / grid).round() as i64);
+            values.push(current.0 - previous.0);
+            values.push(current.1 - previous.1);
+            previous = current;
+        }
+        return Self { grid, values };
+    }
+
+    pub fn decode(&self) -> Vec<Point> {
+        let mut points = Vec::with_capacity(self.values.len() / 2);
+        let mut current = (0, 0);
+        for step in self.values.chunks_exact(2) {
+            current = (current.0 + step[0], current.1 + step[1]);
+            points.push((current.0 as f32 * self.grid, current.1 as f32 * self.grid));
+        }
+        return points;
+    }
+}
+
+impl WhiteBoardData {
+    /// Moves the points of every line into their delta-encoded form.
+    pub fn pack_points(&mut self, grid: f32) {
+        for line in self.lines.iter_mut() {
+            line.packed_points = Some(DeltaPoints::encode(&line.points, grid));
+            line.points = Vec::new();
+        }
+    }
+
+    /// Restores points of lines that were stored delta-encoded.
+    pub fn unpack_points(&mut self) {
+        for line in self.lines.iter_mut() {
+            if let Some(packed) = line.packed_points.take() {
+                line.points = packed.decode();
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rdp_drops_collinear_points() {
+        let points = vec![(0.0, 0.0), (1.0, 0.01), (2.0, 0.0), (3.0, 5.0), (4.0, 10.0)];
+        assert_eq!(rdp_indices(&points, 0.1), vec![0, 2, 4]);
+        assert_eq!(rdp_indices(&points, 0.0), vec![0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn test_simplify_keeps_pressures_aligned() {
+        let mut board: WhiteBoardData = serde_json::from_str(
+            r##"{"lines":[{"id":"a","p":[[0,0],[1.02,1],[2,2],[2,2.01],[5,2]],"c":"#000","w":2,"pr":[0.1,0.2,0.3,0.4,0.5]}],"cursorPosition":null}"##
+        ).unwrap();
+        SimplifyConfig { tolerance: 0.1, grid: 0.5, delta_storage: false }.apply(&mut board);
+        assert_eq!(board.lines[0].points, vec![(0.0, 0.0), (2.0, 2.0), (5.0, 2.0)]);
+        assert_eq!(board.lines[0].pressures, Some(vec![0.1, 0.3, 0.5]));
+    }
+
+    #[test]
+    fn test_delta_points_round_trip() {
+        let points = vec![(10.0, 10.0), (10.5, 11.0), (9.0, 12.5)];
+        let packed = DeltaPoints::encode(&points, 0.5);
+        assert_eq!(packed.values, vec![20, 20, 1, 2, -3, 3]);
+        assert_eq!(packed.decode(), points);
+    }
+}
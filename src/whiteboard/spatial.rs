@@ -0,0 +1,148 @@
+use rstar::{ RTree, RTreeObject, AABB };
+use std::collections::HashMap;
+use super::{ Line, Point, WhiteBoardData };
+use super::simplify::distance_to_segment;
+
+pub type Rect = AABB<[f32; 2]>;
+
+/// Bounding box of one element in the R-tree.
+#[derive(Debug, Clone, PartialEq)]
+struct ElementBox {
+    id: String,
+    envelope: Rect,
+}
+
+impl RTreeObject for ElementBox {
+    type Envelope = Rect;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+/// Bounding box of a line, grown by half its width so that the whole stroke
+/// is covered.
+pub(super) fn line_bounds(line: &Line) -> Option<Rect> {
+    let first = line.points.first()?;
+    let (mut min, mut max) = (*first, *first);
+    for (x, y) in &line.points {
+        min = (min.0.min(*x), min.1.min(*y));
+        max = (max.0.max(*x), max.1.max(*y));
+    }
+    let half_width = line.width as f32 / 2.0;
+    return Some(AABB::from_corners(
+        [min.0 - half_width, min.1 - half_width],
+        [max.0 + half_width, max.1 + half_width],
+    ));
+}
+
+/// R-tree of the bounding boxes of a board's lines, keyed by element id.
+#[derive(Debug, Default)]
+pub struct SpatialIndex {
+    tree: RTree<ElementBox>,
+    boxes: HashMap<String, Rect>,
+}
+
+impl SpatialIndex {
+    pub fn build(board: &WhiteBoardData) -> Self {
+        let elements: Vec<ElementBox> = board.lines.iter()
+            .filter_map(|line| Some(ElementBox { id: line.id.clone(), envelope: line_bounds(line)? }))
+            .collect();
+        let boxes = elements.iter().map(|e| (e.id.clone(), e.envelope)).collect();
+        return Self { tree: RTree::bulk_load(elements), boxes };
+    }
+
+    /// Indexes a line again after it was added or changed.
+    pub(super) fn update_line(&mut self, line: &Line) {
+        match line_bounds(line) {
+            Some(envelope) if self.boxes.get(&line.id) == Some(&envelope) => {}
+            Some(envelope) => {
+                self.remove(&line.id);
+                self.tree.insert(ElementBox { id: line.id.clone(), envelope });
+                self.boxes.insert(line.id.clone(), envelope);
+            }
+            None => self.remove(&line.id),
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if let Some(envelope) = self.boxes.remove(id) {
+            self.tree.remove(&ElementBox { id: id.to_string(), envelope });
+        }
+    }
+
+    /// Ids of the elements whose bounding box intersects `rect`.
+    pub fn query_rect(&self, rect: &Rect) -> Vec<&str> {
+        return self.tree
+            .locate_in_envelope_intersecting(rect)
+            .map(|e| e.id.as_str())
+            .collect();
+    }
+
+    /// Ids of the elements whose bounding box is within `radius` of `point`.
+    /// Callers that need an exact answer check the candidates with `line_hit`.
+    pub fn query_point(&self, point: Point, radius: f32) -> Vec<&str> {
+        let rect = AABB::from_corners(
+            [point.0 - radius, point.1 - radius],
+            [point.0 + radius, point.1 + radius],
+        );
+        return self.query_rect(&rect);
+    }
+}
+
+/// Whether a circle of `radius` around `point` touches the stroke of `line`.
+pub(super) fn line_hit(line: &Line, point: Point, radius: f32) -> bool {
+    let reach = radius + line.width as f32 / 2.0;
+    if line.points.len() == 1 {
+        return distance_to_segment(point, line.points[0], line.points[0]) <= reach;
+    }
+    return line.points.windows(2).any(|segment| distance_to_segment(point, segment[0], segment[1]) <= reach);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> WhiteBoardData {
+        serde_json::from_str(r##"{"lines":[
+            {"id":"a","p":[[0,0],[10,0]],"c":"#000","w":2},
+            {"id":"b","p":[[100,100],[110,120]],"c":"#000","w":2},
+            {"id":"c","p":[[0,10],[10,20]],"c":"#000","w":4}
+        ]}"##).unwrap()
+    }
+
+    fn sorted(mut ids: Vec<&str>) -> Vec<&str> {
+        ids.sort();
+        return ids;
+    }
+
+    #[test]
+    fn test_query_rect_and_point() {
+        let board = board();
+        let index = SpatialIndex::build(&board);
+        assert_eq!(index.boxes.len(), 3);
+
+        let view = AABB::from_corners([-5.0, -5.0], [20.0, 20.0]);
+        assert_eq!(sorted(index.query_rect(&view)), vec!["a", "c"]);
+        assert_eq!(index.query_point((105.0, 110.0), 1.0), vec!["b"]);
+
+        assert!(line_hit(&board.lines[0], (5.0, 1.5), 0.5));
+        assert!(!line_hit(&board.lines[0], (5.0, 3.0), 0.5));
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        let mut board = board();
+        let mut index = SpatialIndex::build(&board);
+
+        board.lines[1].points = vec![(0.0, 0.0), (1.0, 1.0)];
+        index.update_line(&board.lines[1]);
+        let view = AABB::from_corners([-5.0, -5.0], [5.0, 5.0]);
+        assert_eq!(sorted(index.query_rect(&view)), vec!["a", "b"]);
+
+        index.remove("a");
+        assert_eq!(index.query_rect(&view), vec!["b"]);
+        assert_eq!(index.boxes.len(), 2);
+    }
+}
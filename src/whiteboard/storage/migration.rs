@@ -0,0 +1,267 @@
+use mongodb::{ bson::{ doc, Bson, Document, to_document }, Collection };
+use futures::TryStreamExt;
+use serde_json::{ json, Value };
+use sqlx::PgPool;
+use std::fmt::Display;
+use crate::project::page::Page;
+use crate::whiteboard::new_element_id;
+use crate::whiteboard::settings::BoardSettings;
+
+/// Version written into every stored board. Bump it together with a new
+/// entry in `MIGRATIONS` and a fixture in `fixtures/` whenever the stored
+/// shape of `WhiteBoardData` changes.
+pub const CURRENT_SCHEMA_VERSION: u32 = 6;
+
+/// Where the board being migrated belongs. Needed by migrations that add
+/// data older documents did not record.
+pub struct MigrationContext {
+    project_id: i64,
+    page_id: i64,
+}
+
+impl MigrationContext {
+    pub fn new(project_id: i64, page_id: i64) -> Self {
+        return Self { project_id, page_id };
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MigrationError {
+    NewerVersion { found: u32 },
+    InvalidDocument(String),
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NewerVersion { found } =>
+                write!(f, "board schema version {} is newer than the supported version {}", found, CURRENT_SCHEMA_VERSION),
+            Self::InvalidDocument(reason) =>
+                write!(f, "invalid board document: {}", reason),
+        }
+    }
+}
+
+type Migration = fn(&mut Value, &MigrationContext) -> Result<(), MigrationError>;
+
+/// `MIGRATIONS[i]` upgrades a document from version `i + 1` to `i + 2`.
+const MIGRATIONS: &[Migration] = &[
+    v1_to_v2,
+    v2_to_v3,
+    v3_to_v4,
+    v4_to_v5,
+    v5_to_v6,
+];
+
+/// Version 1 is the original format without pages, element ids or a version
+/// field. Version 2 records the page, gives every line an id and always has
+/// `groups` and `frames` lists.
+fn v1_to_v2(document: &mut Value, context: &MigrationContext) -> Result<(), MigrationError> {
+    let object = document.as_object_mut()
+        .ok_or_else(|| MigrationError::InvalidDocument("not an object".to_string()))?;
+
+    object.entry("project_id").or_insert(json!(context.project_id));
+    if object.get("page_id").is_none_or(|v| v.is_null()) {
+        object.insert("page_id".to_string(), json!(context.page_id));
+    }
+
+    let data = object.get_mut("data")
+        .and_then(|d| d.as_object_mut())
+        .ok_or_else(|| MigrationError::InvalidDocument("missing 'data'".to_string()))?;
+
+    data.entry("groups").or_insert(json!([]));
+    data.entry("frames").or_insert(json!([]));
+    let lines = data.entry("lines").or_insert(json!([]));
+    let lines = lines.as_array_mut()
+        .ok_or_else(|| MigrationError::InvalidDocument("'lines' is not a list".to_string()))?;
+
+    for line in lines.iter_mut() {
+        let line = line.as_object_mut()
+            .ok_or_else(|| MigrationError::InvalidDocument("line is not an object".to_string()))?;
+        let has_id = line.get("id").and_then(|id| id.as_str()).is_some_and(|id| !id.is_empty());
+        if !has_id {
+            line.insert("id".to_string(), json!(new_element_id()));
+        }
+    }
+    return Ok(());
+}
+
+/// Version 3 no longer stores the last cursor with the board, presence is
+/// kept apart in `whiteboard::presence`.
+fn v2_to_v3(document: &mut Value, _context: &MigrationContext) -> Result<(), MigrationError> {
+    let data = document.get_mut("data")
+        .and_then(|d| d.as_object_mut())
+        .ok_or_else(|| MigrationError::InvalidDocument("missing 'data'".to_string()))?;
+    data.remove("cursorPosition");
+    return Ok(());
+}
+
+/// Version 4 stores board settings next to the data.
+fn v3_to_v4(document: &mut Value, _context: &MigrationContext) -> Result<(), MigrationError> {
+    let object = document.as_object_mut()
+        .ok_or_else(|| MigrationError::InvalidDocument("not an object".to_string()))?;
+    object.entry("settings").or_insert(json!(BoardSettings::default()));
+    return Ok(());
+}
+
+/// Version 5 adds text elements and always has a `texts` list.
+fn v4_to_v5(document: &mut Value, _context: &MigrationContext) -> Result<(), MigrationError> {
+    let data = document.get_mut("data")
+        .and_then(|d| d.as_object_mut())
+        .ok_or_else(|| MigrationError::InvalidDocument("missing 'data'".to_string()))?;
+    data.entry("texts").or_insert(json!([]));
+    return Ok(());
+}
+
+/// Version 6 no longer stores settings with the board, they are shared by
+/// every page of the project and kept with the project.
+fn v5_to_v6(document: &mut Value, _context: &MigrationContext) -> Result<(), MigrationError> {
+    let object = document.as_object_mut()
+        .ok_or_else(|| MigrationError::InvalidDocument("not an object".to_string()))?;
+    object.remove("settings");
+    return Ok(());
+}
+
+pub fn get_schema_version(document: &Value) -> u32 {
+    return document.get("schema_version")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .unwrap_or(1);
+}
+
+/// Upgrades a stored board, in its JSON form, to `CURRENT_SCHEMA_VERSION`.
+pub fn migrate(mut document: Value, context: &MigrationContext) -> Result<Value, MigrationError> {
+    let version = get_schema_version(&document);
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(MigrationError::NewerVersion { found: version });
+    }
+
+    for migration in &MIGRATIONS[(version.max(1) - 1) as usize..] {
+        migration(&mut document, context)?;
+    }
+
+    if let Some(object) = document.as_object_mut() {
+        object.insert("schema_version".to_string(), json!(CURRENT_SCHEMA_VERSION));
+    }
+    return Ok(document);
+}
+
+/// Converts a Mongo document, without its `_id`, to the JSON form migrations
+/// work on.
+pub fn document_to_json(document: Document) -> Value {
+    return Bson::Document(document).into_relaxed_extjson();
+}
+
+
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub scanned: u64,
+    pub migrated: u64,
+    pub failed: u64,
+}
+
+/// Rewrites every stored board in `collection` to the latest schema. Boards
+/// saved before pages existed are assigned to the first page of their
+/// project.
+pub async fn migrate_collection(collection: &Collection<Document>, pg_pool: &PgPool) -> Result<MigrationReport, String> {
+    let mut report = MigrationReport::default();
+    let outdated = doc! { "$or": [
+        { "schema_version": { "$exists": false } },
+        { "schema_version": { "$lt": CURRENT_SCHEMA_VERSION } },
+    ] };
+    let mut cursor = collection.find(outdated).await.map_err(|e| e.to_string())?;
+
+    while let Some(mut document) = cursor.try_next().await.map_err(|e| e.to_string())? {
+        report.scanned += 1;
+        let id = match document.remove("_id") {
+            Some(id) => id,
+            None => {
+                report.failed += 1;
+                continue;
+            }
+        };
+        let project_id = document.get_i64("project_id")
+            .or_else(|_| document.get_i32("project_id").map(|v| v as i64))
+            .unwrap_or(0);
+        let page_id = match document.get_i64("page_id") {
+            Ok(page_id) => page_id,
+            Err(_) => match Page::get_first_page(pg_pool, project_id).await {
+                Ok(Some(page)) => page.get_id().unwrap(),
+                _ => {
+                    println!("[{}] skipped: project {} has no pages", id, project_id);
+                    report.failed += 1;
+                    continue;
+                }
+            },
+        };
+
+        let context = MigrationContext::new(project_id, page_id);
+        let migrated = migrate(document_to_json(document), &context)
+            .map_err(|e| e.to_string())
+            .and_then(|value| to_document(&value).map_err(|e| e.to_string()));
+
+        match migrated {
+            Ok(mut replacement) => {
+                replacement.insert("_id", id.clone());
+                collection.replace_one(doc! { "_id": id }, replacement).await.map_err(|e| e.to_string())?;
+                report.migrated += 1;
+            }
+            Err(e) => {
+                println!("[{}] failed: {}", id, e);
+                report.failed += 1;
+            }
+        }
+    }
+
+    return Ok(report);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::whiteboard::WhiteBoardData;
+
+    const FIXTURES: &[(u32, &str)] = &[
+        (1, include_str!("fixtures/v1.json")),
+        (2, include_str!("fixtures/v2.json")),
+        (3, include_str!("fixtures/v3.json")),
+        (4, include_str!("fixtures/v4.json")),
+        (5, include_str!("fixtures/v5.json")),
+        (6, include_str!("fixtures/v6.json")),
+    ];
+
+    #[test]
+    fn test_every_historical_version_has_a_fixture() {
+        let versions: Vec<u32> = FIXTURES.iter().map(|(v, _)| *v).collect();
+        assert_eq!(versions, (1..=CURRENT_SCHEMA_VERSION).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_fixtures_migrate_to_current_version() {
+        for (version, fixture) in FIXTURES {
+            let document: Value = serde_json::from_str(fixture).unwrap();
+            assert_eq!(get_schema_version(&document), *version);
+
+            let migrated = migrate(document, &MigrationContext::new(7, 12)).unwrap();
+            assert_eq!(get_schema_version(&migrated), CURRENT_SCHEMA_VERSION, "fixture v{}", version);
+            assert_eq!(migrated["page_id"], json!(12), "fixture v{}", version);
+            assert!(migrated["data"].get("cursorPosition").is_none(), "fixture v{}", version);
+            assert!(migrated.get("settings").is_none(), "fixture v{}", version);
+            assert!(migrated["data"]["texts"].is_array(), "fixture v{}", version);
+
+            let board: WhiteBoardData = serde_json::from_value(migrated["data"].clone()).unwrap();
+            assert!(board.lines.iter().all(|l| !l.id.is_empty()), "fixture v{}", version);
+            assert_eq!(board.lines.len(), 2, "fixture v{}", version);
+        }
+    }
+
+    #[test]
+    fn test_newer_versions_are_rejected() {
+        let document = json!({ "schema_version": CURRENT_SCHEMA_VERSION + 1, "data": {} });
+        assert_eq!(
+            migrate(document, &MigrationContext::new(1, 1)),
+            Err(MigrationError::NewerVersion { found: CURRENT_SCHEMA_VERSION + 1 })
+        );
+    }
+}
@@ -1,11 +1,19 @@
+pub mod migration;
 pub mod mongo;
+pub mod oplog;
 pub mod redis;
+pub mod stats;
+pub mod thumbnail;
 use crate::whiteboard::WhiteBoardData;
 
 pub trait WhiteBoardStorage {
-    async fn get_saving_data(&mut self) -> String;
-    async fn save(&mut self);
-    async fn set_whiteboard(&mut self, value: WhiteBoardData);
-    async fn get_whiteboard(&mut self) -> &WhiteBoardData;
+    async fn get_saving_data(&mut self) -> Result<String, String>;
+    async fn save(&mut self) -> Result<(), String>;
+    async fn set_whiteboard(&mut self, value: WhiteBoardData) -> Result<(), String>;
+    /// The board of the page, empty when it has none yet. Fails when the
+    /// stored board can't be read.
+    async fn get_whiteboard(&mut self) -> Result<&WhiteBoardData, String>;
+    async fn delete(&mut self);
     fn get_project_id(&self) -> i64;
-}
\ No newline at end of file
+    fn get_page_id(&self) -> i64;
+}
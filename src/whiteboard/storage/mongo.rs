@@ -1,21 +1,30 @@
-use mongodb::{ bson::{ doc, oid::ObjectId, Document, to_document, from_document }, Collection };
+use mongodb::{ bson::{ doc, oid::ObjectId, Document, to_document }, Collection };
 use serde::{ Serialize, Deserialize };
 use serde_json::Value;
+use sqlx::PgPool;
+use std::sync::Arc;
+use crate::project::page::Page;
 use crate::whiteboard::WhiteBoardData;
 use super::WhiteBoardStorage;
+use super::migration::{ self, MigrationContext, CURRENT_SCHEMA_VERSION };
 #[derive(Serialize, Deserialize, Debug)]
 struct MongodbSavingData {
-    #[serde(rename = "_id")]
+    // Filled in from the raw document, migrations only see the other fields.
+    #[serde(rename = "_id", skip_deserializing, default = "ObjectId::new")]
     id: ObjectId,
+    schema_version: u32,
     project_id: i64,
+    page_id: i64,
     data: WhiteBoardData,
 }
 
 impl MongodbSavingData {
-    fn new(id: ObjectId, project_id: i64, data: WhiteBoardData) -> Self {
+    fn new(id: ObjectId, project_id: i64, page_id: i64, data: WhiteBoardData) -> Self {
         return Self {
             id,
+            schema_version: CURRENT_SCHEMA_VERSION,
             project_id,
+            page_id,
             data,
         };
     }
@@ -23,40 +32,99 @@ impl MongodbSavingData {
 
 pub struct MongoDBStorage {
     project_id: i64,
+    page_id: i64,
     collection: Collection<Document>,
     object_id: Option<ObjectId>,
     whiteboard: Option<WhiteBoardData>,
+    // Needed to find the first page of the project for a legacy document
+    pg_pool: Option<Arc<PgPool>>,
 }
 
 impl MongoDBStorage {
     pub fn new(
         project_id: i64,
+        page_id: i64,
         collection: Collection<Document>,
         object_id: Option<ObjectId>
     ) -> Self {
         return Self {
             project_id: project_id,
+            page_id: page_id,
             collection: collection,
             object_id: object_id,
             whiteboard: None,
+            pg_pool: None,
         };
     }
 
-    async fn load_whiteboard_data(&self) -> Result<WhiteBoardData, String> {
-        let filter = doc! { "project_id": self.get_project_id() };
-        let query_result = self.collection.find_one(filter).await;
+    /// Lets the storage hand a board saved before pages existed to the first
+    /// page of its project. Without it such a board is never loaded.
+    pub fn with_pg_pool(mut self, pg_pool: Arc<PgPool>) -> Self {
+        self.pg_pool = Some(pg_pool);
+        return self;
+    }
+
+    /// The stored board, `None` when the page has none yet.
+    async fn load_whiteboard_data(&mut self) -> Result<Option<WhiteBoardData>, String> {
+        let filter = doc! { "project_id": self.get_project_id(), "page_id": self.get_page_id() };
+        let query_result = self.collection.find_one(filter).await
+            .map_err(|e| e.to_string())?;
+
+        match query_result {
+            None => {
+                return self.adopt_legacy_document().await;
+            }
+            Some(value) => {
+                return self.decode_document(value).map(Some);
+            }
+        }
+    }
+
+    // A project saved before pages existed has a single document without a
+    // page id. The first page of the project takes it over when loaded.
+    async fn adopt_legacy_document(&mut self) -> Result<Option<WhiteBoardData>, String> {
+        let pg_pool = match &self.pg_pool {
+            Some(pg_pool) => pg_pool,
+            None => return Ok(None),
+        };
+        let first_page = Page::get_first_page(pg_pool, self.get_project_id()).await
+            .map_err(|e| e.to_string())?;
+        if first_page.and_then(|page| page.get_id()) != Some(self.get_page_id()) {
+            return Ok(None);
+        }
+
+        let filter = doc! { "project_id": self.get_project_id(), "page_id": { "$exists": false } };
+        let update = doc! { "$set": { "page_id": self.get_page_id() } };
+        let query_result = self.collection.find_one_and_update(filter, update).await
+            .map_err(|e| e.to_string())?;
 
-        match query_result.unwrap() {
+        match query_result {
             None => {
-                return Err("No whiteboard data found in mongo collection.".to_string());
+                return Ok(None);
             }
             Some(value) => {
-                let saving_data: MongodbSavingData = from_document(value).unwrap();
-                return Ok(saving_data.data);
+                return self.decode_document(value).map(Some);
             }
         }
     }
 
+    /// Brings a stored document up to `CURRENT_SCHEMA_VERSION` and reads the
+    /// board out of it. The document itself is rewritten on the next save.
+    fn decode_document(&mut self, mut document: Document) -> Result<WhiteBoardData, String> {
+        let object_id = document.get_object_id("_id").map_err(|e| e.to_string())?;
+        document.remove("_id");
+
+        let context = MigrationContext::new(self.get_project_id(), self.get_page_id());
+        let value = migration::migrate(migration::document_to_json(document), &context)
+            .map_err(|e| e.to_string())?;
+        let mut saving_data: MongodbSavingData = serde_json::from_value(value)
+            .map_err(|e| e.to_string())?;
+
+        self.object_id = Some(object_id);
+        saving_data.data.unpack_points();
+        return Ok(saving_data.data);
+    }
+
     fn get_document_object_id(&mut self) -> ObjectId {
         if self.object_id.is_none() {
             self.object_id = Some(ObjectId::new());
@@ -66,53 +134,69 @@ impl MongoDBStorage {
     }
 }
 impl WhiteBoardStorage for MongoDBStorage {
-    async fn get_saving_data(&mut self) -> String {
-        let data = self.get_whiteboard().await.clone();
+    async fn get_saving_data(&mut self) -> Result<String, String> {
+        let data = self.get_whiteboard().await?.clone();
         let object_id = self.get_document_object_id();
 
-        let saving_data = MongodbSavingData::new(object_id, self.project_id, data);
+        let saving_data = MongodbSavingData::new(object_id, self.project_id, self.page_id, data);
 
-        return serde_json::to_string(&saving_data).unwrap();
+        return serde_json::to_string(&saving_data).map_err(|e| e.to_string());
     }
 
-    async fn save(&mut self) {
+    async fn save(&mut self) -> Result<(), String> {
+        let data = self.get_saving_data().await?;
         let doc_id = self.get_document_object_id();
-        let data = self.get_saving_data();
 
         let filter = doc! { "_id": doc_id };
-        let json_value: Value = serde_json::from_str(data.await.as_str()).unwrap();
+        let json_value: Value = serde_json::from_str(data.as_str()).map_err(|e| e.to_string())?;
 
         // Convert serde_json::Value to bson::Document
-        let update_body: Document = to_document(&json_value).unwrap();
+        let update_body: Document = to_document(&json_value).map_err(|e| e.to_string())?;
 
         // Wrap the update body in a "$set" operation
         let update = doc! { "$set": update_body };
-        let result = self.collection.update_one(filter, update).upsert(true).await.unwrap();
+        let result = self.collection.update_one(filter, update).upsert(true).await
+            .map_err(|e| e.to_string())?;
 
         if result.matched_count > 0 {
             println!("Document updated.")
         } else {
             println!("New document inserted.")
         }
+        return Ok(());
     }
 
-    async fn set_whiteboard(&mut self, value: WhiteBoardData) {
+    async fn set_whiteboard(&mut self, value: WhiteBoardData) -> Result<(), String> {
         self.whiteboard = Some(value);
+        return Ok(());
     }
 
-    async fn get_whiteboard(&mut self) -> &WhiteBoardData {
+    // A page without a stored board is empty. A board that can't be read
+    // is an error, an empty one in its place would overwrite it on save.
+    async fn get_whiteboard(&mut self) -> Result<&WhiteBoardData, String> {
         if self.whiteboard.is_none() {
-            println!("Whiteboard data is empty");
-            let whiteboard = self
-                .load_whiteboard_data().await
-                .unwrap_or(WhiteBoardData::new_empty());
+            let whiteboard = self.load_whiteboard_data().await?
+                .unwrap_or_else(WhiteBoardData::new_empty);
             self.whiteboard = Some(whiteboard);
         }
 
-        return self.whiteboard.as_ref().unwrap();
+        return Ok(self.whiteboard.as_ref().unwrap());
+    }
+
+    async fn delete(&mut self) {
+        let filter = doc! { "project_id": self.get_project_id(), "page_id": self.get_page_id() };
+        match self.collection.delete_many(filter).await {
+            Ok(result) => println!("{} whiteboard document(s) deleted.", result.deleted_count),
+            Err(e) => println!("Failed to delete whiteboard documents: {}", e),
+        }
+        self.whiteboard = None;
     }
 
     fn get_project_id(&self) -> i64 {
         return self.project_id;
     }
+
+    fn get_page_id(&self) -> i64 {
+        return self.page_id;
+    }
 }
@@ -0,0 +1,121 @@
+use chrono::Utc;
+use mongodb::{ bson::{ doc, to_document, Document }, Collection, Cursor, IndexModel };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+/// One operation applied to a page, with what is needed to revert it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpLogEntry {
+    project_id: i64,
+    page_id: i64,
+    user_id: i64,
+    /// Name of the operation, the `type` of its WebSocket message.
+    kind: String,
+    /// The operation as it was broadcast, without `type` and `page_id`.
+    op: Value,
+    /// Data needed to undo the operation, if it can be undone.
+    undo: Option<Value>,
+    /// Milliseconds since the Unix epoch.
+    created_at: i64,
+}
+
+impl OpLogEntry {
+    pub fn new<O: Serialize, U: Serialize>(
+        project_id: i64,
+        page_id: i64,
+        user_id: i64,
+        kind: &str,
+        op: &O,
+        undo: Option<&U>,
+    ) -> Self {
+        return Self {
+            project_id,
+            page_id,
+            user_id,
+            kind: kind.to_string(),
+            op: serde_json::to_value(op).unwrap(),
+            undo: undo.map(|u| serde_json::to_value(u).unwrap()),
+            created_at: Utc::now().timestamp_millis(),
+        };
+    }
+
+    pub fn get_user_id(&self) -> i64 {
+        return self.user_id;
+    }
+
+    pub fn get_kind(&self) -> &str {
+        return &self.kind;
+    }
+
+    pub fn get_op(&self) -> &Value {
+        return &self.op;
+    }
+
+    pub fn get_created_at(&self) -> i64 {
+        return self.created_at;
+    }
+}
+
+/// Append-only log of the operations applied to boards, stored in the
+/// `whiteboard_ops` collection next to the boards.
+pub struct OpLog {
+    collection: Collection<Document>,
+}
+
+impl OpLog {
+    pub fn new(collection: Collection<Document>) -> Self {
+        return Self { collection };
+    }
+
+    /// Creates the index the history of a page is read with, in the order
+    /// of its versions. Existing indexes are left as they are.
+    pub async fn create_indexes(&self) -> Result<(), mongodb::error::Error> {
+        let index = IndexModel::builder()
+            .keys(doc! { "project_id": 1, "page_id": 1, "created_at": 1, "_id": 1 })
+            .build();
+        self.collection.create_index(index).await?;
+        return Ok(());
+    }
+
+    pub async fn record(&self, entry: &OpLogEntry) -> Result<(), String> {
+        let document = to_document(entry).map_err(|e| e.to_string())?;
+        self.collection.insert_one(document).await.map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    fn get_filter(project_id: i64, page_id: i64) -> Document {
+        return doc! { "project_id": project_id, "page_id": page_id };
+    }
+
+    pub async fn count(&self, project_id: i64, page_id: i64) -> Result<u64, String> {
+        return self.collection.count_documents(Self::get_filter(project_id, page_id)).await.map_err(|e| e.to_string());
+    }
+
+    /// The operations of a page in the order they were applied.
+    pub async fn history(&self, project_id: i64, page_id: i64) -> Result<Cursor<OpLogEntry>, String> {
+        return self.collection.clone_with_type::<OpLogEntry>()
+            .find(Self::get_filter(project_id, page_id))
+            .sort(doc! { "created_at": 1, "_id": 1 })
+            .await
+            .map_err(|e| e.to_string());
+    }
+
+    /// The operations of a page recorded up to `until`, or all of them
+    /// without `until`, in the order they were applied.
+    pub async fn history_until(&self, project_id: i64, page_id: i64, until: Option<i64>) -> Result<Cursor<OpLogEntry>, String> {
+        let mut filter = Self::get_filter(project_id, page_id);
+        if let Some(until) = until {
+            filter.insert("created_at", doc! { "$lte": until });
+        }
+        return self.collection.clone_with_type::<OpLogEntry>()
+            .find(filter)
+            .sort(doc! { "created_at": 1, "_id": 1 })
+            .await
+            .map_err(|e| e.to_string());
+    }
+
+    pub async fn delete_page(&self, project_id: i64, page_id: i64) -> Result<(), String> {
+        self.collection.delete_many(Self::get_filter(project_id, page_id)).await.map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+}
@@ -1,82 +1,167 @@
 use super::WhiteBoardStorage;
 use crate::whiteboard::WhiteBoardData;
+use crate::whiteboard::simplify::PIPELINE;
+use super::stats::IngestStats;
+use super::migration::{ self, MigrationContext, CURRENT_SCHEMA_VERSION };
 use mongodb::{ bson::Document, Collection };
 use redis::{ Client, AsyncCommands };
+use sqlx::PgPool;
 use std::sync::Arc;
 use super::mongo::MongoDBStorage;
 use serde::{ Serialize, Deserialize };
 use std::time::{ SystemTime, UNIX_EPOCH };
 
+// Times an update starts over when others keep changing the page under it
+const UPDATE_ATTEMPTS: usize = 10;
+
 pub struct RedisStorage {
     project_id: i64,
+    page_id: i64,
     redis_cli: Arc<Client>,
     mongo_collection: Collection<Document>,
 
     data: Option<WhiteBoardData>,
+    pg_pool: Option<Arc<PgPool>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct RedisSavingData {
+    schema_version: u32,
     project_id: i64,
+    page_id: i64,
     data: WhiteBoardData,
 }
 
 impl RedisSavingData {
-    fn new(project_id: i64, data: WhiteBoardData) -> Self {
+    fn new(project_id: i64, page_id: i64, data: WhiteBoardData) -> Self {
         return Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
             project_id,
+            page_id,
             data,
         };
     }
 }
 
 impl RedisStorage {
-    pub fn new(project_id: i64, redis_cli: Arc<Client>, mongo_collection: Collection<Document>) -> Self {
+    pub fn new(project_id: i64, page_id: i64, redis_cli: Arc<Client>, mongo_collection: Collection<Document>) -> Self {
         return Self {
             project_id,
+            page_id,
             redis_cli,
             mongo_collection,
             data: None,
+            pg_pool: None,
         };
     }
 
+    /// See `MongoDBStorage::with_pg_pool`.
+    pub fn with_pg_pool(mut self, pg_pool: Arc<PgPool>) -> Self {
+        self.pg_pool = Some(pg_pool);
+        return self;
+    }
+
     fn get_mongo_storage(&self) -> MongoDBStorage {
-        return MongoDBStorage::new(self.get_project_id(), self.mongo_collection.clone(), None);
+        let storage = MongoDBStorage::new(self.get_project_id(), self.get_page_id(), self.mongo_collection.clone(), None);
+        return match &self.pg_pool {
+            Some(pg_pool) => storage.with_pg_pool(pg_pool.clone()),
+            None => storage,
+        };
     }
 
-    async fn load_whiteboard_data(&mut self) -> WhiteBoardData {
+    /// Applies `change` to the board of the page as a single update. The cache entry is watched while the change is made; when
+    /// someone else writes it first, the change starts over on their result.
+    /// Returns whether `change` reported a change.
+    pub async fn update<F>(&mut self, mut change: F) -> Result<bool, String>
+    where
+        F: FnMut(&mut WhiteBoardData) -> bool,
+    {
+        // A connection of its own, WATCH applies to everything sent on it
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await
+            .map_err(|e| e.to_string())?;
+        let key = self.get_cache_key();
+
+        for _ in 0..UPDATE_ATTEMPTS {
+            redis::cmd("WATCH").arg(&key).exec_async(&mut con).await.map_err(|e| e.to_string())?;
+            let cached_value: Option<String> = con.get(&key).await.map_err(|e| e.to_string())?;
+            let mut data = match cached_value {
+                Some(value) => self.decode_cached_data(&value)?.data,
+                None => self.get_mongo_storage().get_whiteboard().await?.clone(),
+            };
+            data.unpack_points();
+
+            if !change(&mut data) {
+                redis::cmd("UNWATCH").exec_async(&mut con).await.map_err(|e| e.to_string())?;
+                return Ok(false);
+            }
+
+            let saving_data = RedisSavingData::new(self.project_id, self.page_id, data.clone());
+            let written: Option<()> = redis::pipe()
+                .atomic()
+                .set_ex(&key, Self::serialize_saving_data(saving_data), 3600).ignore()
+                .hset("updated_whiteboards", &key, Self::get_current_time_ns()).ignore()
+                .query_async(&mut con).await
+                .map_err(|e| e.to_string())?;
+            if written.is_some() {
+                self.data = Some(data);
+                return Ok(true);
+            }
+        }
+        return Err(format!("{} kept changing, the update was given up", key));
+    }
+
+    async fn load_whiteboard_data(&mut self) -> Result<WhiteBoardData, String> {
         println!("Loading whiteboard data");
-        let con = self.redis_cli.get_multiplexed_async_connection();
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await
+            .map_err(|e| e.to_string())?;
 
         let key = self.get_cache_key();
 
-        let cached_value: Option<String> = con.await.unwrap().get(key).await.unwrap();
+        let cached_value: Option<String> = con.get(key).await.map_err(|e| e.to_string())?;
 
-        if cached_value.is_none() {
-            println!("cache miss");
-            let mut mongo_storage = self.get_mongo_storage();
-            let whiteboard =  mongo_storage.get_whiteboard().await;
-            
-            let redis_data = RedisSavingData::new(self.project_id, whiteboard.clone());
-            self.save_data_in_cache(redis_data).await;
-            return whiteboard.clone();
-        } else {
+        if let Some(value) = cached_value {
             println!("cache hit");
-            let saved_data: RedisSavingData = serde_json
-                ::from_str(cached_value.unwrap().as_str())
-                .unwrap();
-            return saved_data.data;
+            let mut saved_data = self.decode_cached_data(&value)?;
+            saved_data.data.unpack_points();
+            return Ok(saved_data.data);
         }
+
+        println!("cache miss");
+        let mut mongo_storage = self.get_mongo_storage();
+        let whiteboard = mongo_storage.get_whiteboard().await?.clone();
+
+        let redis_data = RedisSavingData::new(self.project_id, self.page_id, whiteboard.clone());
+        self.save_data_in_cache(redis_data).await.map_err(|e| e.to_string())?;
+        return Ok(whiteboard);
+    }
+
+    // Entries written by an older server are migrated like Mongo documents.
+    // One that can't be read is an error: it may hold changes not saved to
+    // Mongo yet, loading the Mongo copy over it would lose them.
+    fn decode_cached_data(&self, value: &str) -> Result<RedisSavingData, String> {
+        let context = MigrationContext::new(self.get_project_id(), self.get_page_id());
+        return serde_json::from_str(value)
+            .map_err(|e| e.to_string())
+            .and_then(|value| migration::migrate(value, &context).map_err(|e| e.to_string()))
+            .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+            .map_err(|e| format!("cached whiteboard data can't be read: {}", e));
+    }
+
+    fn serialize_saving_data(mut data: RedisSavingData) -> String {
+        if PIPELINE.delta_storage {
+            data.data.pack_points(PIPELINE.storage_grid());
+        }
+        return serde_json::to_string(&data).unwrap();
     }
 
     fn get_cache_key(&self) -> String {
-        return format!("whiteboard:{}", self.get_project_id());
+        return format!("whiteboard:{}:{}", self.get_project_id(), self.get_page_id());
     }
 
-    async fn save_data_in_cache(&self, data: RedisSavingData) {
+    async fn save_data_in_cache(&self, data: RedisSavingData) -> redis::RedisResult<()> {
         println!("Saving whiteboard data");
 
-        let con = self.redis_cli.get_multiplexed_async_connection();
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
 
         let script = redis::Script::new(
             r#"
@@ -92,21 +177,21 @@ impl RedisStorage {
         );
 
         let key = self.get_cache_key();
-        let string_data = serde_json::to_string(&data).unwrap();
+        let string_data = Self::serialize_saving_data(data);
         let result: String = script
             .key(key)
             .arg(string_data)
             .arg(Self::get_current_time_ns())
-            .invoke_async(&mut con.await.unwrap()).await
-            .unwrap();
+            .invoke_async(&mut con).await?;
         println!("{}", result);
+        return Ok(());
     }
 
 
-    async fn update_data_in_cache(&self, data: RedisSavingData) {
+    async fn update_data_in_cache(&self, data: RedisSavingData) -> redis::RedisResult<()> {
         println!("Updating whiteboard data");
 
-        let con = self.redis_cli.get_multiplexed_async_connection();
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
 
         let script = redis::Script::new(
             r#"
@@ -124,24 +209,24 @@ impl RedisStorage {
         );
 
         let key = self.get_cache_key();
-        let string_data = serde_json::to_string(&data).unwrap();
+        let string_data = Self::serialize_saving_data(data);
         let result: String = script
             .key(key)
             .arg(string_data)
             .arg(Self::get_current_time_ns())
-            .invoke_async(&mut con.await.unwrap()).await
-            .unwrap();
+            .invoke_async(&mut con).await?;
         println!("{}", result);
+        return Ok(());
     }
 
 
 
-    async fn update_expire_time_in_cache(&self) {
+    async fn update_expire_time_in_cache(&self) -> redis::RedisResult<()> {
         println!("Updating expire time");
 
-        let con = self.redis_cli.get_multiplexed_async_connection();
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
         let key = self.get_cache_key();
-        let _: () = con.await.unwrap().expire(key, 3600).await.unwrap();
+        return con.expire(key, 3600).await;
     }
 
 
@@ -157,22 +242,50 @@ impl WhiteBoardStorage for RedisStorage {
         self.project_id
     }
 
-    async fn get_saving_data(&mut self) -> String {
-        return "".to_string();
+    fn get_page_id(&self) -> i64 {
+        self.page_id
+    }
+
+    async fn get_saving_data(&mut self) -> Result<String, String> {
+        return Ok("".to_string());
+    }
+
+    async fn get_whiteboard(&mut self) -> Result<&WhiteBoardData, String> {
+        self.data = Some(self.load_whiteboard_data().await?);
+        if let Err(e) = self.update_expire_time_in_cache().await {
+            println!("Failed to update expire time: {}", e);
+        }
+        return Ok(self.data.as_ref().unwrap());
     }
 
-    async fn get_whiteboard(&mut self) -> &WhiteBoardData {
-        self.data = Some(self.load_whiteboard_data().await);
-        self.update_expire_time_in_cache().await;
-        return self.data.as_ref().unwrap();
+    async fn save(&mut self) -> Result<(), String> {
+        return Ok(());
     }
 
-    async fn save(&mut self) {
-        return;
+    async fn set_whiteboard(&mut self, value: WhiteBoardData) -> Result<(), String> {
+        let saving_data = RedisSavingData::new(self.project_id, self.page_id, value);
+        return self.update_data_in_cache(saving_data).await.map_err(|e| e.to_string());
     }
 
-    async fn set_whiteboard(&mut self, value: WhiteBoardData) {
-        let saving_data = RedisSavingData::new(self.project_id, value);
-        self.update_data_in_cache(saving_data).await;
+    async fn delete(&mut self) {
+        println!("Deleting whiteboard data");
+
+        // Cleanup is best effort, the Mongo document goes either way
+        let key = self.get_cache_key();
+        let deleted = match self.redis_cli.get_multiplexed_async_connection().await {
+            Ok(mut con) => redis::pipe()
+                .del(&key)
+                .hdel("updated_whiteboards", &key)
+                .del(IngestStats::get_key(self.project_id, self.page_id))
+                .query_async::<()>(&mut con)
+                .await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = deleted {
+            println!("Failed to delete cached whiteboard {}: {}", key, e);
+        }
+
+        self.get_mongo_storage().delete().await;
+        self.data = None;
     }
 }
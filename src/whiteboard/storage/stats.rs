@@ -0,0 +1,69 @@
+use crate::whiteboard::WhiteBoardData;
+use crate::whiteboard::simplify::SimplifyConfig;
+use redis::{ Client, AsyncCommands };
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Byte counters of the ingest pipeline for one page, kept in Redis.
+#[derive(Serialize, Debug, Default)]
+pub struct IngestStats {
+    updates: i64,
+    bytes_received: i64,
+    bytes_simplified: i64,
+    bytes_stored: i64,
+}
+
+impl IngestStats {
+    /// Measures one update. `bytes_received` is the size of the incoming
+    /// message, `data` the board after the pipeline ran.
+    pub fn for_update(bytes_received: usize, data: &WhiteBoardData, config: &SimplifyConfig) -> Self {
+        let bytes_simplified = serde_json::to_vec(data).map(|v| v.len()).unwrap_or(0);
+        let bytes_stored = if config.delta_storage {
+            let mut packed = data.clone();
+            packed.pack_points(config.storage_grid());
+            serde_json::to_vec(&packed).map(|v| v.len()).unwrap_or(0)
+        } else {
+            bytes_simplified
+        };
+
+        return Self {
+            updates: 1,
+            bytes_received: bytes_received as i64,
+            bytes_simplified: bytes_simplified as i64,
+            bytes_stored: bytes_stored as i64,
+        };
+    }
+
+    pub fn get_key(project_id: i64, page_id: i64) -> String {
+        return format!("whiteboard_stats:{}:{}", project_id, page_id);
+    }
+
+    pub async fn record(&self, redis_cli: &Client, project_id: i64, page_id: i64) -> redis::RedisResult<()> {
+        let mut con = redis_cli.get_multiplexed_async_connection().await?;
+        let key = Self::get_key(project_id, page_id);
+        redis::pipe()
+            .hincr(&key, "updates", self.updates).ignore()
+            .hincr(&key, "bytes_received", self.bytes_received).ignore()
+            .hincr(&key, "bytes_simplified", self.bytes_simplified).ignore()
+            .hincr(&key, "bytes_stored", self.bytes_stored).ignore()
+            .query_async::<()>(&mut con)
+            .await
+    }
+
+    pub async fn load(redis_cli: &Client, project_id: i64, page_id: i64) -> redis::RedisResult<Self> {
+        let mut con = redis_cli.get_multiplexed_async_connection().await?;
+        let values: HashMap<String, i64> = con.hgetall(Self::get_key(project_id, page_id)).await?;
+        let get = |field: &str| values.get(field).copied().unwrap_or(0);
+
+        return Ok(Self {
+            updates: get("updates"),
+            bytes_received: get("bytes_received"),
+            bytes_simplified: get("bytes_simplified"),
+            bytes_stored: get("bytes_stored"),
+        });
+    }
+
+    pub fn get_bytes_saved(&self) -> i64 {
+        return self.bytes_received - self.bytes_stored;
+    }
+}
@@ -0,0 +1,71 @@
+use redis::{ Client, AsyncCommands };
+use std::sync::{ Arc, LazyLock };
+
+
+/// `WHITEBOARD_THUMBNAIL_DELAY`, seconds to wait after a change before the
+/// project thumbnail is rendered again. Changes made meanwhile are picked up
+/// by the same render.
+pub static THUMBNAIL_DELAY: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("WHITEBOARD_THUMBNAIL_DELAY").ok().and_then(|v| v.parse().ok()).unwrap_or(5)
+});
+
+/// The PNG thumbnail of a project, cached in Redis, and the flag that keeps
+/// at most one regeneration pending per project across every node.
+pub struct ThumbnailStore {
+    project_id: i64,
+    redis_cli: Arc<Client>,
+}
+
+impl ThumbnailStore {
+    pub fn new(project_id: i64, redis_cli: Arc<Client>) -> Self {
+        return Self { project_id, redis_cli };
+    }
+
+    fn get_key(&self) -> String {
+        return format!("whiteboard_thumbnail:{}", self.project_id);
+    }
+
+    fn get_pending_key(&self) -> String {
+        return format!("whiteboard_thumbnail_pending:{}", self.project_id);
+    }
+
+    pub async fn get(&self) -> redis::RedisResult<Option<Vec<u8>>> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        return con.get(self.get_key()).await;
+    }
+
+    pub async fn set(&self, png: &[u8]) -> redis::RedisResult<()> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        return con.set(self.get_key(), png).await;
+    }
+
+    /// Removes the thumbnail and any pending regeneration, for a deleted
+    /// project.
+    pub async fn delete(&self) -> redis::RedisResult<()> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        return con.del(&[self.get_key(), self.get_pending_key()]).await;
+    }
+
+    /// Marks a regeneration as pending. Returns `false` when one already is,
+    /// in which case the caller has nothing to do. The flag expires on its
+    /// own in case the node that claimed it goes away.
+    pub async fn claim_regeneration(&self) -> redis::RedisResult<bool> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(self.get_pending_key())
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(*THUMBNAIL_DELAY * 2 + 30)
+            .query_async(&mut con)
+            .await?;
+        return Ok(claimed.is_some());
+    }
+
+    /// Clears the pending flag right before rendering, so that changes made
+    /// during the render schedule another one.
+    pub async fn start_regeneration(&self) -> redis::RedisResult<()> {
+        let mut con = self.redis_cli.get_multiplexed_async_connection().await?;
+        return con.del(self.get_pending_key()).await;
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Deserialize;
+use serde_json::json;
+use super::{ Group, WhiteBoardData };
+use super::diff::BoardDelta;
+use super::eraser::EraseOps;
+use super::storage::oplog::OpLogEntry;
+use super::transform::Transform;
+
+/// An operation of the op log that changes a board, read back from its
+/// `kind` and `op`. Settings changes logged while settings were kept per
+/// page are skipped, they are not part of the board. Drawing updates are
+/// logged as deltas, entries with the whole board are from before.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "kind", content = "op", rename_all = "snake_case")]
+pub enum BoardOp {
+    DrawingUpdate { data: WhiteBoardData },
+    DrawingDelta { delta: BoardDelta },
+    Transform { ids: Vec<String>, matrix: Transform },
+    Erase(EraseOps),
+    Group { group: Group },
+    Ungroup { group_id: String },
+}
+
+impl BoardOp {
+    /// `None` for entries that do not change the board or could not be read.
+    pub fn from_entry(entry: &OpLogEntry) -> Option<Self> {
+        let value = json!({ "kind": entry.get_kind(), "op": entry.get_op() });
+        return serde_json::from_value(value).ok();
+    }
+}
+
+
+/// A page rebuilt from its op log, starting from an empty board.
+pub struct Replay {
+    board: WhiteBoardData,
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        return Self { board: WhiteBoardData::new_empty() };
+    }
+
+    pub fn get_board(&self) -> &WhiteBoardData {
+        return &self.board;
+    }
+
+    /// Applies an operation the same way storage applied it when it was
+    /// received.
+    pub fn apply(&mut self, op: BoardOp) {
+        match op {
+            BoardOp::DrawingUpdate { mut data } => {
+                data.unpack_points();
+                self.board = data;
+            }
+            BoardOp::DrawingDelta { delta } => {
+                delta.apply_to(&mut self.board);
+            }
+            BoardOp::Transform { ids, matrix } => {
+                self.board.apply_transform(&ids, &matrix);
+            }
+            BoardOp::Erase(ops) => {
+                self.board.apply_erase(&ops);
+            }
+            BoardOp::Group { group } => self.board.add_group(group),
+            BoardOp::Ungroup { group_id } => {
+                self.board.remove_group(&group_id);
+            }
+        }
+    }
+}
+
+/// The board after every entry recorded up to `at`, or after all of them
+/// without `at`. Both ends of a comparison are rebuilt this way, so content
+/// that was never logged is missing from both instead of showing up as a
+/// change.
+pub fn replay_until<'a>(entries: impl IntoIterator<Item = &'a OpLogEntry>, at: Option<i64>) -> WhiteBoardData {
+    let mut replay = Replay::new();
+    for entry in entries {
+        if at.is_some_and(|at| entry.get_created_at() > at) {
+            break;
+        }
+        if let Some(op) = BoardOp::from_entry(entry) {
+            replay.apply(op);
+        }
+    }
+    return replay.board;
+}
+
+/// Whether the state after operation `index` (of `total`) is one of
+/// `frames` evenly spaced frames. The last operation always is, and every
+/// operation is when there are fewer operations than frames.
+pub fn is_frame(index: usize, total: usize, frames: usize) -> bool {
+    if total == 0 || frames == 0 {
+        return false;
+    }
+    return (index + 1) * frames / total > index * frames / total;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::whiteboard::diff::diff_boards;
+
+    fn entry(kind: &str, op: serde_json::Value) -> OpLogEntry {
+        return OpLogEntry::new(1, 1, 1, kind, &op, None::<&()>);
+    }
+
+    #[test]
+    fn test_replay_applies_logged_operations() {
+        let mut replay = Replay::new();
+        let entries = [
+            entry("drawing_update", json!({ "data": { "lines": [
+                { "id": "a", "p": [[0, 0], [10, 0]], "c": "#000000", "w": 2 },
+                { "id": "b", "p": [[0, 5], [10, 5]], "c": "#000000", "w": 2 }
+            ] } })),
+            entry("transform", json!({ "ids": ["a"], "matrix": [1, 0, 0, 1, 5, 5] })),
+            entry("erase", json!({ "erased": [{ "id": "b", "fragments": [] }] })),
+            entry("group", json!({ "group": { "id": "g", "children": ["a"] } })),
+            entry("drawing_delta", json!({ "delta": { "lines": [
+                "a", { "id": "c", "p": [[0, 9], [10, 9]], "c": "#000000", "w": 2 }
+            ], "groups": ["g"] } })),
+            entry("cursor_update", json!({ "data": {} })),
+        ];
+        let ops: Vec<BoardOp> = entries.iter().filter_map(BoardOp::from_entry).collect();
+        assert_eq!(ops.len(), 5);
+        for op in ops {
+            replay.apply(op);
+        }
+
+        let board = replay.get_board();
+        assert_eq!(board.lines.len(), 2);
+        assert_eq!(board.lines[0].points, vec![(5.0, 5.0), (15.0, 5.0)]);
+        assert_eq!(board.lines[1].id, "c");
+        assert_eq!(board.groups[0].children, vec!["a"]);
+    }
+
+    #[test]
+    fn test_frames_are_evenly_spaced() {
+        let frames: Vec<usize> = (0..10).filter(|i| is_frame(*i, 10, 4)).collect();
+        assert_eq!(frames, vec![2, 4, 7, 9]);
+        assert_eq!((0..3).filter(|i| is_frame(*i, 3, 10)).count(), 3);
+    }
+
+    #[test]
+    fn test_versions_ignore_content_from_before_the_log() {
+        let logged = |created_at: i64, kind: &str, op: serde_json::Value| -> OpLogEntry {
+            return serde_json::from_value(json!({
+                "project_id": 1, "page_id": 1, "user_id": 1, "kind": kind, "op": op, "undo": null, "created_at": created_at,
+            })).unwrap();
+        };
+        // "old" was drawn before operations were logged, only its move was
+        let stored: WhiteBoardData = serde_json::from_value(json!({ "lines": [
+            { "id": "old", "p": [[5, 5], [15, 5]], "c": "#000000", "w": 2 },
+            { "id": "new", "p": [[0, 9], [10, 9]], "c": "#000000", "w": 2 }
+        ] })).unwrap();
+        let entries = [
+            logged(10, "transform", json!({ "ids": ["old"], "matrix": [1, 0, 0, 1, 5, 5] })),
+            logged(20, "drawing_update", json!({ "data": { "lines": [
+                { "id": "new", "p": [[0, 9], [10, 9]], "c": "#000000", "w": 2 }
+            ] } })),
+        ];
+
+        let before = replay_until(&entries, Some(15));
+        assert_eq!(diff_boards(&before, &stored).added.len(), 2);
+
+        let diff = diff_boards(&before, &replay_until(&entries, None));
+        let added: Vec<&str> = diff.added.iter().map(|change| change.id.as_str()).collect();
+        assert_eq!(added, vec!["new"]);
+        assert!(diff.removed.is_empty() && diff.modified.is_empty());
+        assert_eq!(replay_until(&entries, Some(5)).lines.len(), 0);
+    }
+}
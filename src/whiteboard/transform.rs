@@ -0,0 +1,101 @@
+use serde::{ Deserialize, Serialize };
+use super::Point;
+
+/// 2D affine matrix `[a, b, c, d, e, f]`, using the same layout as SVG's
+/// `matrix()`: `x' = a*x + c*y + e` and `y' = b*x + d*y + f`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Transform(pub [f32; 6]);
+
+impl Transform {
+    pub fn translate(dx: f32, dy: f32) -> Self {
+        return Self([1.0, 0.0, 0.0, 1.0, dx, dy]);
+    }
+
+    pub fn scale(sx: f32, sy: f32, origin: Point) -> Self {
+        let (ox, oy) = origin;
+        return Self([sx, 0.0, 0.0, sy, ox - sx * ox, oy - sy * oy]);
+    }
+
+    /// Rotation by `angle` radians around `origin`.
+    pub fn rotate(angle: f32, origin: Point) -> Self {
+        let (ox, oy) = origin;
+        let (sin, cos) = angle.sin_cos();
+        return Self([cos, sin, -sin, cos, ox - cos * ox + sin * oy, oy - sin * ox - cos * oy]);
+    }
+
+    pub fn apply(&self, point: Point) -> Point {
+        let [a, b, c, d, e, f] = self.0;
+        let (x, y) = point;
+        return (a * x + c * y + e, b * x + d * y + f);
+    }
+
+    /// Factor applied to stroke widths. Exact for uniform scales, an
+    /// area-preserving approximation otherwise.
+    pub fn width_scale(&self) -> f32 {
+        let [a, b, c, d, _, _] = self.0;
+        return (a * d - b * c).abs().sqrt();
+    }
+
+    pub fn is_finite(&self) -> bool {
+        return self.0.iter().all(|v| v.is_finite());
+    }
+}
+
+
+/// Transform operations clients can ask the server to apply to elements.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op")]
+pub enum TransformOp {
+    #[serde(rename = "move")] Move {
+        dx: f32,
+        dy: f32,
+    },
+    #[serde(rename = "scale")] Scale {
+        sx: f32,
+        sy: f32,
+        origin: Point,
+    },
+    #[serde(rename = "rotate")] Rotate {
+        angle: f32,
+        origin: Point,
+    },
+    #[serde(rename = "matrix")] Matrix {
+        m: Transform,
+    },
+}
+
+impl TransformOp {
+    pub fn to_matrix(&self) -> Transform {
+        match self {
+            Self::Move { dx, dy } => Transform::translate(*dx, *dy),
+            Self::Scale { sx, sy, origin } => Transform::scale(*sx, *sy, *origin),
+            Self::Rotate { angle, origin } => Transform::rotate(*angle, *origin),
+            Self::Matrix { m } => *m,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Point, b: Point) {
+        assert!((a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_rotate_around_origin_point() {
+        let t = Transform::rotate(std::f32::consts::FRAC_PI_2, (1.0, 1.0));
+        assert_close(t.apply((2.0, 1.0)), (1.0, 2.0));
+        assert_close(t.apply((1.0, 1.0)), (1.0, 1.0));
+    }
+
+    #[test]
+    fn test_scale_keeps_origin_fixed() {
+        let t = Transform::scale(2.0, 3.0, (10.0, 10.0));
+        assert_close(t.apply((10.0, 10.0)), (10.0, 10.0));
+        assert_close(t.apply((11.0, 11.0)), (12.0, 13.0));
+        assert!((Transform::scale(2.0, 2.0, (0.0, 0.0)).width_scale() - 2.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,490 @@
+use std::fmt::Display;
+use std::sync::LazyLock;
+use super::{ Group, Line, Point, WhiteBoardData };
+use super::eraser::EraseOps;
+use super::transform::Transform;
+use super::presence::{ CursorPosition, Viewport };
+use super::settings::BoardSettings;
+
+
+pub static LIMITS: LazyLock<BoardLimits> = LazyLock::new(BoardLimits::from_env);
+
+/// Per-board limits enforced on incoming data. Each value can be overridden
+/// with the environment variable named next to it.
+#[derive(Debug, Clone)]
+pub struct BoardLimits {
+    /// `WHITEBOARD_MAX_ELEMENTS`
+    pub max_elements: usize,
+    /// `WHITEBOARD_MAX_POINTS_PER_STROKE`
+    pub max_points_per_stroke: usize,
+    /// `WHITEBOARD_MAX_STROKE_WIDTH`
+    pub max_stroke_width: u32,
+    /// `WHITEBOARD_MAX_COORDINATE`, the largest absolute coordinate accepted
+    pub max_coordinate: f32,
+    /// `WHITEBOARD_MAX_TEXT_LENGTH`, in characters
+    pub max_text_length: usize,
+    /// `WHITEBOARD_MAX_IDS_PER_EVENT`, elements a transform or group names
+    pub max_ids_per_event: usize,
+}
+
+impl Default for BoardLimits {
+    fn default() -> Self {
+        return Self {
+            max_elements: 10_000,
+            max_points_per_stroke: 20_000,
+            max_stroke_width: 200,
+            max_coordinate: 1_000_000.0,
+            max_text_length: 10_000,
+            max_ids_per_event: 10_000,
+        };
+    }
+}
+
+impl BoardLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        return Self {
+            max_elements: env_or("WHITEBOARD_MAX_ELEMENTS", defaults.max_elements),
+            max_points_per_stroke: env_or("WHITEBOARD_MAX_POINTS_PER_STROKE", defaults.max_points_per_stroke),
+            max_stroke_width: env_or("WHITEBOARD_MAX_STROKE_WIDTH", defaults.max_stroke_width),
+            max_coordinate: env_or("WHITEBOARD_MAX_COORDINATE", defaults.max_coordinate),
+            max_text_length: env_or("WHITEBOARD_MAX_TEXT_LENGTH", defaults.max_text_length),
+            max_ids_per_event: env_or("WHITEBOARD_MAX_IDS_PER_EVENT", defaults.max_ids_per_event),
+        };
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    TooManyElements { count: usize, max: usize },
+    TooManyPoints { element: String, count: usize, max: usize },
+    EmptyStroke { element: String },
+    InvalidCoordinate { element: String, index: usize },
+    CoordinateOutOfRange { element: String, index: usize, max: f32 },
+    InvalidColor { element: String, color: String },
+    InvalidNumber { element: String, field: &'static str },
+    MismatchedLength { element: String, field: &'static str, expected: usize, found: usize },
+    TextTooLong { element: String, length: usize, max: usize },
+    TooManyIds { element: String, count: usize, max: usize },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooManyElements { count, max } =>
+                write!(f, "board has {} elements, the limit is {}", count, max),
+            Self::TooManyPoints { element, count, max } =>
+                write!(f, "element '{}' has {} points, the limit is {}", element, count, max),
+            Self::EmptyStroke { element } =>
+                write!(f, "element '{}' has no points", element),
+            Self::InvalidCoordinate { element, index } =>
+                write!(f, "element '{}' has a non-finite coordinate at point {}", element, index),
+            Self::CoordinateOutOfRange { element, index, max } =>
+                write!(f, "element '{}' has a coordinate outside +/-{} at point {}", element, max, index),
+            Self::InvalidColor { element, color } =>
+                write!(f, "element '{}' has an invalid color '{}'", element, color),
+            Self::InvalidNumber { element, field } =>
+                write!(f, "element '{}' has a non-finite value in '{}'", element, field),
+            Self::MismatchedLength { element, field, expected, found } =>
+                write!(f, "element '{}' has {} values in '{}' but {} points", element, found, field, expected),
+            Self::TextTooLong { element, length, max } =>
+                write!(f, "element '{}' has {} characters, the limit is {}", element, length, max),
+            Self::TooManyIds { element, count, max } =>
+                write!(f, "'{}' names {} elements, the limit is {}", element, count, max),
+        }
+    }
+}
+
+
+/// Checks a full board and normalizes it in place: colors become lowercase
+/// `#rrggbb` (or `#rrggbbaa`), widths, opacities, pressures and font sizes are
+/// clamped to their valid ranges. Values that cannot be repaired are rejected.
+pub fn validate_board(board: &mut WhiteBoardData, limits: &BoardLimits) -> Result<(), ValidationError> {
+    let count = board.lines.len() + board.frames.len() + board.groups.len() + board.texts.len();
+    if count > limits.max_elements {
+        return Err(ValidationError::TooManyElements { count, max: limits.max_elements });
+    }
+
+    for line in board.lines.iter_mut() {
+        check_line(line, limits)?;
+    }
+
+    for frame in board.frames.iter_mut() {
+        let values = [frame.x, frame.y, frame.width, frame.height];
+        check_finite(&frame.id, "frame", &values)?;
+        check_points(&frame.id, &[(frame.x, frame.y), (frame.x + frame.width, frame.y + frame.height)], limits)?;
+        frame.width = frame.width.abs();
+        frame.height = frame.height.abs();
+    }
+
+    for text in board.texts.iter_mut() {
+        let element = text.id.clone();
+        check_points(&element, &[(text.x, text.y)], limits)?;
+        let length = text.text.chars().count();
+        if length > limits.max_text_length {
+            return Err(ValidationError::TextTooLong { element, length, max: limits.max_text_length });
+        }
+        text.color = match normalize_color(&text.color) {
+            Some(color) => color,
+            None => return Err(ValidationError::InvalidColor { element, color: text.color.clone() }),
+        };
+        check_finite(&element, "size", &[text.size])?;
+        text.size = text.size.clamp(1.0, 1000.0);
+        if let Some(rotation) = text.rotation {
+            check_finite(&element, "rotation", &[rotation])?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// Checks the fragments an eraser leaves like any other stroke before they
+/// replace the erased lines.
+pub fn validate_erase(ops: &mut EraseOps, limits: &BoardLimits) -> Result<(), ValidationError> {
+    for fragment in ops.fragments_mut() {
+        check_line(fragment, limits)?;
+    }
+    return Ok(());
+}
+
+/// Checks a stroke and normalizes it in place, see `validate_board`.
+fn check_line(line: &mut Line, limits: &BoardLimits) -> Result<(), ValidationError> {
+    let element = line.id.clone();
+    // The packed form is only written by storage, never taken from clients
+    line.packed_points = None;
+
+    if line.points.is_empty() {
+        return Err(ValidationError::EmptyStroke { element });
+    }
+    if line.points.len() > limits.max_points_per_stroke {
+        return Err(ValidationError::TooManyPoints {
+            element,
+            count: line.points.len(),
+            max: limits.max_points_per_stroke,
+        });
+    }
+    check_points(&element, &line.points, limits)?;
+
+    line.color = match normalize_color(&line.color) {
+        Some(color) => color,
+        None => return Err(ValidationError::InvalidColor { element, color: line.color.clone() }),
+    };
+    line.width = line.width.clamp(1, limits.max_stroke_width);
+
+    if let Some(pressures) = line.pressures.as_mut() {
+        check_length(&element, "pr", line.points.len(), pressures.len())?;
+        check_finite(&element, "pr", pressures)?;
+        pressures.iter_mut().for_each(|p| *p = p.clamp(0.0, 1.0));
+    }
+    if let Some(timestamps) = line.timestamps.as_ref() {
+        check_length(&element, "ts", line.points.len(), timestamps.len())?;
+    }
+    if let Some(opacity) = line.opacity.as_mut() {
+        check_finite(&element, "o", std::slice::from_ref(opacity))?;
+        *opacity = opacity.clamp(0.0, 1.0);
+    }
+    if let Some(dash) = line.dash.as_mut() {
+        check_finite(&element, "d", dash)?;
+        dash.iter_mut().for_each(|d| *d = d.max(0.0));
+        if dash.iter().all(|d| *d == 0.0) {
+            line.dash = None;
+        }
+    }
+    return Ok(());
+}
+
+pub fn validate_cursor(cursor: &mut CursorPosition, limits: &BoardLimits) -> Result<(), ValidationError> {
+    let element = format!("cursor:{}", cursor.user_id);
+    check_points(&element, &[(cursor.x, cursor.y)], limits)?;
+    cursor.color = match normalize_color(&cursor.color) {
+        Some(color) => color,
+        None => return Err(ValidationError::InvalidColor { element, color: cursor.color.clone() }),
+    };
+    return Ok(());
+}
+
+/// Checks the path and radius of an eraser. Nothing is normalized, an eraser
+/// is never stored.
+pub fn validate_eraser(path: &[Point], radius: f32, limits: &BoardLimits) -> Result<(), ValidationError> {
+    let element = "eraser".to_string();
+    if path.is_empty() {
+        return Err(ValidationError::EmptyStroke { element });
+    }
+    if path.len() > limits.max_points_per_stroke {
+        return Err(ValidationError::TooManyPoints { element, count: path.len(), max: limits.max_points_per_stroke });
+    }
+    check_points(&element, path, limits)?;
+    if !radius.is_finite() || radius <= 0.0 || radius > limits.max_stroke_width as f32 {
+        return Err(ValidationError::InvalidNumber { element, field: "radius" });
+    }
+    return Ok(());
+}
+
+pub fn validate_settings(settings: &mut BoardSettings, limits: &BoardLimits) -> Result<(), ValidationError> {
+    let element = "settings".to_string();
+    settings.background = match normalize_color(&settings.background) {
+        Some(color) => color,
+        None => return Err(ValidationError::InvalidColor { element, color: settings.background.clone() }),
+    };
+    if !settings.grid_spacing.is_finite() || settings.grid_spacing <= 0.0 || settings.grid_spacing > limits.max_coordinate {
+        return Err(ValidationError::InvalidNumber { element, field: "grid_spacing" });
+    }
+    if let Some(bounds) = settings.bounds.as_mut() {
+        check_finite(&element, "bounds", &[bounds.x, bounds.y, bounds.width, bounds.height])?;
+        check_points(&element, &[(bounds.x, bounds.y), (bounds.x + bounds.width, bounds.y + bounds.height)], limits)?;
+        bounds.width = bounds.width.abs();
+        bounds.height = bounds.height.abs();
+    }
+    return Ok(());
+}
+
+pub fn validate_viewport(viewport: &mut Viewport, limits: &BoardLimits) -> Result<(), ValidationError> {
+    let values = [viewport.x, viewport.y, viewport.width, viewport.height, viewport.zoom];
+    check_finite("viewport", "viewport", &values)?;
+    check_points("viewport", &[(viewport.x, viewport.y)], limits)?;
+    viewport.width = viewport.width.abs();
+    viewport.height = viewport.height.abs();
+    viewport.zoom = viewport.zoom.abs();
+    return Ok(());
+}
+
+/// Checks the matrix of a transform and the number of elements it moves.
+pub fn validate_transform(ids: &[String], matrix: &Transform, limits: &BoardLimits) -> Result<(), ValidationError> {
+    check_ids("transform", ids, limits)?;
+    if !matrix.is_finite() {
+        return Err(ValidationError::InvalidNumber { element: "transform".to_string(), field: "matrix" });
+    }
+    return Ok(());
+}
+
+/// Checks that a transform keeps every point it moves within
+/// `max_coordinate`. Runs on the board the transform is about to be applied
+/// to, a finite matrix can still scale points out of range.
+pub fn validate_transformed(board: &WhiteBoardData, ids: &[String], matrix: &Transform, limits: &BoardLimits) -> Result<(), ValidationError> {
+    let targets = board.expand_ids(ids);
+    for line in board.lines.iter().filter(|l| targets.contains(&l.id)) {
+        let points: Vec<Point> = line.points.iter().map(|point| matrix.apply(*point)).collect();
+        check_points(&line.id, &points, limits)?;
+    }
+    for text in board.texts.iter().filter(|t| targets.contains(&t.id)) {
+        check_points(&text.id, &[matrix.apply((text.x, text.y))], limits)?;
+    }
+    return Ok(());
+}
+
+pub fn validate_group(group: &Group, limits: &BoardLimits) -> Result<(), ValidationError> {
+    return check_ids(&group.id, &group.children, limits);
+}
+
+fn check_ids(element: &str, ids: &[String], limits: &BoardLimits) -> Result<(), ValidationError> {
+    if ids.len() > limits.max_ids_per_event {
+        return Err(ValidationError::TooManyIds { element: element.to_string(), count: ids.len(), max: limits.max_ids_per_event });
+    }
+    return Ok(());
+}
+
+fn check_points(element: &str, points: &[Point], limits: &BoardLimits) -> Result<(), ValidationError> {
+    for (index, (x, y)) in points.iter().enumerate() {
+        if !x.is_finite() || !y.is_finite() {
+            return Err(ValidationError::InvalidCoordinate { element: element.to_string(), index });
+        }
+        if x.abs() > limits.max_coordinate || y.abs() > limits.max_coordinate {
+            return Err(ValidationError::CoordinateOutOfRange {
+                element: element.to_string(),
+                index,
+                max: limits.max_coordinate,
+            });
+        }
+    }
+    return Ok(());
+}
+
+fn check_finite(element: &str, field: &'static str, values: &[f32]) -> Result<(), ValidationError> {
+    if values.iter().all(|v| v.is_finite()) {
+        return Ok(());
+    }
+    return Err(ValidationError::InvalidNumber { element: element.to_string(), field });
+}
+
+fn check_length(element: &str, field: &'static str, expected: usize, found: usize) -> Result<(), ValidationError> {
+    if expected == found {
+        return Ok(());
+    }
+    return Err(ValidationError::MismatchedLength { element: element.to_string(), field, expected, found });
+}
+
+
+const NAMED_COLORS: &[(&str, &str)] = &[
+    ("black", "#000000"),
+    ("white", "#ffffff"),
+    ("red", "#ff0000"),
+    ("green", "#008000"),
+    ("blue", "#0000ff"),
+    ("yellow", "#ffff00"),
+    ("orange", "#ffa500"),
+    ("purple", "#800080"),
+    ("pink", "#ffc0cb"),
+    ("brown", "#a52a2a"),
+    ("gray", "#808080"),
+    ("grey", "#808080"),
+    ("cyan", "#00ffff"),
+    ("magenta", "#ff00ff"),
+];
+
+/// Converts `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `rgb()`, `rgba()` and a
+/// few CSS color names to lowercase `#rrggbb`, or `#rrggbbaa` when the color
+/// is not fully opaque.
+pub fn normalize_color(color: &str) -> Option<String> {
+    let color = color.trim().to_ascii_lowercase();
+
+    if let Some((_, hex)) = NAMED_COLORS.iter().find(|(name, _)| *name == color) {
+        return Some(hex.to_string());
+    }
+
+    let rgba: [u8; 4] = if let Some(hex) = color.strip_prefix('#') {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok().map(|v| v * 17);
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        match hex.len() {
+            3 => [digit(0)?, digit(1)?, digit(2)?, 255],
+            4 => [digit(0)?, digit(1)?, digit(2)?, digit(3)?],
+            6 => [pair(0)?, pair(2)?, pair(4)?, 255],
+            8 => [pair(0)?, pair(2)?, pair(4)?, pair(6)?],
+            _ => return None,
+        }
+    } else {
+        let (args, has_alpha) = if let Some(rest) = color.strip_prefix("rgba(") {
+            (rest.strip_suffix(')')?, true)
+        } else if let Some(rest) = color.strip_prefix("rgb(") {
+            (rest.strip_suffix(')')?, false)
+        } else {
+            return None;
+        };
+        let parts: Vec<&str> = args.split(',').map(|p| p.trim()).collect();
+        if parts.len() != if has_alpha { 4 } else { 3 } {
+            return None;
+        }
+        let channel = |p: &str| p.parse::<u8>().ok();
+        let alpha = match parts.get(3) {
+            Some(a) => {
+                let a = a.parse::<f32>().ok().filter(|a| (0.0..=1.0).contains(a))?;
+                (a * 255.0).round() as u8
+            }
+            None => 255,
+        };
+        [channel(parts[0])?, channel(parts[1])?, channel(parts[2])?, alpha]
+    };
+
+    if rgba[3] == 255 {
+        return Some(format!("#{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2]));
+    }
+    return Some(format!("#{:02x}{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2], rgba[3]));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(json: &str) -> WhiteBoardData {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_normalize_color() {
+        assert_eq!(normalize_color("#ABC"), Some("#aabbcc".to_string()));
+        assert_eq!(normalize_color(" Red "), Some("#ff0000".to_string()));
+        assert_eq!(normalize_color("rgba(0, 128, 255, 0.5)"), Some("#0080ff80".to_string()));
+        assert_eq!(normalize_color("#12345g"), None);
+        assert_eq!(normalize_color("url(javascript:alert(1))"), None);
+    }
+
+    #[test]
+    fn test_validate_board_clamps_and_normalizes() {
+        let mut data = board(r#"{"lines":[{"id":"a","p":[[0,0],[1,1]],"c":"BLUE","w":4000000000,"o":3.0}],"cursorPosition":null}"#);
+        validate_board(&mut data, &BoardLimits::default()).unwrap();
+        assert_eq!(data.lines[0].color, "#0000ff");
+        assert_eq!(data.lines[0].width, 200);
+        assert_eq!(data.lines[0].opacity, Some(1.0));
+    }
+
+    #[test]
+    fn test_validate_board_rejects_invalid_values() {
+        let limits = BoardLimits { max_points_per_stroke: 2, ..BoardLimits::default() };
+
+        let mut empty = board(r##"{"lines":[{"id":"a","p":[],"c":"#000","w":1}],"cursorPosition":null}"##);
+        assert_eq!(validate_board(&mut empty, &limits), Err(ValidationError::EmptyStroke { element: "a".to_string() }));
+
+        let mut long = board(r##"{"lines":[{"id":"b","p":[[0,0],[1,1],[2,2]],"c":"#000","w":1}],"cursorPosition":null}"##);
+        assert_eq!(
+            validate_board(&mut long, &limits).unwrap_err().to_string(),
+            "element 'b' has 3 points, the limit is 2"
+        );
+
+        let mut nan = board(r##"{"lines":[{"id":"c","p":[[0,0]],"c":"#000","w":1}],"cursorPosition":null}"##);
+        nan.lines[0].points[0].1 = f32::NAN;
+        assert_eq!(validate_board(&mut nan, &limits), Err(ValidationError::InvalidCoordinate { element: "c".to_string(), index: 0 }));
+    }
+
+    #[test]
+    fn test_validate_transform_and_group() {
+        let limits = BoardLimits { max_ids_per_event: 2, ..BoardLimits::default() };
+        let ids = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(validate_transform(&ids, &Transform::translate(1.0, 2.0), &limits), Ok(()));
+
+        let nan = Transform::scale(f32::NAN, 1.0, (0.0, 0.0));
+        assert_eq!(
+            validate_transform(&ids, &nan, &limits),
+            Err(ValidationError::InvalidNumber { element: "transform".to_string(), field: "matrix" })
+        );
+
+        let too_many = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(
+            validate_group(&Group::new("g".to_string(), too_many.clone()), &limits).unwrap_err().to_string(),
+            "'g' names 3 elements, the limit is 2"
+        );
+        assert!(validate_transform(&too_many, &Transform::translate(0.0, 0.0), &limits).is_err());
+    }
+
+    #[test]
+    fn test_transform_keeps_points_in_range() {
+        let limits = BoardLimits { max_coordinate: 1000.0, ..BoardLimits::default() };
+        let board: WhiteBoardData = serde_json::from_str(r##"{
+            "lines": [{"id":"a","p":[[0,0],[10,10]],"c":"#000","w":2}],
+            "groups": [{"id":"g","children":["t"]}],
+            "texts": [{"id":"t","x":500,"y":0,"text":"hi","color":"#000","size":12}]
+        }"##).unwrap();
+        let ids = vec!["a".to_string()];
+        let huge = Transform::scale(1e30, 1e30, (0.0, 0.0));
+        assert_eq!(validate_transform(&ids, &huge, &limits), Ok(()));
+        assert_eq!(
+            validate_transformed(&board, &ids, &huge, &limits),
+            Err(ValidationError::CoordinateOutOfRange { element: "a".to_string(), index: 1, max: 1000.0 })
+        );
+        assert_eq!(validate_transformed(&board, &ids, &Transform::scale(50.0, 50.0, (0.0, 0.0)), &limits), Ok(()));
+
+        // Groups are checked through their members
+        let group = vec!["g".to_string()];
+        assert!(validate_transformed(&board, &group, &Transform::translate(600.0, 0.0), &limits).is_err());
+        assert_eq!(validate_transformed(&board, &group, &Transform::translate(-600.0, 0.0), &limits), Ok(()));
+    }
+    #[test]
+    fn test_erase_fragments_follow_stroke_limits() {
+        let live = crate::whiteboard::live::LiveBoard::new(board(
+            r##"{"lines":[{"id":"a","p":[[0,0],[10,0],[20,0]],"c":"#000","w":1}]}"##
+        ));
+        let (mut ops, _) = live.erase(&[(2.0, 0.0)], 1.0, crate::whiteboard::eraser::EraseMode::Partial);
+        assert_eq!(validate_erase(&mut ops, &BoardLimits::default()), Ok(()));
+
+        // The fragment after the cut keeps the two vertices it had
+        let limits = BoardLimits { max_points_per_stroke: 2, ..BoardLimits::default() };
+        assert!(matches!(validate_erase(&mut ops, &limits), Err(ValidationError::TooManyPoints { count: 3, .. })));
+    }
+}